@@ -1,56 +1,70 @@
 #![allow(dead_code, unused_imports, unused_assignments)]
 use std::{any::Any, fs, iter::Peekable, slice::Iter, collections::HashMap, fmt::format};
 
-use jcpuinstructions::{Instruction, JumpFlag, Register, JUMP_FLAGS};
+use jcpuinstructions::{encode, spec_for, spec_for_mnemonic, specs_for_mnemonic, AluOp, DecodedInstruction, Instruction, InstructionSpec, JumpFlag, OperandKind, Register, JUMP_FLAGS};
 
 use crate::structures::{Token, TokenType};
 
-type OpType = (&'static str, Instruction, Vec<TokenType>, Vec<TokenType>, usize);
-
-fn rule_for_op(op: &str) -> Option<(&str, u8, Vec<TokenType>, Vec<TokenType>,usize)> {
-    //@TODO make l/r values vectors of options to have more options per token
-    let rules: Vec<OpType> = Vec::from([
-        ("data",Instruction::DATA,vec![TokenType::Identifier], vec![TokenType::Value],2),
-        ("ld",Instruction::LD,vec![TokenType::Identifier],vec![TokenType::Identifier],2),
-        ("st",Instruction::ST,vec![TokenType::Identifier],vec![TokenType::Identifier],2),
-        ("add",Instruction::ADD,vec![TokenType::Identifier],vec![TokenType::Identifier],1),
-        ("sub",Instruction::SUB,vec![TokenType::Identifier],vec![TokenType::Identifier],1),
-        ("cmp", Instruction::CMP, vec![TokenType::Identifier], vec![TokenType::Identifier, TokenType::Value],2),
-        ("inc", Instruction::INC, vec![TokenType::Identifier], vec![],1),
-        ("pop", Instruction::POP, vec![TokenType::Identifier], vec![],1),
-        ("push", Instruction::PUSH, vec![TokenType::Identifier], vec![],1),
-        ("dec", Instruction::DEC, vec![TokenType::Identifier], vec![],1),
-        ("jmpr", Instruction::JMPR, vec![TokenType::LabelDst], vec![],1),
-
-        ("int", Instruction::INT, vec![TokenType::Value], vec![],2),
-        ("jmp", Instruction::JMP, vec![TokenType::LabelDst], vec![],2),
-        ("jmpif", Instruction::JMPIF, vec![TokenType::LabelDst], vec![],2),
-        ("sf", Instruction::SF, vec![TokenType::Value], vec![], 2),
-        ("cli", Instruction::CLI, vec![], vec![],1),
-        ("clf", Instruction::CLF, vec![], vec![],1),
-        ("crf", Instruction::CRF, vec![], vec![],1),
-        ("hlt", Instruction::HLT, vec![], vec![],1)
-    ]);
+// The token types each operand kind accepts
+fn token_types(kind: Option<&OperandKind>) -> Vec<TokenType> {
+    match kind {
+        Some(OperandKind::Register) => vec![TokenType::Identifier],
+        Some(OperandKind::Value) => vec![TokenType::Value],
+        Some(OperandKind::Address) => vec![TokenType::LabelDst],
+        None => vec![],
+    }
+}
+
+// The rules for each op come straight from the ISA table in jcpuinstructions, None when it is not an op
+fn rule_for_op(op: &str) -> Option<(&'static str, u8, Vec<TokenType>, Vec<TokenType>, usize)> {
     let opname = op.to_string().to_lowercase();
     //println!("op: {}", opname);
 
     // handle jmpif flags
-    for rule in rules.iter() {
-        if rule.0 == "jmpif" && opname.contains("jmpif") {
-            if let Some(flagstr) = opname.split("jmpif").last() {
-                //println!("last: {}", flagstr);
-                for (i, flag) in JUMP_FLAGS.iter().enumerate() {
-                    if flag == &flagstr.to_lowercase().as_str() {
-                        return Some((rule.0, (Instruction::JMPIF as u8) | i as u8 , rule.2.clone(), vec![], rule.4));
-                    }
-                }
-                panic!("unknown jump flag on jumpif");
-            };
-        } else if rule.0 == opname {
-            return Some((rule.0, rule.1.clone() as u8, rule.2.clone(), rule.3.clone(), rule.4 ));
+    if let Some(flagstr) = opname.strip_prefix("jmpif") {
+        let rule = spec_for_mnemonic("jmpif")?;
+        //println!("last: {}", flagstr);
+        for (i, flag) in JUMP_FLAGS.iter().enumerate() {
+            if flag == &flagstr {
+                return Some((rule.mnemonic, rule.opcode() | i as u8, token_types(rule.operands.first()), vec![], rule.size));
+            }
         }
+        return None;
     }
-    None
+
+    // an op with a register and a value form accepts the operands of both
+    let rule = spec_for_mnemonic(&opname)?;
+    let mut left_values = vec![];
+    let mut right_values = vec![];
+    for form in specs_for_mnemonic(&opname) {
+        for ttype in token_types(form.operands.first()) {
+            if !left_values.contains(&ttype) {
+                left_values.push(ttype);
+            }
+        }
+        for ttype in token_types(form.operands.get(1)) {
+            if !right_values.contains(&ttype) {
+                right_values.push(ttype);
+            }
+        }
+    }
+
+    Some((rule.mnemonic, rule.opcode(), left_values, right_values, rule.size))
+}
+
+// Pick the form of the op that takes the given operands, this decides the encoding and size
+fn spec_for_operands(opname: &str, left_token: Option<&Token>, right_token: Option<&Token>) -> &'static InstructionSpec {
+    // a $label passed as a value counts as one
+    let accepts = |kind: Option<&OperandKind>, token: Option<&Token>| match token {
+        Some(token) if token.ttype == TokenType::LabelDst && kind == Some(&OperandKind::Value) => true,
+        Some(token) => token_types(kind).contains(&token.ttype),
+        None => true,
+    };
+    let first = spec_for_mnemonic(opname).expect("unknown operation");
+
+    specs_for_mnemonic(opname)
+        .find(|form| accepts(form.operands.first(), left_token) && accepts(form.operands.get(1), right_token))
+        .unwrap_or(first)
 }
 
 
@@ -82,7 +96,23 @@ fn get_value(token: &Token) -> u8 {
     }
 }
 
-pub fn lex(tokens: Vec<Token>, output_path: String){
+// image_size is the most the machine profile can load, the boot area and every bank
+pub fn lex(tokens: Vec<Token>, output_path: String, image_size: usize){
+    let (mut bin_operations, debug_ops) = assemble(tokens);
+    if bin_operations.len() > image_size {
+        panic!("compiled binary is {} bytes, the machine holds {}", bin_operations.len(), image_size)
+    }
+
+    //println!("{:?}", bin_operations);
+    for op in &bin_operations {
+        println!("{:08b}", op)
+    }
+    write_file(&mut bin_operations, output_path);
+    write_debug_file(debug_ops)
+}
+
+// Turn the tokens into the binary image and the debug listing of every op
+pub fn assemble(tokens: Vec<Token>) -> (Vec<u8>, Vec<String>) {
     let mut peekable_tokens = tokens.iter().peekable();
     let mut operations: Vec<(&str, u8, Option<Token>, Option<Token>)> = Vec::new();
     let mut addresses: HashMap<String, usize> = HashMap::new();
@@ -91,13 +121,22 @@ pub fn lex(tokens: Vec<Token>, output_path: String){
 
     // This could probably be improved, but iterate over the list and gather
     // a list of addresses from the labels
-    for tok in &tokens {
+    for (i, tok) in tokens.iter().enumerate() {
+        // a jump or call target can share its name with an op (e.g. CALL $inc), it is not an op
+        let is_target = i > 0 && tokens[i - 1].ttype == TokenType::LabelDst;
+
         if tok.ttype == TokenType::LabelSrc {
             addresses.insert(tok.tvalue.clone(), op_address);
+        } else if is_target {
+            continue;
         } else if let Some(op) = rule_for_op(tok.tvalue.as_str()) {
             // we only increment if we are an op and not a label src or another type of token
             // we also increment the number of op size in bytes.
-            op_address += op.4;
+            let right_token = match tokens.get(i + 2) {
+                Some(comma) if comma.ttype == TokenType::Comma => tokens.get(i + 3),
+                _ => None,
+            };
+            op_address += spec_for_operands(op.0, tokens.get(i + 1), right_token).size;
         }
     }
 
@@ -122,6 +161,7 @@ pub fn lex(tokens: Vec<Token>, output_path: String){
 
             let mut left_token_option: Option<&Token> = None;
             let mut right_token_option: Option<&Token> = None;
+            let mut label_value: Option<Token> = None;
 
 
             if !left_values.is_empty() && !right_values.is_empty() {
@@ -130,6 +170,23 @@ pub fn lex(tokens: Vec<Token>, output_path: String){
                 let tcomm = peekable_tokens.next();
                 right_token_option = peekable_tokens.next();
 
+                // a $label as a value is its address, e.g. DATA R1, $handler
+                if let Some(label_token) = right_token_option.filter(|t| t.ttype == TokenType::LabelDst) {
+                    let ntoken = peekable_tokens.next().expect("label expected after $");
+                    let address = match ntoken.ttype {
+                        TokenType::Identifier => match addresses.get(&ntoken.tvalue) {
+                            Some(address) => address.to_string(),
+                            None => panic!("unknown address {} specified at line: {}, col: {}", &ntoken.tvalue, label_token.line, label_token.column),
+                        },
+                        _ => ntoken.tvalue.clone(),
+                    };
+
+                    label_value = Some(Token { ttype: TokenType::Value, tvalue: address, line: ntoken.line, column: ntoken.column });
+                }
+                if label_value.is_some() {
+                    right_token_option = label_value.as_ref();
+                }
+
                 if tcomm.unwrap().ttype != TokenType::Comma {
                     panic!(
                         "Syntax error comma required to seperate arguments. line: {}, column: {}",
@@ -152,51 +209,14 @@ pub fn lex(tokens: Vec<Token>, output_path: String){
                 }
 
                 let a = left_token_option.unwrap().clone();
-                // if a.ttype == TokenType::Value {
-                //     // check if ttype is identifier or value
-                //     // if value add sf instruction
-                //     //
-                //     let sf_1 = Token {
-                //         ttype: TokenType::Value,
-                //         tvalue: String::from("64"),
-                //         line: right_token_option.unwrap().line,
-                //         column: right_token_option.unwrap().column
-                //     };
-                //     op_address += 1;
-                //     operations.push(("sf", 0b00000010, Some(sf_1), None));
-                // }
-
                 let b = right_token_option.unwrap().clone();
-                if opname == "cmp" {
-                    if b.ttype == TokenType::Value {
-                        // check if ttype is identifier or value
-                        // if value add sf instruction
-                        let sf_2 = Token {
-                            ttype: TokenType::Value,
-                            tvalue: String::from("32"),
-                            line: right_token_option.unwrap().line,
-                            column: right_token_option.unwrap().column
-                        };
-
-                        debug_ops.push(format!("{}: {} {}", op_address, "SF".to_string(), &sf_2.tvalue));
-                        operations.push(("sf", 0b00000010, Some(sf_2), None));
-                        op_address += 2;
 
-                    }
-
-                    debug_ops.push(format!("{}: {} {}, {}", op_address, &opname.to_uppercase(), &a.tvalue, &b.tvalue));
-                    operations.push((opname, op.clone() as u8, Some(a), Some(b)));
-
-                    op_address += 1;
-                    debug_ops.push(format!("{}: {}", op_address, "CRF".to_string()));
-                    operations.push(("crf", 0b00000100, None, None))
-                } else {
-                    debug_ops.push(format!("{}: {} {}, {}", op_address, &opname.to_uppercase(), &a.tvalue, &b.tvalue));
-                    operations.push((opname, op.clone() as u8, Some(a), Some(b)))
-                }
+                debug_ops.push(format!("{}: {} {}, {}", op_address, &opname.to_uppercase(), &a.tvalue, &b.tvalue));
+                operations.push((opname, op.clone() as u8, Some(a), Some(b)))
 
             } else if !left_values.is_empty() && right_values.is_empty() {
                 let tlval = peekable_tokens.next();
+                left_token_option = tlval;
 
                 if let Some(left_token) = left_token_option {
                     if !left_values.contains(&left_token.ttype) {
@@ -277,7 +297,12 @@ pub fn lex(tokens: Vec<Token>, output_path: String){
                 //panic!("Syntax error left value cannot be nothing, idiot...")
             }
 
-            op_address += _opsize;
+            op_address += spec_for_operands(opname, left_token_option, right_token_option).size;
+        } else if let Some(flags) = token.tvalue.to_lowercase().strip_prefix("jmpif") {
+            panic!(
+                "Syntax error, unknown jump flags: {}, line: {}, column: {}",
+                flags, token.line, token.column
+            );
         } else {
             panic!(
                 "Syntax error, unknown operation. line: {}, column: {}",
@@ -290,86 +315,88 @@ pub fn lex(tokens: Vec<Token>, output_path: String){
     //run compile function
     // println!("{:#?}", addresses);
     // println!("DEBUG \n {:#?}", debug_ops);
-    compile(operations, output_path);
-    write_debug_file(debug_ops)
+    (compile(operations), debug_ops)
 }
 
-fn compile(vec: Vec<(&str, u8, Option<Token>, Option<Token>)>, output_path: String)  {
+fn compile(vec: Vec<(&str, u8, Option<Token>, Option<Token>)>) -> Vec<u8> {
     let mut bin_operations: Vec<u8> = Vec::new();
 
-    //println!("COMPILED OPS: {:#?}", vec);
-
     for op in vec.iter() {
-        //println!("op: {}", op.0);
-        match op.0 {
-            "data" => {
-                // u8|u8 packed, next byte u8
-                // will panic if not register here
-                let l_register = get_register(op.2.as_ref().unwrap());
-                let r_value = get_value(op.3.as_ref().unwrap());
-
-                bin_operations.push((op.1.clone() as u8) | (l_register as u8) << 2);
-                bin_operations.push(r_value);
-                // need to get next byte
-            },
-            "add" | "sub" | "ld" | "st" => {
-                // u8|u8|u8 packed
-                // will panic if not register here
-                let l_register = get_register(op.2.as_ref().unwrap());
-                let r_register = get_register(op.3.as_ref().unwrap());
-                // println!("{:08b}", op.1);
-                bin_operations.push( (op.1.clone() as u8) | (l_register.clone() as u8) << 2 | (r_register.clone() as u8) << 0 );
-            },
-            "cmp" => {
-                let l_token = op.2.as_ref().unwrap();
-                let r_token = op.3.as_ref().unwrap();
-                let r_val: u8;
-
-                if r_token.ttype == TokenType::Identifier {
-                    r_val = get_register(r_token) as u8;
-                } else {
-                    r_val = get_value(r_token);
-                }
-
-                // println!("{:08b}", op.1);
-                bin_operations.push( (op.1.clone() as u8) | (get_register(l_token) as u8) << 2 );
-                // cmp can compare a register to a register or a value
-                bin_operations.push(r_val);
-            },
-            "jmpr" | "dec" | "inc" | "push" | "pop" => {
-                // u8|u8 packed
-                // will panic if not register here
-                let t_operation = op.2.as_ref().unwrap();
-
-                if t_operation.ttype == TokenType::Identifier {
-                    let l_register = get_register(op.2.as_ref().unwrap());
-                    bin_operations.push( (op.1.clone() as u8) | (l_register as u8) << 2 );
-
-                } else if t_operation.ttype == TokenType::Value {
-                    let l_value = get_value(op.2.as_ref().unwrap());
-                    bin_operations.push( (op.1.clone() as u8) | (l_value as u8) );
-                }
-            },
-            // "jmp"
-            "jmpif" | "jmp" | "int" | "sf" => {
-                let l_value = get_value(op.2.as_ref().unwrap());
-
-                bin_operations.push(op.1.clone());
-                bin_operations.push(l_value as u8);
-                // jmpif 0x04
-            },
-            "clf" | "hlt" | "cli" | "crf" => {
-                bin_operations.push(op.1.clone());
-            },
-            _ => todo!()
+        let (left, right) = (op.2.as_ref(), op.3.as_ref());
+        // the form of the op its operands pick, e.g. ADD R1, 5 is ADDI
+        let spec = spec_for_operands(op.0, left, right);
+        let instruction = instruction(spec, op.1, left, right);
+        let bytes = encode(&instruction);
+
+        // LD R1, R2 and LD R1, R4 have the bytes of INT and CLI
+        let decodes_as = spec_for(bytes[0]).expect("every encoded op has a spec");
+        if decodes_as.instruction != spec.instruction {
+            let token = left.expect("only ops with operands share bytes");
+            panic!(
+                "Syntax error {} can not be encoded, its byte is {}. line: {}, column: {}",
+                instruction, decodes_as.mnemonic.to_uppercase(), token.line, token.column
+            );
         }
+
+        bin_operations.extend(bytes);
     }
 
-    //println!("{:?}", bin_operations);
-    for op in &bin_operations {
-        println!("{:08b}", op)
+    bin_operations
+}
+
+// The instruction for an ISA entry and its operand tokens, checked against the entry when the op was read
+fn instruction(spec: &InstructionSpec, op: u8, left: Option<&Token>, right: Option<&Token>) -> DecodedInstruction {
+    let register = |token: Option<&Token>| get_register(token.expect("a register operand"));
+    let value = |token: Option<&Token>| get_value(token.expect("a value operand"));
+    let alu_op = || match spec.instruction {
+        Instruction::AND | Instruction::ANDI => AluOp::And,
+        Instruction::OR | Instruction::ORI => AluOp::Or,
+        Instruction::XOR | Instruction::XORI => AluOp::Xor,
+        Instruction::SHL | Instruction::SHLI => AluOp::Shl,
+        _ => AluOp::Shr,
+    };
+
+    match spec.instruction {
+        Instruction::LD => DecodedInstruction::Ld { ra: register(left), rb: register(right) },
+        Instruction::ST => DecodedInstruction::St { ra: register(left), rb: register(right) },
+        // the ram address can be in a register or given as a value
+        Instruction::LDI => DecodedInstruction::LdImm { address: value(left), rb: register(right) },
+        Instruction::STI => DecodedInstruction::StImm { address: value(left), rb: register(right) },
+        Instruction::DATA => DecodedInstruction::Data { reg: register(left), value: value(right) },
+        Instruction::ADD => DecodedInstruction::Add { ra: register(left), rb: register(right) },
+        Instruction::SUB => DecodedInstruction::Sub { ra: register(left), rb: register(right) },
+        Instruction::CMP => DecodedInstruction::Cmp { ra: register(left), rb: register(right) },
+        // against a value the result goes back into the register
+        Instruction::ADDI => DecodedInstruction::AddImm { reg: register(left), value: value(right) },
+        Instruction::SUBI => DecodedInstruction::SubImm { reg: register(left), value: value(right) },
+        Instruction::CMPI => DecodedInstruction::CmpImm { reg: register(left), value: value(right) },
+        Instruction::AND | Instruction::OR | Instruction::XOR | Instruction::SHL | Instruction::SHR => {
+            DecodedInstruction::Alu { op: alu_op(), ra: register(left), rb: register(right) }
+        },
+        Instruction::ANDI | Instruction::ORI | Instruction::XORI | Instruction::SHLI | Instruction::SHRI => {
+            DecodedInstruction::AluImm { op: alu_op(), reg: register(left), value: value(right) }
+        },
+        Instruction::NOT => DecodedInstruction::Not { reg: register(left) },
+        Instruction::INC => DecodedInstruction::Inc { reg: register(left) },
+        Instruction::DEC => DecodedInstruction::Dec { reg: register(left) },
+        Instruction::JMPR => DecodedInstruction::Jmpr { reg: register(left) },
+        Instruction::PUSH => DecodedInstruction::Push { reg: register(left) },
+        Instruction::PUSHI => DecodedInstruction::PushImm { value: value(left) },
+        Instruction::POP => DecodedInstruction::Pop { reg: register(left) },
+        Instruction::JMP => DecodedInstruction::Jmp { address: value(left) },
+        // jmpif carries its flags in the low bits of the op
+        Instruction::JMPIF => DecodedInstruction::JmpIf { flags: op & 0b00001111, address: value(left) },
+        Instruction::CALL => DecodedInstruction::Call { address: value(left) },
+        Instruction::INT => DecodedInstruction::Int { value: value(left) },
+        Instruction::BANK => DecodedInstruction::Bank { value: value(left) },
+        Instruction::RET => DecodedInstruction::Ret,
+        Instruction::IRET => DecodedInstruction::Iret,
+        Instruction::CLF => DecodedInstruction::Clf,
+        Instruction::CLI => DecodedInstruction::Cli,
+        Instruction::EI => DecodedInstruction::Ei,
+        Instruction::DI => DecodedInstruction::Di,
+        Instruction::HLT => DecodedInstruction::Hlt,
     }
-    write_file(&mut bin_operations, output_path);
 }
 
 fn write_debug_file(debug_instructions: Vec<String>) {
@@ -0,0 +1,14 @@
+pub mod structures;
+pub mod parser;
+pub mod lexer;
+
+use crate::parser::Parser;
+
+// Assemble jasm source straight to the bytes of a boot image
+pub fn assemble(jsm: &str) -> Vec<u8> {
+    let mut parser = Parser::new(jsm);
+    parser.parse();
+
+    let (bin_operations, _) = lexer::assemble(parser.tokens);
+    bin_operations
+}
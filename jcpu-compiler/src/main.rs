@@ -1,13 +1,28 @@
-mod structures;
-mod parser;
-mod lexer;
-
 use std::{path::Path, fs};
 
-use crate::parser::*;
+use jcpu::config::MachineConfig;
+use jcpu_compiler::{parser::*, lexer};
 
+// jcpu-compiler [--config machine.toml] file.jsm
 fn main() {
-    let file_path = std::env::args().nth(1).expect("no file given");
+    let mut args = std::env::args().skip(1);
+    let mut file_path = None;
+    let mut config = MachineConfig::default();
+    while let Some(arg) = args.next() {
+        if arg == "--config" {
+            let path = args.next().expect("no machine profile given");
+            config = match MachineConfig::load(&path) {
+                Ok(config) => config,
+                Err(e) => {
+                    eprint!("Failed to compile: {}", e);
+                    return;
+                }
+            };
+        } else {
+            file_path = Some(arg);
+        }
+    }
+    let file_path = file_path.expect("no file given");
     let fp = Path::new(&file_path).canonicalize();
 
     match fp {
@@ -17,7 +32,7 @@ fn main() {
             let jsm = fs::read_to_string(fp).expect("failed to read file.");
             let mut parser = Parser::new(&jsm.to_string());
             parser.parse();
-            lexer::lex(parser.tokens, outpath);
+            lexer::lex(parser.tokens, outpath, config.image_size());
         },
         Err(e) => {
            match e.kind() {
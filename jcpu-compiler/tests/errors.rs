@@ -0,0 +1,38 @@
+use jcpu_compiler::assemble;
+
+// the parser counts lines from 0
+
+#[test]
+fn every_jump_flag_assembles() {
+    let image = assemble("start:\nJMPIFC $start\nJMPIFCAEZ $start\nJMPIFCF $start\n");
+    assert_eq!(image, [0x58, 0, 0x5f, 0, 0x50, 0]);
+}
+
+#[test]
+#[should_panic(expected = "Syntax error, unknown jump flags: x, line: 1")]
+fn an_unknown_jump_flag_is_a_syntax_error() {
+    assemble("start:\nJMPIFX $start\n");
+}
+
+#[test]
+#[should_panic(expected = "Syntax error, unknown operation. line: 0")]
+fn an_unknown_op_is_a_syntax_error() {
+    assemble("MOV R1, R2\n");
+}
+
+#[test]
+#[should_panic(expected = "Syntax error LD R1, R2 can not be encoded, its byte is INT. line: 1")]
+fn ld_r1_r2_is_refused() {
+    assemble("DATA R1, 0x60\nLD R1, R2\n");
+}
+
+#[test]
+#[should_panic(expected = "its byte is CLI")]
+fn ld_r1_r4_is_refused() {
+    assemble("LD R1, R4\n");
+}
+
+#[test]
+fn the_other_lds_assemble() {
+    assert_eq!(assemble("LD R1, R1\nLD R1, R3\nLD R2, R1\nLD 0x60, R2\n"), [0x00, 0x02, 0x04, 0x75, 0x60]);
+}
@@ -0,0 +1,237 @@
+/*
+    Turns a boot.img back into jasm. Jump and call targets get their labels back
+    so the output compiles to the same bytes.
+
+    Bytes that are not an instruction (an unknown opcode, or an image that ends part
+    way through one) are kept as comments and warned about, so those images do not
+    reassemble to the same bytes.
+*/
+
+use std::{collections::BTreeSet, fs, process};
+
+use jcpuinstructions::{decode, DecodeError, DecodedInstruction, JUMP_FLAGS};
+
+fn main() {
+    let mut args = std::env::args().skip(1);
+    let mut check = false;
+    let mut output: Option<String> = None;
+    let mut input = String::from("boot.img");
+
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--check" => check = true,
+            "-o" => output = Some(args.next().expect("no output file given")),
+            _ => input = arg,
+        }
+    }
+
+    let image = fs::read(&input).expect("failed to read boot image.");
+
+    let (jsm, warnings) = disassemble(&image);
+    for warning in warnings {
+        eprintln!("warning: {}: {}", input, warning);
+    }
+
+    match output {
+        Some(path) => fs::write(path, &jsm).expect("Unable to write file"),
+        None => print!("{}", jsm),
+    }
+
+    if check {
+        let rebuilt = jcpu_compiler::assemble(&jsm);
+
+        if rebuilt != image {
+            let at = rebuilt.iter().zip(image.iter()).position(|(a, b)| a != b).unwrap_or(rebuilt.len().min(image.len()));
+            eprintln!("roundtrip failed: reassembled image differs at address {}", at);
+            process::exit(1);
+        }
+
+        eprintln!("roundtrip ok: {} bytes", image.len());
+    }
+}
+
+// The jasm, and what could not be decoded
+pub fn disassemble(image: &[u8]) -> (String, Vec<String>) {
+    // None for a byte that is not an instruction
+    let mut lines: Vec<(usize, Option<DecodedInstruction>)> = Vec::new();
+    let mut warnings = Vec::new();
+    // where the bytes of a cut off last instruction start
+    let mut tail = None;
+    let mut address = 0;
+
+    while address < image.len() {
+        match decode(&image[address..]) {
+            Ok(instruction) => {
+                lines.push((address, Some(instruction)));
+                address += instruction.size();
+            },
+            Err(e @ DecodeError::UnknownOpcode(_)) => {
+                warnings.push(format!("{} at address {}", e, address));
+                lines.push((address, None));
+                address += 1;
+            },
+            // only the end of the image cuts an instruction short
+            Err(e) => {
+                warnings.push(format!("{} at address {}, the image ends there", e, address));
+                tail = Some(address);
+                break;
+            },
+        }
+    }
+
+    // only addresses that start an instruction (or the end of the image) can carry a label
+    let end = tail.unwrap_or(image.len());
+    let starts: BTreeSet<usize> = lines.iter().filter(|l| l.1.is_some()).map(|l| l.0).chain([end]).collect();
+    let labels: BTreeSet<usize> = lines
+        .iter()
+        .filter_map(|l| match l.1? {
+            DecodedInstruction::Jmp { address }
+            | DecodedInstruction::JmpIf { address, .. }
+            | DecodedInstruction::Call { address } => Some(address as usize),
+            _ => None,
+        })
+        .filter(|a| starts.contains(a))
+        .collect();
+
+    let mut jsm = String::from("; disassembled by jcpu-disasm\n");
+
+    for (address, instruction) in lines.iter() {
+        if labels.contains(address) {
+            jsm.push_str(&format!("{}:\n", label(*address)));
+        }
+
+        let instruction = match instruction {
+            Some(instruction) => instruction,
+            None => {
+                jsm.push_str(&format!("    ; {:#04x} is not an instruction\n", image[*address]));
+                continue;
+            },
+        };
+
+        let text = match *instruction {
+            DecodedInstruction::Jmp { address } if labels.contains(&(address as usize)) => {
+                format!("JMP ${}", label(address as usize))
+            },
+            DecodedInstruction::Call { address } if labels.contains(&(address as usize)) => {
+                format!("CALL ${}", label(address as usize))
+            },
+            DecodedInstruction::JmpIf { flags, address } if labels.contains(&(address as usize)) => {
+                format!("JMPIF{} ${}", JUMP_FLAGS[flags as usize].to_uppercase(), label(address as usize))
+            },
+            instruction => instruction.to_string(),
+        };
+
+        jsm.push_str(&format!("    {}\n", text));
+    }
+
+    if labels.contains(&end) {
+        jsm.push_str(&format!("{}:\n", label(end)));
+    }
+
+    if let Some(start) = tail {
+        let bytes: Vec<String> = image[start..].iter().map(|b| format!("{:#04x}", b)).collect();
+        jsm.push_str(&format!("    ; the image ends part way through an instruction: {}\n", bytes.join(" ")));
+    }
+
+    (jsm, warnings)
+}
+
+fn label(address: usize) -> String {
+    format!("L{}", address)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use jcpuinstructions::{encode, AluOp, Register};
+
+    fn roundtrip(image: &[u8]) {
+        let (jsm, warnings) = disassemble(image);
+        assert_eq!(jcpu_compiler::assemble(&jsm), image, "{}", jsm);
+        assert!(warnings.is_empty(), "{:?}", warnings);
+    }
+
+    #[test]
+    fn program_with_labels_reassembles_to_the_same_bytes() {
+        let jsm = "start:\nDATA R1, 2\nDATA R2, 0x10\nADD R1, R2\nSUB R2, 3\nCMP R1, 5\nJMPIFE $done\nPUSH R1\nCALL $sub\nPOP R2\nJMP $start\nsub:\nINC R3\nXOR R3, R4\nSHL R3, 2\nRET\ndone:\nHLT\n";
+        let image = jcpu_compiler::assemble(jsm);
+
+        roundtrip(&image);
+        assert!(disassemble(&image).0.contains("CALL $L"));
+    }
+
+    #[test]
+    fn one_of_every_instruction_reassembles_to_the_same_bytes() {
+        let (r2, r3) = (Register::R2, Register::R3);
+        let instructions = [
+            DecodedInstruction::Ld { ra: r3, rb: r2 },
+            DecodedInstruction::St { ra: r2, rb: r3 },
+            DecodedInstruction::LdImm { address: 200, rb: r2 },
+            DecodedInstruction::StImm { address: 201, rb: r3 },
+            DecodedInstruction::Data { reg: r2, value: 0xff },
+            DecodedInstruction::Jmpr { reg: r3 },
+            DecodedInstruction::Jmp { address: 0 },
+            // not the start of an instruction, so it stays a raw address
+            DecodedInstruction::JmpIf { flags: 0b0110, address: 1 },
+            DecodedInstruction::Call { address: 250 },
+            DecodedInstruction::Ret,
+            DecodedInstruction::Clf,
+            DecodedInstruction::Hlt,
+            DecodedInstruction::Add { ra: r2, rb: r3 },
+            DecodedInstruction::Sub { ra: r3, rb: r2 },
+            DecodedInstruction::AddImm { reg: r2, value: 9 },
+            DecodedInstruction::SubImm { reg: r3, value: 9 },
+            DecodedInstruction::Cmp { ra: r2, rb: r3 },
+            DecodedInstruction::CmpImm { reg: r3, value: 42 },
+            DecodedInstruction::Inc { reg: r2 },
+            DecodedInstruction::Dec { reg: r3 },
+            DecodedInstruction::Push { reg: r2 },
+            DecodedInstruction::PushImm { value: 7 },
+            DecodedInstruction::Pop { reg: r3 },
+            DecodedInstruction::Int { value: 1 },
+            DecodedInstruction::Cli,
+            DecodedInstruction::Iret,
+            DecodedInstruction::Ei,
+            DecodedInstruction::Di,
+            DecodedInstruction::Bank { value: 2 },
+            DecodedInstruction::Alu { op: AluOp::And, ra: r2, rb: r3 },
+            DecodedInstruction::Alu { op: AluOp::Or, ra: r2, rb: r3 },
+            DecodedInstruction::Alu { op: AluOp::Xor, ra: r2, rb: r3 },
+            DecodedInstruction::Alu { op: AluOp::Shl, ra: r2, rb: r3 },
+            DecodedInstruction::Alu { op: AluOp::Shr, ra: r2, rb: r3 },
+            DecodedInstruction::AluImm { op: AluOp::And, reg: r3, value: 0x0f },
+            DecodedInstruction::AluImm { op: AluOp::Or, reg: r3, value: 0x0f },
+            DecodedInstruction::AluImm { op: AluOp::Xor, reg: r3, value: 0x0f },
+            DecodedInstruction::AluImm { op: AluOp::Shl, reg: r3, value: 1 },
+            DecodedInstruction::AluImm { op: AluOp::Shr, reg: r3, value: 1 },
+            DecodedInstruction::Not { reg: r2 },
+        ];
+        let image: Vec<u8> = instructions.iter().flat_map(encode).collect();
+
+        roundtrip(&image);
+    }
+    #[test]
+    fn a_cut_off_last_instruction_is_left_in_a_comment() {
+        // DATA R1, 5 then a CALL without its address
+        let mut image = encode(&DecodedInstruction::Data { reg: Register::R1, value: 5 });
+        image.push(encode(&DecodedInstruction::Call { address: 0 })[0]);
+
+        let (jsm, warnings) = disassemble(&image);
+        assert!(jsm.ends_with("    ; the image ends part way through an instruction: 0x61\n"), "{}", jsm);
+        assert_eq!(warnings, ["opcode 01100001 is missing its operand byte at address 2, the image ends there"]);
+        // the rest still assembles
+        assert_eq!(jcpu_compiler::assemble(&jsm), image[..2]);
+    }
+
+    #[test]
+    fn unknown_opcodes_are_left_in_comments() {
+        let mut image = encode(&DecodedInstruction::Inc { reg: Register::R2 });
+        image.push(0x67);
+        image.extend(encode(&DecodedInstruction::Hlt));
+
+        let (jsm, warnings) = disassemble(&image);
+        assert!(jsm.contains("    ; 0x67 is not an instruction\n"), "{}", jsm);
+        assert_eq!(warnings, ["unknown opcode 01100111 at address 1"]);
+        assert_eq!(jcpu_compiler::assemble(&jsm), [image[0], image[2]]);
+    }
+}
@@ -0,0 +1,112 @@
+use crate::Instruction;
+
+// What an instruction expects for each operand when written in jasm
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum OperandKind {
+    Register,
+    Value,
+    // a $label or a $value
+    Address,
+}
+
+impl OperandKind {
+    pub fn usage(&self) -> &'static str {
+        match self {
+            OperandKind::Register => "Rx",
+            OperandKind::Value => "value",
+            OperandKind::Address => "$addr",
+        }
+    }
+}
+
+pub struct InstructionSpec {
+    pub instruction: Instruction,
+    // lowercase, as the assembler matches it
+    pub mnemonic: &'static str,
+    // the bits of the first byte that identify the instruction, the rest are operands
+    pub mask: u8,
+    pub operands: &'static [OperandKind],
+    pub size: usize,
+    pub cycles: usize,
+    pub description: &'static str,
+}
+
+impl InstructionSpec {
+    pub fn opcode(&self) -> u8 {
+        self.instruction as u8
+    }
+
+    pub fn matches(&self, byte: u8) -> bool {
+        byte & self.mask == self.opcode()
+    }
+
+    // e.g. "CMP Rx, value"
+    pub fn usage(&self) -> String {
+        let operands: Vec<&str> = self.operands.iter().map(|o| o.usage()).collect();
+
+        format!("{} {}", self.mnemonic.to_uppercase(), operands.join(", ")).trim_end().to_string()
+    }
+}
+
+const R: OperandKind = OperandKind::Register;
+const V: OperandKind = OperandKind::Value;
+const ADDR: OperandKind = OperandKind::Address;
+
+// The whole instruction set. Decoding takes the first entry that matches so
+// the non packed instructions sit above LD, which shares their opcode bits.
+pub static ISA: [InstructionSpec; 40] = [
+    InstructionSpec { instruction: Instruction::INT, mnemonic: "int", mask: 0xFF, operands: &[V], size: 2, cycles: 2, description: "raise the irq line value" },
+    InstructionSpec { instruction: Instruction::CLI, mnemonic: "cli", mask: 0xFF, operands: &[], size: 1, cycles: 1, description: "clear the interrupt" },
+    InstructionSpec { instruction: Instruction::CLF, mnemonic: "clf", mask: 0xFF, operands: &[], size: 1, cycles: 1, description: "clear the compare flags" },
+    InstructionSpec { instruction: Instruction::CALL, mnemonic: "call", mask: 0xFF, operands: &[ADDR], size: 2, cycles: 3, description: "push the return address and jump to the address" },
+    InstructionSpec { instruction: Instruction::RET, mnemonic: "ret", mask: 0xFF, operands: &[], size: 1, cycles: 2, description: "pop the return address pushed by CALL and jump to it" },
+    InstructionSpec { instruction: Instruction::IRET, mnemonic: "iret", mask: 0xFF, operands: &[], size: 1, cycles: 3, description: "return from an interrupt, restoring the flags" },
+    InstructionSpec { instruction: Instruction::EI, mnemonic: "ei", mask: 0xFF, operands: &[], size: 1, cycles: 1, description: "enable interrupts" },
+    InstructionSpec { instruction: Instruction::DI, mnemonic: "di", mask: 0xFF, operands: &[], size: 1, cycles: 1, description: "disable interrupts" },
+    InstructionSpec { instruction: Instruction::BANK, mnemonic: "bank", mask: 0xFF, operands: &[V], size: 2, cycles: 2, description: "show the ram bank value in the banked window" },
+    InstructionSpec { instruction: Instruction::HLT, mnemonic: "hlt", mask: 0xFF, operands: &[], size: 1, cycles: 1, description: "halt the cpu" },
+    InstructionSpec { instruction: Instruction::LD, mnemonic: "ld", mask: 0xF0, operands: &[R, R], size: 1, cycles: 2, description: "load RB from the ram address in RA" },
+    InstructionSpec { instruction: Instruction::LDI, mnemonic: "ld", mask: 0xFC, operands: &[V, R], size: 2, cycles: 2, description: "load RB from the ram address value" },
+    InstructionSpec { instruction: Instruction::ST, mnemonic: "st", mask: 0xF0, operands: &[R, R], size: 1, cycles: 2, description: "store RB at the ram address in RA" },
+    InstructionSpec { instruction: Instruction::STI, mnemonic: "st", mask: 0xFC, operands: &[V, R], size: 2, cycles: 2, description: "store RB at the ram address value" },
+    InstructionSpec { instruction: Instruction::DATA, mnemonic: "data", mask: 0xF0, operands: &[R, V], size: 2, cycles: 2, description: "load value into the register" },
+    InstructionSpec { instruction: Instruction::JMPR, mnemonic: "jmpr", mask: 0xF0, operands: &[R], size: 1, cycles: 1, description: "jump to the address in the register" },
+    InstructionSpec { instruction: Instruction::JMP, mnemonic: "jmp", mask: 0xF0, operands: &[ADDR], size: 2, cycles: 2, description: "jump to the address" },
+    InstructionSpec { instruction: Instruction::JMPIF, mnemonic: "jmpif", mask: 0xF0, operands: &[ADDR], size: 2, cycles: 2, description: "jump to the address if any of the C,A,E,Z flags is set" },
+    InstructionSpec { instruction: Instruction::ADD, mnemonic: "add", mask: 0xF0, operands: &[R, R], size: 1, cycles: 1, description: "RB = RA + RB" },
+    InstructionSpec { instruction: Instruction::ADDI, mnemonic: "add", mask: 0xFF, operands: &[R, V], size: 3, cycles: 2, description: "RA = RA + value" },
+    InstructionSpec { instruction: Instruction::SUB, mnemonic: "sub", mask: 0xF0, operands: &[R, R], size: 1, cycles: 1, description: "RB = RA - RB" },
+    InstructionSpec { instruction: Instruction::SUBI, mnemonic: "sub", mask: 0xFF, operands: &[R, V], size: 3, cycles: 2, description: "RA = RA - value" },
+    InstructionSpec { instruction: Instruction::CMP, mnemonic: "cmp", mask: 0xF0, operands: &[R, R], size: 1, cycles: 1, description: "compare RA to RB" },
+    InstructionSpec { instruction: Instruction::CMPI, mnemonic: "cmp", mask: 0xFC, operands: &[R, V], size: 2, cycles: 2, description: "compare RA to value" },
+    InstructionSpec { instruction: Instruction::INC, mnemonic: "inc", mask: 0xF0, operands: &[R], size: 1, cycles: 1, description: "add one to the register" },
+    InstructionSpec { instruction: Instruction::DEC, mnemonic: "dec", mask: 0xF0, operands: &[R], size: 1, cycles: 1, description: "take one from the register" },
+    InstructionSpec { instruction: Instruction::PUSH, mnemonic: "push", mask: 0xF0, operands: &[R], size: 1, cycles: 2, description: "push the register onto the stack" },
+    InstructionSpec { instruction: Instruction::PUSHI, mnemonic: "push", mask: 0xFF, operands: &[V], size: 2, cycles: 2, description: "push value onto the stack" },
+    InstructionSpec { instruction: Instruction::POP, mnemonic: "pop", mask: 0xF0, operands: &[R], size: 1, cycles: 2, description: "pop the top of the stack into the register" },
+    InstructionSpec { instruction: Instruction::AND, mnemonic: "and", mask: 0xFF, operands: &[R, R], size: 2, cycles: 1, description: "RB = RA & RB" },
+    InstructionSpec { instruction: Instruction::ANDI, mnemonic: "and", mask: 0xFF, operands: &[R, V], size: 3, cycles: 2, description: "RA = RA & value" },
+    InstructionSpec { instruction: Instruction::OR, mnemonic: "or", mask: 0xFF, operands: &[R, R], size: 2, cycles: 1, description: "RB = RA | RB" },
+    InstructionSpec { instruction: Instruction::ORI, mnemonic: "or", mask: 0xFF, operands: &[R, V], size: 3, cycles: 2, description: "RA = RA | value" },
+    InstructionSpec { instruction: Instruction::XOR, mnemonic: "xor", mask: 0xFF, operands: &[R, R], size: 2, cycles: 1, description: "RB = RA ^ RB" },
+    InstructionSpec { instruction: Instruction::XORI, mnemonic: "xor", mask: 0xFF, operands: &[R, V], size: 3, cycles: 2, description: "RA = RA ^ value" },
+    InstructionSpec { instruction: Instruction::NOT, mnemonic: "not", mask: 0xFF, operands: &[R], size: 2, cycles: 1, description: "flip every bit of the register" },
+    InstructionSpec { instruction: Instruction::SHL, mnemonic: "shl", mask: 0xFF, operands: &[R, R], size: 2, cycles: 1, description: "RB = RA << RB, 0 when shifting by 8 or more" },
+    InstructionSpec { instruction: Instruction::SHLI, mnemonic: "shl", mask: 0xFF, operands: &[R, V], size: 3, cycles: 2, description: "RA = RA << value, 0 when shifting by 8 or more" },
+    InstructionSpec { instruction: Instruction::SHR, mnemonic: "shr", mask: 0xFF, operands: &[R, R], size: 2, cycles: 1, description: "RB = RA >> RB, 0 when shifting by 8 or more" },
+    InstructionSpec { instruction: Instruction::SHRI, mnemonic: "shr", mask: 0xFF, operands: &[R, V], size: 3, cycles: 2, description: "RA = RA >> value, 0 when shifting by 8 or more" },
+];
+
+// The spec for the first byte of an instruction
+pub fn spec_for(byte: u8) -> Option<&'static InstructionSpec> {
+    ISA.iter().find(|spec| spec.matches(byte))
+}
+
+pub fn spec_for_mnemonic(mnemonic: &str) -> Option<&'static InstructionSpec> {
+    ISA.iter().find(|spec| spec.mnemonic.eq_ignore_ascii_case(mnemonic))
+}
+
+// Some mnemonics have a register and a value form, each with its own encoding
+pub fn specs_for_mnemonic(mnemonic: &str) -> impl Iterator<Item = &'static InstructionSpec> + '_ {
+    ISA.iter().filter(move |spec| spec.mnemonic.eq_ignore_ascii_case(mnemonic))
+}
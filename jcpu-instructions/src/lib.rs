@@ -1,23 +1,29 @@
+use std::fmt;
+
+mod isa;
+pub use isa::{spec_for, spec_for_mnemonic, specs_for_mnemonic, InstructionSpec, OperandKind, ISA};
+
 #[repr(u8)]
 #[derive(Clone, Debug)]
 pub enum JumpFlag {
     // Where CPU flags: C (carry), A (a > than), E (a equal to), Z (a = 0)
-    CF  = 0b0000,  // clear flag
+    // JMPIF jumps when any of the named flags is set
+    CF  = 0b0000,  // carry flag, names no condition but jumps on carry
     Z   = 0b0001,   // zero
     E   = 0b0010,   // a=b
     EZ  = 0b0011,   // a=b or a=0
     A   = 0b0100,   // a > b
     AZ  = 0b0101,   // a > b or 0
-    AE  = 0b0110,   // a = b
-    AEZ = 0b0111,   // a = b or 0
+    AE  = 0b0110,   // a >= b
+    AEZ = 0b0111,   // a >= b or 0
     C   = 0b1000,   // jump carry
-    CZ  = 0b1001,   //
-    CE  = 0b1010,   //
-    CEZ = 0b1011,   //
-    CA  = 0b1100,   //
-    CAZ = 0b1101,   //
-    CAE = 0b1110,   //
-    CAEZ= 0b1111,   //
+    CZ  = 0b1001,   // carry or 0
+    CE  = 0b1010,   // carry or a=b
+    CEZ = 0b1011,   // carry or a=b or 0
+    CA  = 0b1100,   // carry or a > b
+    CAZ = 0b1101,   // carry or a > b or 0
+    CAE = 0b1110,   // carry or a >= b
+    CAEZ= 0b1111,   // any flag
 }
 
 pub static JUMP_FLAGS: [&str; 16] = [
@@ -26,7 +32,7 @@ pub static JUMP_FLAGS: [&str; 16] = [
 
 // These are the machine language codes for the ALU instructions
 #[repr(u8)]
-#[derive(Clone, Debug)]
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
 pub enum Instruction {
     // ALU instructions are [1][OPC][RA][RB] where opcode is 3 bits, RA and RB are 2 bits
     // OP contents of RA and RB and put into RB
@@ -35,6 +41,7 @@ pub enum Instruction {
     SUB     = 0b10010000,
 
     // COMPARISON
+    // CMP RA, RB
     CMP     = 0b10100000,
 
     // INCREMENT AND DECREMENT
@@ -66,6 +73,19 @@ pub enum Instruction {
     // e.g. JMPA 0x01 ; jump to address 0x01 if the A flag is set
     JMPIF = 0b01010000,
     CLF   = 0b01100000,
+    // Push the address of the next instruction and jump to the next byte in ram
+    // CALL ADDR
+    CALL  = 0b01100001,
+    // Pop the address pushed by CALL and jump back to it
+    RET   = 0b01100010,
+    // Pop the return address and flags pushed when the interrupt was taken
+    IRET  = 0b01100011,
+    // Enable and disable interrupts, they start disabled
+    EI    = 0b01100100,
+    DI    = 0b01100101,
+    // Switch the banked ram window to the bank in the next byte
+    // BANK xxxxxxxx
+    BANK  = 0b01100110,
 
     // PUSH expects a register or a value
     PUSH = 0b11010000,
@@ -74,19 +94,366 @@ pub enum Instruction {
 
     INT = 0b00000001,
     CLI = 0b00000011,
-    // SET VALUE Flag, takes a value of 0b0100_0000 or 0b0010_0000 to let us know if
-    // R1 or 2 is a value or a register
-    SF  = 0b00000010,
-    CRF = 0b00000100,
 
     // HALT
-    HLT = 0b01110000
+    HLT = 0b01110000,
+
+    // Immediate forms take the value from the next byte in ram
+    // PUSH xxxxxxxx
+    PUSHI = 0b01110001,
+    // [0111][01][RB], load RB from the ram address in the next byte
+    // LD xxxxxxxx, RB
+    LDI   = 0b01110100,
+    // [0111][10][RB], store RB at the ram address in the next byte
+    // ST xxxxxxxx, RB
+    STI   = 0b01111000,
+    // [0111][11][RA], compare RA to the next byte
+    // CMP RA, xxxxxxxx
+    CMPI  = 0b01111100,
+
+    // Bitwise instructions are [1110][I][OPC] followed by a byte of [0000][RA][RB]
+    // OP RA, RB puts the result into RB like the other ALU instructions
+    AND = 0b11100000,
+    OR  = 0b11100001,
+    XOR = 0b11100010,
+    // NOT RA, RA = !RA
+    NOT = 0b11100011,
+    // Shifts by 8 or more shift every bit out and leave 0
+    SHL = 0b11100100,
+    SHR = 0b11100101,
+    // With I set the instruction takes a third byte value instead of RB
+    // OP RA, value puts the result into RA
+    ANDI = 0b11101000,
+    ORI  = 0b11101001,
+    XORI = 0b11101010,
+    SHLI = 0b11101100,
+    SHRI = 0b11101101,
+    // ADD and SUB have their register forms above, only the immediate forms live here
+    ADDI = 0b11101110,
+    SUBI = 0b11101111,
 }
 
-#[derive(Debug, Clone, PartialEq, Eq)]
+// The bitwise ops that have a register and an immediate form
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AluOp {
+    And,
+    Or,
+    Xor,
+    Shl,
+    Shr,
+}
+
+impl AluOp {
+    pub fn instruction(&self, immediate: bool) -> Instruction {
+        match (self, immediate) {
+            (AluOp::And, false) => Instruction::AND,
+            (AluOp::Or, false) => Instruction::OR,
+            (AluOp::Xor, false) => Instruction::XOR,
+            (AluOp::Shl, false) => Instruction::SHL,
+            (AluOp::Shr, false) => Instruction::SHR,
+            (AluOp::And, true) => Instruction::ANDI,
+            (AluOp::Or, true) => Instruction::ORI,
+            (AluOp::Xor, true) => Instruction::XORI,
+            (AluOp::Shl, true) => Instruction::SHLI,
+            (AluOp::Shr, true) => Instruction::SHRI,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum Register {
     R1 = 0b00,
     R2 = 0b01,
     R3 = 0b10,
     R4 = 0b11,
 }
+
+impl Register {
+    // Registers are always packed into two bits of an instruction byte
+    pub fn from_bits(bits: u8) -> Register {
+        match bits & 0b11 {
+            0b00 => Register::R1,
+            0b01 => Register::R2,
+            0b10 => Register::R3,
+            _ => Register::R4,
+        }
+    }
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            Register::R1 => "R1",
+            Register::R2 => "R2",
+            Register::R3 => "R3",
+            Register::R4 => "R4",
+        }
+    }
+}
+
+// A single instruction pulled apart into its operands. This is the one place
+// the byte layout of every instruction lives, the CPU decodes with it and the
+// compiler encodes with it.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DecodedInstruction {
+    Ld { ra: Register, rb: Register },
+    St { ra: Register, rb: Register },
+    LdImm { address: u8, rb: Register },
+    StImm { address: u8, rb: Register },
+    Data { reg: Register, value: u8 },
+    Jmpr { reg: Register },
+    Jmp { address: u8 },
+    JmpIf { flags: u8, address: u8 },
+    Call { address: u8 },
+    Ret,
+    Clf,
+    Hlt,
+    Add { ra: Register, rb: Register },
+    Sub { ra: Register, rb: Register },
+    // reg = reg + value and reg = reg - value
+    AddImm { reg: Register, value: u8 },
+    SubImm { reg: Register, value: u8 },
+    Cmp { ra: Register, rb: Register },
+    CmpImm { reg: Register, value: u8 },
+    Inc { reg: Register },
+    Dec { reg: Register },
+    Push { reg: Register },
+    PushImm { value: u8 },
+    Pop { reg: Register },
+    Int { value: u8 },
+    Cli,
+    Iret,
+    Ei,
+    Di,
+    Bank { value: u8 },
+    // RB = RA op RB
+    Alu { op: AluOp, ra: Register, rb: Register },
+    // reg = reg op value
+    AluImm { op: AluOp, reg: Register, value: u8 },
+    Not { reg: Register },
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum DecodeError {
+    Empty,
+    UnknownOpcode(u8),
+    // the opcode needs an operand byte that is not there
+    Truncated(u8),
+}
+
+impl fmt::Display for DecodeError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            DecodeError::Empty => write!(f, "no bytes to decode"),
+            DecodeError::UnknownOpcode(op) => write!(f, "unknown opcode {:08b}", op),
+            DecodeError::Truncated(op) => write!(f, "opcode {:08b} is missing its operand byte", op),
+        }
+    }
+}
+
+impl DecodedInstruction {
+    pub fn instruction(&self) -> Instruction {
+        match self {
+            DecodedInstruction::Ld { .. } => Instruction::LD,
+            DecodedInstruction::St { .. } => Instruction::ST,
+            DecodedInstruction::LdImm { .. } => Instruction::LDI,
+            DecodedInstruction::StImm { .. } => Instruction::STI,
+            DecodedInstruction::Data { .. } => Instruction::DATA,
+            DecodedInstruction::Jmpr { .. } => Instruction::JMPR,
+            DecodedInstruction::Jmp { .. } => Instruction::JMP,
+            DecodedInstruction::JmpIf { .. } => Instruction::JMPIF,
+            DecodedInstruction::Call { .. } => Instruction::CALL,
+            DecodedInstruction::Ret => Instruction::RET,
+            DecodedInstruction::Clf => Instruction::CLF,
+            DecodedInstruction::Hlt => Instruction::HLT,
+            DecodedInstruction::Add { .. } => Instruction::ADD,
+            DecodedInstruction::Sub { .. } => Instruction::SUB,
+            DecodedInstruction::AddImm { .. } => Instruction::ADDI,
+            DecodedInstruction::SubImm { .. } => Instruction::SUBI,
+            DecodedInstruction::Cmp { .. } => Instruction::CMP,
+            DecodedInstruction::CmpImm { .. } => Instruction::CMPI,
+            DecodedInstruction::Inc { .. } => Instruction::INC,
+            DecodedInstruction::Dec { .. } => Instruction::DEC,
+            DecodedInstruction::Push { .. } => Instruction::PUSH,
+            DecodedInstruction::PushImm { .. } => Instruction::PUSHI,
+            DecodedInstruction::Pop { .. } => Instruction::POP,
+            DecodedInstruction::Int { .. } => Instruction::INT,
+            DecodedInstruction::Cli => Instruction::CLI,
+            DecodedInstruction::Iret => Instruction::IRET,
+            DecodedInstruction::Ei => Instruction::EI,
+            DecodedInstruction::Di => Instruction::DI,
+            DecodedInstruction::Bank { .. } => Instruction::BANK,
+            DecodedInstruction::Alu { op, .. } => op.instruction(false),
+            DecodedInstruction::AluImm { op, .. } => op.instruction(true),
+            DecodedInstruction::Not { .. } => Instruction::NOT,
+        }
+    }
+
+    pub fn spec(&self) -> &'static InstructionSpec {
+        let instruction = self.instruction();
+
+        ISA.iter().find(|spec| spec.instruction == instruction).expect("instruction missing from the ISA table")
+    }
+
+    pub fn mnemonic(&self) -> &'static str {
+        self.spec().mnemonic
+    }
+
+    // size of the instruction in bytes, including any operand byte
+    pub fn size(&self) -> usize {
+        self.spec().size
+    }
+}
+
+// Prints the instruction as jasm, jumps are printed with raw addresses
+impl fmt::Display for DecodedInstruction {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let op = self.mnemonic().to_uppercase();
+
+        match *self {
+            DecodedInstruction::Ld { ra, rb }
+            | DecodedInstruction::St { ra, rb }
+            | DecodedInstruction::Add { ra, rb }
+            | DecodedInstruction::Sub { ra, rb }
+            | DecodedInstruction::Cmp { ra, rb } => write!(f, "{} {}, {}", op, ra.name(), rb.name()),
+            DecodedInstruction::Data { reg, value }
+            | DecodedInstruction::AddImm { reg, value }
+            | DecodedInstruction::SubImm { reg, value }
+            | DecodedInstruction::CmpImm { reg, value } => write!(f, "{} {}, {}", op, reg.name(), value),
+            DecodedInstruction::LdImm { address, rb } | DecodedInstruction::StImm { address, rb } => {
+                write!(f, "{} {}, {}", op, address, rb.name())
+            },
+            DecodedInstruction::Alu { ra, rb, .. } => write!(f, "{} {}, {}", op, ra.name(), rb.name()),
+            DecodedInstruction::AluImm { reg, value, .. } => write!(f, "{} {}, {}", op, reg.name(), value),
+            DecodedInstruction::Jmpr { reg }
+            | DecodedInstruction::Inc { reg }
+            | DecodedInstruction::Dec { reg }
+            | DecodedInstruction::Push { reg }
+            | DecodedInstruction::Pop { reg }
+            | DecodedInstruction::Not { reg } => write!(f, "{} {}", op, reg.name()),
+            DecodedInstruction::Jmp { address } | DecodedInstruction::Call { address } => write!(f, "{} ${}", op, address),
+            DecodedInstruction::JmpIf { flags, address } => {
+                write!(f, "{}{} ${}", op, JUMP_FLAGS[(flags & 0x0F) as usize].to_uppercase(), address)
+            },
+            DecodedInstruction::Int { value }
+            | DecodedInstruction::PushImm { value }
+            | DecodedInstruction::Bank { value } => write!(f, "{} {}", op, value),
+            DecodedInstruction::Clf
+            | DecodedInstruction::Hlt
+            | DecodedInstruction::Ret
+            | DecodedInstruction::Cli
+            | DecodedInstruction::Iret
+            | DecodedInstruction::Ei
+            | DecodedInstruction::Di => write!(f, "{}", op),
+        }
+    }
+}
+
+fn reg_a(instruction: u8) -> Register {
+    Register::from_bits((instruction & 0x0C) >> 2)
+}
+
+fn reg_b(instruction: u8) -> Register {
+    Register::from_bits(instruction & 0x03)
+}
+
+pub fn decode(bytes: &[u8]) -> Result<DecodedInstruction, DecodeError> {
+    let instruction = *bytes.first().ok_or(DecodeError::Empty)?;
+    let spec = spec_for(instruction).ok_or(DecodeError::UnknownOpcode(instruction))?;
+
+    if bytes.len() < spec.size {
+        return Err(DecodeError::Truncated(instruction));
+    }
+
+    // the operand byte (if any) is the next byte in ram
+    let value = if spec.size > 1 { bytes[1] } else { 0 };
+
+    let ra = reg_a(instruction);
+    let rb = reg_b(instruction);
+
+    // the bitwise instructions keep their registers in the second byte
+    let alu = |op: AluOp| DecodedInstruction::Alu { op, ra: reg_a(bytes[1]), rb: reg_b(bytes[1]) };
+    let alu_imm = |op: AluOp| DecodedInstruction::AluImm { op, reg: reg_a(bytes[1]), value: bytes[2] };
+
+    let decoded = match spec.instruction {
+        Instruction::LD => DecodedInstruction::Ld { ra, rb },
+        Instruction::ST => DecodedInstruction::St { ra, rb },
+        Instruction::LDI => DecodedInstruction::LdImm { address: value, rb },
+        Instruction::STI => DecodedInstruction::StImm { address: value, rb },
+        Instruction::DATA => DecodedInstruction::Data { reg: ra, value },
+        Instruction::JMPR => DecodedInstruction::Jmpr { reg: ra },
+        Instruction::JMP => DecodedInstruction::Jmp { address: value },
+        Instruction::JMPIF => DecodedInstruction::JmpIf { flags: instruction & 0x0F, address: value },
+        Instruction::CALL => DecodedInstruction::Call { address: value },
+        Instruction::RET => DecodedInstruction::Ret,
+        Instruction::CLF => DecodedInstruction::Clf,
+        Instruction::HLT => DecodedInstruction::Hlt,
+        Instruction::ADD => DecodedInstruction::Add { ra, rb },
+        Instruction::SUB => DecodedInstruction::Sub { ra, rb },
+        Instruction::ADDI => DecodedInstruction::AddImm { reg: reg_a(bytes[1]), value: bytes[2] },
+        Instruction::SUBI => DecodedInstruction::SubImm { reg: reg_a(bytes[1]), value: bytes[2] },
+        Instruction::CMP => DecodedInstruction::Cmp { ra, rb },
+        Instruction::CMPI => DecodedInstruction::CmpImm { reg: rb, value },
+        Instruction::INC => DecodedInstruction::Inc { reg: ra },
+        Instruction::DEC => DecodedInstruction::Dec { reg: ra },
+        Instruction::PUSH => DecodedInstruction::Push { reg: ra },
+        Instruction::PUSHI => DecodedInstruction::PushImm { value },
+        Instruction::POP => DecodedInstruction::Pop { reg: ra },
+        Instruction::INT => DecodedInstruction::Int { value },
+        Instruction::CLI => DecodedInstruction::Cli,
+        Instruction::IRET => DecodedInstruction::Iret,
+        Instruction::EI => DecodedInstruction::Ei,
+        Instruction::DI => DecodedInstruction::Di,
+        Instruction::BANK => DecodedInstruction::Bank { value },
+        Instruction::AND => alu(AluOp::And),
+        Instruction::OR => alu(AluOp::Or),
+        Instruction::XOR => alu(AluOp::Xor),
+        Instruction::SHL => alu(AluOp::Shl),
+        Instruction::SHR => alu(AluOp::Shr),
+        Instruction::ANDI => alu_imm(AluOp::And),
+        Instruction::ORI => alu_imm(AluOp::Or),
+        Instruction::XORI => alu_imm(AluOp::Xor),
+        Instruction::SHLI => alu_imm(AluOp::Shl),
+        Instruction::SHRI => alu_imm(AluOp::Shr),
+        Instruction::NOT => DecodedInstruction::Not { reg: reg_a(bytes[1]) },
+    };
+
+    Ok(decoded)
+}
+
+pub fn encode(instruction: &DecodedInstruction) -> Vec<u8> {
+    // the operand bits packed into the first byte and the operand bytes, if any
+    let (bits, operands) = match *instruction {
+        DecodedInstruction::Ld { ra, rb }
+        | DecodedInstruction::St { ra, rb }
+        | DecodedInstruction::Add { ra, rb }
+        | DecodedInstruction::Sub { ra, rb }
+        | DecodedInstruction::Cmp { ra, rb } => ((ra as u8) << 2 | (rb as u8), vec![]),
+        DecodedInstruction::Data { reg, value } => ((reg as u8) << 2, vec![value]),
+        DecodedInstruction::LdImm { address, rb } | DecodedInstruction::StImm { address, rb } => (rb as u8, vec![address]),
+        DecodedInstruction::CmpImm { reg, value } => (reg as u8, vec![value]),
+        DecodedInstruction::AddImm { reg, value } | DecodedInstruction::SubImm { reg, value } => (0, vec![(reg as u8) << 2, value]),
+        DecodedInstruction::Jmpr { reg }
+        | DecodedInstruction::Inc { reg }
+        | DecodedInstruction::Dec { reg }
+        | DecodedInstruction::Push { reg }
+        | DecodedInstruction::Pop { reg } => ((reg as u8) << 2, vec![]),
+        DecodedInstruction::Jmp { address } | DecodedInstruction::Call { address } => (0, vec![address]),
+        DecodedInstruction::JmpIf { flags, address } => (flags & 0x0F, vec![address]),
+        DecodedInstruction::Int { value }
+        | DecodedInstruction::PushImm { value }
+        | DecodedInstruction::Bank { value } => (0, vec![value]),
+        DecodedInstruction::Clf
+        | DecodedInstruction::Hlt
+        | DecodedInstruction::Ret
+        | DecodedInstruction::Cli
+        | DecodedInstruction::Iret
+        | DecodedInstruction::Ei
+        | DecodedInstruction::Di => (0, vec![]),
+        DecodedInstruction::Alu { ra, rb, .. } => (0, vec![(ra as u8) << 2 | (rb as u8)]),
+        DecodedInstruction::AluImm { reg, value, .. } => (0, vec![(reg as u8) << 2, value]),
+        DecodedInstruction::Not { reg } => (0, vec![(reg as u8) << 2]),
+    };
+
+    let mut bytes = vec![instruction.spec().opcode() | bits];
+    bytes.extend(operands);
+    bytes
+}
@@ -0,0 +1,100 @@
+use jcpuinstructions::{decode, encode, spec_for, AluOp, DecodedInstruction, Instruction, Register, ISA};
+
+const REGISTERS: [Register; 4] = [Register::R1, Register::R2, Register::R3, Register::R4];
+const VALUES: [u8; 4] = [0, 1, 0x80, 0xFF];
+
+fn alu_op(instruction: Instruction) -> AluOp {
+    match instruction {
+        Instruction::AND | Instruction::ANDI => AluOp::And,
+        Instruction::OR | Instruction::ORI => AluOp::Or,
+        Instruction::XOR | Instruction::XORI => AluOp::Xor,
+        Instruction::SHL | Instruction::SHLI => AluOp::Shl,
+        _ => AluOp::Shr,
+    }
+}
+
+// Every way of filling in the operands of one ISA entry, over all registers and a few values
+fn examples(instruction: Instruction) -> Vec<DecodedInstruction> {
+    let pairs = || REGISTERS.iter().flat_map(|ra| REGISTERS.iter().map(move |rb| (*ra, *rb)));
+    let with_value = |f: &dyn Fn(Register, u8) -> DecodedInstruction| -> Vec<DecodedInstruction> {
+        REGISTERS.iter().flat_map(|r| VALUES.iter().map(move |v| (*r, *v))).map(|(r, v)| f(r, v)).collect()
+    };
+    let values = |f: &dyn Fn(u8) -> DecodedInstruction| -> Vec<DecodedInstruction> { VALUES.iter().map(|v| f(*v)).collect() };
+    let registers = |f: &dyn Fn(Register) -> DecodedInstruction| -> Vec<DecodedInstruction> { REGISTERS.iter().map(|r| f(*r)).collect() };
+
+    match instruction {
+        Instruction::LD => pairs().map(|(ra, rb)| DecodedInstruction::Ld { ra, rb }).collect(),
+        Instruction::ST => pairs().map(|(ra, rb)| DecodedInstruction::St { ra, rb }).collect(),
+        Instruction::ADD => pairs().map(|(ra, rb)| DecodedInstruction::Add { ra, rb }).collect(),
+        Instruction::SUB => pairs().map(|(ra, rb)| DecodedInstruction::Sub { ra, rb }).collect(),
+        Instruction::CMP => pairs().map(|(ra, rb)| DecodedInstruction::Cmp { ra, rb }).collect(),
+        Instruction::AND | Instruction::OR | Instruction::XOR | Instruction::SHL | Instruction::SHR => {
+            pairs().map(|(ra, rb)| DecodedInstruction::Alu { op: alu_op(instruction), ra, rb }).collect()
+        },
+        Instruction::ANDI | Instruction::ORI | Instruction::XORI | Instruction::SHLI | Instruction::SHRI => {
+            with_value(&|reg, value| DecodedInstruction::AluImm { op: alu_op(instruction), reg, value })
+        },
+        Instruction::LDI => with_value(&|rb, address| DecodedInstruction::LdImm { address, rb }),
+        Instruction::STI => with_value(&|rb, address| DecodedInstruction::StImm { address, rb }),
+        Instruction::DATA => with_value(&|reg, value| DecodedInstruction::Data { reg, value }),
+        Instruction::ADDI => with_value(&|reg, value| DecodedInstruction::AddImm { reg, value }),
+        Instruction::SUBI => with_value(&|reg, value| DecodedInstruction::SubImm { reg, value }),
+        Instruction::CMPI => with_value(&|reg, value| DecodedInstruction::CmpImm { reg, value }),
+        Instruction::JMPR => registers(&|reg| DecodedInstruction::Jmpr { reg }),
+        Instruction::INC => registers(&|reg| DecodedInstruction::Inc { reg }),
+        Instruction::DEC => registers(&|reg| DecodedInstruction::Dec { reg }),
+        Instruction::PUSH => registers(&|reg| DecodedInstruction::Push { reg }),
+        Instruction::POP => registers(&|reg| DecodedInstruction::Pop { reg }),
+        Instruction::NOT => registers(&|reg| DecodedInstruction::Not { reg }),
+        Instruction::JMP => values(&|address| DecodedInstruction::Jmp { address }),
+        Instruction::CALL => values(&|address| DecodedInstruction::Call { address }),
+        Instruction::JMPIF => (0..16).flat_map(|flags| VALUES.iter().map(move |address| DecodedInstruction::JmpIf { flags, address: *address })).collect(),
+        Instruction::INT => values(&|value| DecodedInstruction::Int { value }),
+        Instruction::PUSHI => values(&|value| DecodedInstruction::PushImm { value }),
+        Instruction::BANK => values(&|value| DecodedInstruction::Bank { value }),
+        Instruction::RET => vec![DecodedInstruction::Ret],
+        Instruction::CLF => vec![DecodedInstruction::Clf],
+        Instruction::HLT => vec![DecodedInstruction::Hlt],
+        Instruction::CLI => vec![DecodedInstruction::Cli],
+        Instruction::IRET => vec![DecodedInstruction::Iret],
+        Instruction::EI => vec![DecodedInstruction::Ei],
+        Instruction::DI => vec![DecodedInstruction::Di],
+    }
+}
+
+// LD R1, R2 and LD R1, R4 are 0x01 and 0x03, which INT and CLI took over
+fn shadowed(instruction: &DecodedInstruction) -> bool {
+    matches!(instruction, DecodedInstruction::Ld { ra: Register::R1, rb: Register::R2 | Register::R4 })
+}
+
+#[test]
+fn every_isa_entry_round_trips() {
+    for spec in ISA.iter() {
+        let examples = examples(spec.instruction);
+        assert!(!examples.is_empty(), "no examples for {:?}", spec.instruction);
+
+        for instruction in examples.iter().filter(|i| !shadowed(i)) {
+            let bytes = encode(instruction);
+
+            assert_eq!(bytes.len(), spec.size, "size of {}", instruction);
+            assert!(spec.matches(bytes[0]), "{} encodes to {:02x}, not {:?}", instruction, bytes[0], spec.instruction);
+            assert_eq!(spec_for(bytes[0]).map(|s| s.instruction), Some(spec.instruction), "{} decodes as another instruction", instruction);
+            assert_eq!(decode(&bytes), Ok(*instruction), "{:02x?}", bytes);
+        }
+    }
+}
+
+#[test]
+fn int_and_cli_take_the_ld_encodings_they_overlap() {
+    let ld_r1_r2 = encode(&DecodedInstruction::Ld { ra: Register::R1, rb: Register::R2 });
+    let ld_r1_r4 = encode(&DecodedInstruction::Ld { ra: Register::R1, rb: Register::R4 });
+
+    assert_eq!(ld_r1_r2, [Instruction::INT as u8]);
+    assert_eq!(ld_r1_r4, [Instruction::CLI as u8]);
+    assert_eq!(decode(&[0x01, 0x07]), Ok(DecodedInstruction::Int { value: 7 }));
+    assert_eq!(decode(&ld_r1_r4), Ok(DecodedInstruction::Cli));
+
+    // only those two, every other LD still decodes as LD
+    let ld_examples = examples(Instruction::LD);
+    assert_eq!(ld_examples.iter().filter(|i| shadowed(i)).count(), 2);
+}
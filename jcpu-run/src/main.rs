@@ -0,0 +1,436 @@
+/*
+    Boots a boot.img without the TUI and runs it until HLT, a fault, a breakpoint or
+    watchpoint or the cycle limit, then dumps the machine. Meant for scripts and CI,
+    so the exit code says how the run ended:
+
+    0 the program halted
+    1 bad arguments or files
+    2 the cycle limit was reached
+    3 the cpu faulted
+    4 a breakpoint or watchpoint fired
+
+    A uart in the machine profile talks to its backend while the program runs, the
+    dump ends with everything it sent.
+
+    --screenshot saves the screen as it was at the end, --frames saves every frame
+    it drew (or with --capture change, every picture it showed) as numbered images
+    and --gif puts those frames in one animated GIF.
+
+    With --gdb it serves a GDB remote protocol client instead, and exits once the
+    client detaches.
+*/
+
+use std::{fs, process};
+
+use jcpu::{
+    capture::{CaptureMode, Frame, Recorder},
+    config::MachineConfig,
+    debugger::{DebugRegister, Debugger, Stop, WatchAccess, Watchpoint},
+    fault::{FaultPolicies, FaultPolicy},
+    gdbstub,
+    motherboard::Motherboard,
+    keyboard::{key_of, Keyboard},
+    peripheral::{Screen, MODE_TEXT},
+    tracer::{TraceFormat, Tracer},
+    uart::Uart,
+};
+use serde_json::json;
+
+const USAGE: &str = "usage: jcpu-run [--config PATH] [--max-cycles N] [--format text|json] [--keys TEXT] [--keys-file PATH] [--key-interval N] [--faults halt|trap|ignore] [--break ADDR] [--watch ADDR[-END][:r|w|rw]] [--watch-reg REG] [--gdb HOST:PORT|unix:PATH] [--trace PATH] [--trace-format text|json] [--screenshot PATH] [--frames PATH] [--gif PATH] [--capture frame|change] [--scale N] [--gif-delay N] [-o PATH] [boot.img]";
+
+#[derive(PartialEq)]
+enum Format {
+    Text,
+    Json,
+}
+
+fn status(stop: &Stop) -> &'static str {
+    match stop {
+        Stop::Halted => "halted",
+        Stop::CycleLimit => "cycle_limit",
+        Stop::Fault(_) => "fault",
+        Stop::Breakpoint(_) | Stop::Ram { .. } | Stop::Register { .. } => "break",
+    }
+}
+
+fn exit_code(stop: &Stop) -> i32 {
+    match stop {
+        Stop::Halted => 0,
+        Stop::CycleLimit => 2,
+        Stop::Fault(_) => 3,
+        Stop::Breakpoint(_) | Stop::Ram { .. } | Stop::Register { .. } => 4,
+    }
+}
+
+fn main() {
+    let mut args = std::env::args().skip(1);
+    let mut input = String::from("boot.img");
+    let mut output: Option<String> = None;
+    let mut format = Format::Text;
+    let mut max_cycles: usize = 10_000;
+    let mut keys = String::new();
+    let mut key_interval: usize = 1;
+    let mut policy = FaultPolicy::Halt;
+    let mut debugger = Debugger::new();
+    let mut gdb: Option<String> = None;
+    let mut trace: Option<String> = None;
+    let mut trace_format = TraceFormat::Text;
+    let mut config = MachineConfig::default();
+    let mut screenshot: Option<String> = None;
+    let mut frames: Option<String> = None;
+    let mut gif: Option<String> = None;
+    let mut capture = CaptureMode::Frame;
+    let mut scale: usize = 1;
+    let mut gif_delay: usize = 10;
+
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--config" => config = match MachineConfig::load(&value(args.next())) {
+                Ok(config) => config,
+                Err(e) => fail(&e.to_string()),
+            },
+            "--max-cycles" => max_cycles = number(args.next()),
+            "--key-interval" => key_interval = number(args.next()).max(1),
+            "--keys" => keys.push_str(&value(args.next())),
+            "--keys-file" => {
+                let path = value(args.next());
+                match fs::read_to_string(&path) {
+                    Ok(text) => keys.push_str(&text),
+                    Err(e) => fail(&format!("failed to read {}: {}", path, e)),
+                }
+            },
+            "--format" => format = match value(args.next()).as_str() {
+                "text" => Format::Text,
+                "json" => Format::Json,
+                other => fail(&format!("unknown format {}", other)),
+            },
+            "--faults" => policy = match value(args.next()).as_str() {
+                "halt" => FaultPolicy::Halt,
+                "trap" => FaultPolicy::Trap,
+                "ignore" => FaultPolicy::Ignore,
+                other => fail(&format!("unknown fault policy {}", other)),
+            },
+            "--break" => debugger.add_breakpoint(byte(args.next())),
+            "--watch" => {
+                let watchpoint = watchpoint(&value(args.next()));
+                debugger.watch(watchpoint);
+            },
+            "--watch-reg" => {
+                let name = value(args.next());
+                match DebugRegister::from_name(&name) {
+                    Some(register) => debugger.watch(Watchpoint::Register(register)),
+                    None => fail(&format!("unknown register {}", name)),
+                };
+            },
+            "--gdb" => gdb = Some(value(args.next())),
+            "--trace" => trace = Some(value(args.next())),
+            "--trace-format" => trace_format = match value(args.next()).as_str() {
+                "text" => TraceFormat::Text,
+                "json" => TraceFormat::JsonLines,
+                other => fail(&format!("unknown trace format {}", other)),
+            },
+            "--screenshot" => screenshot = Some(value(args.next())),
+            "--frames" => frames = Some(value(args.next())),
+            "--gif" => gif = Some(value(args.next())),
+            "--capture" => capture = match value(args.next()).as_str() {
+                "frame" => CaptureMode::Frame,
+                "change" => CaptureMode::Change,
+                other => fail(&format!("unknown capture mode {}", other)),
+            },
+            "--scale" => scale = number(args.next()).clamp(1, 64),
+            "--gif-delay" => gif_delay = number(args.next()).min(u16::MAX as usize),
+            "-o" => output = Some(value(args.next())),
+            "-h" | "--help" => {
+                println!("{}", USAGE);
+                return;
+            },
+            // a misspelled option is not a file name
+            _ if arg.starts_with('-') => fail(&format!("unknown option {}", arg)),
+            _ => input = arg,
+        }
+    }
+
+    let image = match fs::read(&input) {
+        Ok(image) => image,
+        Err(e) => fail(&format!("failed to read {}: {}", input, e)),
+    };
+    if image.len() > config.image_size() {
+        fail(&format!("{} is {} bytes, the machine holds {}", input, image.len(), config.image_size()));
+    }
+
+    let mut key_codes = Vec::new();
+    for c in keys.chars() {
+        match key_code(c) {
+            Some(code) => key_codes.push(code),
+            None => fail(&format!("no key code for {:?}", c)),
+        }
+    }
+
+    let mut mb = match Motherboard::with_config(&input, "", config) {
+        Ok(mb) => mb,
+        Err(e) => fail(&e.to_string()),
+    };
+    mb.fault_policies = FaultPolicies::all(policy);
+    // nothing steps back here, so skip recording
+    mb.history.limit = 0;
+    mb.load(image);
+    // where the serial port goes, a pty has to be opened by a terminal program
+    if let Some(uart) = mb.find_kind::<Uart>().and_then(|id| mb.device::<Uart>(id)) {
+        eprintln!("serial port: {}", uart.backend.describe());
+    }
+
+    if let Some(path) = &trace {
+        match Tracer::to_file(path, trace_format) {
+            Ok(tracer) => mb.tracer = Some(tracer),
+            Err(e) => fail(&format!("failed to create {}: {}", path, e)),
+        }
+    }
+
+    if let Some(address) = gdb {
+        eprintln!("waiting for a gdb client on {}", address);
+        let served = match address.strip_prefix("unix:") {
+            Some(path) => serve_unix(&mut mb, &mut debugger, path),
+            None => gdbstub::serve_tcp(&mut mb, &mut debugger, &address),
+        };
+        if let Err(e) = served {
+            fail(&format!("gdb session on {} failed: {}", address, e))
+        }
+        return;
+    }
+
+    let mut recorder = (frames.is_some() || gif.is_some()).then(|| Recorder::new(capture));
+    let (stop, cycles) = run(&mut mb, &mut debugger, max_cycles, &key_codes, key_interval, recorder.as_mut());
+
+    if let Some(mut tracer) = mb.tracer.take() {
+        if let Some(e) = tracer.error.take().map_or_else(|| tracer.flush().err(), Some) {
+            fail(&format!("failed to write the trace: {}", e))
+        }
+    }
+
+    if let Some(path) = &screenshot {
+        if let Err(e) = Frame::of(&mb).and_then(|frame| frame.save(path, scale)) {
+            fail(&format!("failed to save the screenshot: {}", e))
+        }
+    }
+    if let Some(recorder) = &recorder {
+        if let Some(path) = &frames {
+            if let Err(e) = recorder.save_frames(path, scale) {
+                fail(&format!("failed to save the frames: {}", e))
+            }
+        }
+        if let Some(path) = &gif {
+            if let Err(e) = recorder.save_gif(path, gif_delay as u16, scale) {
+                fail(&format!("failed to save the gif: {}", e))
+            }
+        }
+    }
+
+    let dump = match format {
+        Format::Text => dump_text(&mb, &stop, cycles),
+        Format::Json => dump_json(&mb, &stop, cycles),
+    };
+
+    match output {
+        Some(path) => {
+            if let Err(e) = fs::write(&path, dump) {
+                fail(&format!("failed to write {}: {}", path, e))
+            }
+        },
+        None => print!("{}", dump),
+    }
+
+    process::exit(exit_code(&stop));
+}
+
+// The debugger runs cycles the way the sim does, one key every key_interval cycles instead of a keypress
+fn run(mb: &mut Motherboard, debugger: &mut Debugger, max_cycles: usize, keys: &[u8], key_interval: usize, mut recorder: Option<&mut Recorder>) -> (Stop, usize) {
+    let mut cycles = 0;
+
+    while cycles < max_cycles {
+        if cycles % key_interval == 0 {
+            if let Some(code) = keys.get(cycles / key_interval) {
+                if let Some(kb) = mb.find_kind::<Keyboard>() {
+                    mb.input(kb, *code);
+                }
+            }
+        }
+
+        let stop = debugger.step(mb);
+        cycles += 1;
+        if let Some(recorder) = recorder.as_deref_mut() {
+            recorder.capture(mb);
+        }
+
+        if let Some(stop) = stop {
+            return (stop, cycles);
+        }
+    }
+
+    (Stop::CycleLimit, cycles)
+}
+
+#[cfg(unix)]
+fn serve_unix(mb: &mut Motherboard, debugger: &mut Debugger, path: &str) -> std::io::Result<()> {
+    gdbstub::serve_unix(mb, debugger, path)
+}
+
+#[cfg(not(unix))]
+fn serve_unix(_: &mut Motherboard, _: &mut Debugger, _: &str) -> std::io::Result<()> {
+    Err(std::io::Error::new(std::io::ErrorKind::Unsupported, "unix sockets are not available here"))
+}
+
+// ADDR, START-END, either followed by :r, :w or :rw (the default)
+fn watchpoint(arg: &str) -> Watchpoint {
+    let (range, access) = arg.split_once(':').unwrap_or((arg, "rw"));
+    let access = match access {
+        "r" => WatchAccess::Read,
+        "w" => WatchAccess::Write,
+        "rw" => WatchAccess::Any,
+        other => fail(&format!("unknown watch access {}", other)),
+    };
+    let (start, end) = range.split_once('-').unwrap_or((range, range));
+
+    Watchpoint::Ram { start: byte(Some(start.to_string())), end: byte(Some(end.to_string())), access }
+}
+
+// The characters the keyboard can type, as ASCII
+fn key_code(c: char) -> Option<u8> {
+    let code = match c {
+        '\r' => b'\n',
+        c if c.is_ascii() => c as u8,
+        _ => return None,
+    };
+    key_of(code).map(|_| code)
+}
+
+fn screen_buffer(mb: &Motherboard) -> Vec<u8> {
+    match mb.find_kind::<Screen>().and_then(|id| mb.device::<Screen>(id)) {
+        Some(screen) => screen.frame(),
+        None => vec![],
+    }
+}
+
+// what the program sent over the serial port, None without one
+fn serial_console(mb: &Motherboard) -> Option<String> {
+    let uart = mb.find_kind::<Uart>().and_then(|id| mb.device::<Uart>(id))?;
+    Some(String::from_utf8_lossy(&uart.console).to_string())
+}
+
+// the characters on a text mode screen, None in graphics mode or without one
+fn screen_text(mb: &Motherboard) -> Option<Vec<String>> {
+    let screen = mb.find_kind::<Screen>().and_then(|id| mb.device::<Screen>(id))?;
+    (screen.mode == MODE_TEXT).then(|| screen.text())
+}
+
+// 0 by 0 when the profile has no screen
+fn screen_size(mb: &Motherboard) -> (u8, u8) {
+    mb.config.screen_size().unwrap_or((0, 0))
+}
+
+fn registers(mb: &Motherboard) -> Vec<(&'static str, u8)> {
+    vec![
+        ("r1", mb.cpu.reg_1),
+        ("r2", mb.cpu.reg_2),
+        ("r3", mb.cpu.reg_3),
+        ("r4", mb.cpu.reg_4),
+        ("ir", mb.cpu.reg_ir),
+        ("iar", mb.cpu.reg_iar),
+        ("mar", mb.cpu.reg_mar),
+        ("out", mb.cpu.reg_out),
+        ("sp", mb.cpu.reg_sp),
+        ("int", mb.cpu.reg_int),
+    ]
+}
+
+fn dump_json(mb: &Motherboard, stop: &Stop, cycles: usize) -> String {
+    let registers: serde_json::Map<String, serde_json::Value> =
+        registers(mb).into_iter().map(|(name, value)| (name.to_string(), json!(value))).collect();
+    let fault = match stop {
+        Stop::Fault(fault) => json!(fault.to_string()),
+        _ => json!(null),
+    };
+
+    let dump = json!({
+        "status": status(stop),
+        "stop": stop.to_string(),
+        "fault": fault,
+        "cycles": cycles,
+        "registers": registers,
+        "flags": mb.cpu.alu.flags,
+        "bank": mb.ram.bank,
+        "ram": mb.ram.memory.to_vec(),
+        "screen": {
+            "width": screen_size(mb).0,
+            "height": screen_size(mb).1,
+            "buffer": screen_buffer(mb),
+            "text": screen_text(mb),
+        },
+        "serial": serial_console(mb),
+    });
+
+    format!("{}\n", serde_json::to_string_pretty(&dump).unwrap())
+}
+
+fn dump_text(mb: &Motherboard, stop: &Stop, cycles: usize) -> String {
+    let mut text = format!("status: {}\n", status(stop));
+    text.push_str(&format!("stop: {}\n", stop));
+    text.push_str(&format!("cycles: {}\n", cycles));
+
+    for (name, value) in registers(mb) {
+        text.push_str(&format!("{}: {:02x}\n", name, value));
+    }
+    text.push_str(&format!("flags: {:08b} (INT|-|-|GT|EQ|Z|S|C)\n", mb.cpu.alu.flags));
+    text.push_str(&format!("bank: {}\n", mb.ram.bank));
+
+    text.push_str("ram:\n");
+    for (row, bytes) in mb.ram.memory.chunks(16).enumerate() {
+        let bytes: Vec<String> = bytes.iter().map(|b| format!("{:02x}", b)).collect();
+        text.push_str(&format!("  {:03}: {}\n", row * 16, bytes.join(" ")));
+    }
+
+    text.push_str("screen:\n");
+    for row in screen_buffer(mb).chunks(screen_size(mb).0.max(1) as usize) {
+        let bytes: Vec<String> = row.iter().map(|b| format!("{:02x}", b)).collect();
+        text.push_str(&format!("  {}\n", bytes.join(" ")));
+    }
+    if let Some(lines) = screen_text(mb) {
+        text.push_str("text:\n");
+        for line in lines {
+            text.push_str(&format!("  |{}|\n", line));
+        }
+    }
+
+    if let Some(console) = serial_console(mb) {
+        text.push_str(&format!("serial: {:?}\n", console));
+    }
+
+    text
+}
+
+fn value(arg: Option<String>) -> String {
+    match arg {
+        Some(arg) => arg,
+        None => fail("missing value for the last option"),
+    }
+}
+
+fn number(arg: Option<String>) -> usize {
+    let arg = value(arg);
+    match arg.parse() {
+        Ok(n) => n,
+        Err(_) => fail(&format!("{} is not a number", arg)),
+    }
+}
+
+fn byte(arg: Option<String>) -> u8 {
+    let arg = value(arg);
+    match arg.parse() {
+        Ok(n) => n,
+        Err(_) => fail(&format!("{} is not an address (0-255)", arg)),
+    }
+}
+
+fn fail(msg: &str) -> ! {
+    eprintln!("{}\n{}", msg, USAGE);
+    process::exit(1);
+}
@@ -0,0 +1,97 @@
+use std::{
+    fs,
+    path::PathBuf,
+    process::{Command, Output},
+};
+
+// Assembles program into a boot image of its own and runs jcpu-run on it with args
+fn run(name: &str, program: &str, args: &[&str]) -> Output {
+    let image = std::env::temp_dir().join(format!("jcpu-run-{}-{}.img", name, std::process::id()));
+    fs::write(&image, jcpu_compiler::assemble(program)).unwrap();
+
+    let output = Command::new(env!("CARGO_BIN_EXE_jcpu-run")).args(args).arg(&image).output().unwrap();
+    fs::remove_file(&image).unwrap();
+    output
+}
+
+fn json(output: &Output) -> serde_json::Value {
+    serde_json::from_slice(&output.stdout).unwrap()
+}
+
+#[test]
+fn a_program_that_halts_exits_0() {
+    let output = run("halt", "DATA R1, 5\nADD R1, R1\nHLT\n", &[]);
+
+    assert_eq!(output.status.code(), Some(0));
+    let dump = String::from_utf8(output.stdout).unwrap();
+    assert!(dump.starts_with("status: halted\n"), "{}", dump);
+    assert!(dump.contains("\nr1: 0a\n"), "{}", dump);
+}
+
+#[test]
+fn the_cycle_limit_exits_2() {
+    let output = run("limit", "loop:\nJMP $loop\n", &["--max-cycles", "50", "--format", "json"]);
+
+    assert_eq!(output.status.code(), Some(2));
+    let dump = json(&output);
+    assert_eq!(dump["status"], "cycle_limit");
+    assert_eq!(dump["cycles"], 50);
+}
+
+#[test]
+fn a_fault_exits_3() {
+    // nothing was pushed
+    let output = run("fault", "POP R1\nHLT\n", &["--format", "json"]);
+
+    assert_eq!(output.status.code(), Some(3));
+    let dump = json(&output);
+    assert_eq!(dump["status"], "fault");
+    assert_eq!(dump["fault"], "stack underflow");
+
+    // ignoring it runs on to the HLT
+    assert_eq!(run("ignored", "POP R1\nHLT\n", &["--faults", "ignore"]).status.code(), Some(0));
+}
+
+#[test]
+fn bad_arguments_exit_1() {
+    // a misspelled option is not taken for the image
+    let output = run("misspelled", "HLT\n", &["--max-cycle", "50"]);
+    assert_eq!(output.status.code(), Some(1));
+    assert!(String::from_utf8_lossy(&output.stderr).starts_with("unknown option --max-cycle\n"));
+
+    assert_eq!(run("number", "HLT\n", &["--max-cycles", "lots"]).status.code(), Some(1));
+    let missing = Command::new(env!("CARGO_BIN_EXE_jcpu-run")).arg(PathBuf::from("/nonexistent/boot.img")).output().unwrap();
+    assert_eq!(missing.status.code(), Some(1));
+}
+
+#[test]
+fn the_json_dump_has_the_machine() {
+    let output = run("dump", "DATA R2, 7\nST 140, R2\nBANK 2\nHLT\n", &["--format", "json"]);
+    let dump = json(&output);
+
+    assert_eq!(dump["stop"], "halted");
+    assert_eq!(dump["cycles"], 4);
+    assert_eq!(dump["registers"]["r2"], 7);
+    assert_eq!(dump["bank"], 2);
+    assert_eq!(dump["ram"].as_array().unwrap().len(), 255);
+    // bank 0 held the store, bank 2 is in the window now
+    assert_eq!(dump["ram"][140], 0);
+    assert_eq!(dump["screen"]["width"], 32);
+    assert_eq!(dump["serial"], serde_json::Value::Null);
+}
+
+#[test]
+fn keys_are_typed_while_it_runs() {
+    // waits for each key in turn, reading DATA into R2 then R3
+    let program = "one:\nLD 0, R1\nAND R1, 1\nJMPIFZ $one\nLD 1, R2\ntwo:\nLD 0, R1\nAND R1, 1\nJMPIFZ $two\nLD 1, R3\nHLT\n";
+
+    let output = run("keys", program, &["--keys", "ok", "--key-interval", "20", "--format", "json"]);
+    assert_eq!(output.status.code(), Some(0));
+    let dump = json(&output);
+    assert_eq!((dump["registers"]["r2"].clone(), dump["registers"]["r3"].clone()), (b'o'.into(), b'k'.into()));
+    // the second key only came 20 cycles after the first
+    assert!(dump["cycles"].as_u64().unwrap() > 20);
+
+    // characters no key types are refused
+    assert_eq!(run("nokey", program, &["--keys", "é"]).status.code(), Some(1));
+}
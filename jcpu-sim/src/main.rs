@@ -6,16 +6,16 @@
 
 pub mod sim;
 
-use jcpu::{peripheral::{Keyboard, Screen, get_key_code, Peripheral}, motherboard::{SCREEN_WIDTH, SCREEN_HEIGHT}};
+use jcpu::{config::MachineConfig, debugger::Debugger, keyboard::{KeyEvent, MOD_ALT, MOD_CTRL}, peripheral::{Screen, PALETTE}};
 use sim::Sim;
 
 use crossterm::{
-    event::{DisableMouseCapture, EnableMouseCapture, Event, KeyCode, read, poll, MouseEventKind, MouseButton},
+    event::{DisableMouseCapture, EnableMouseCapture, Event, KeyCode, KeyModifiers, read, poll, MouseEventKind, MouseButton},
     execute,
     terminal::{disable_raw_mode, enable_raw_mode, EnterAlternateScreen, LeaveAlternateScreen},
 };
 // use xcpu::cpu::register;
-use std::{error::Error, io, time::Duration};
+use std::{error::Error, io, path::Path, time::Duration};
 use tui::{
     backend::{Backend, CrosstermBackend},
     layout::{Alignment, Constraint, Direction, Layout},
@@ -26,6 +26,7 @@ use tui::{
 };
 
 pub const FULL: &str = "█";
+pub const UPPER_HALF: &str = "▀";
 
 fn main() -> Result<(), Box<dyn Error>> {
     // setup terminal
@@ -49,22 +50,32 @@ fn main() -> Result<(), Box<dyn Error>> {
     terminal.show_cursor()?;
 
     if let Err(err) = res {
-        println!("{:?}", err)
+        eprintln!("{}", err);
+        std::process::exit(1);
     }
 
     Ok(())
 }
 
 fn run_app<B: Backend>(terminal: &mut Terminal<B>) -> io::Result<()> {
-    // create peripherals
-    const SCREEN_SIZE: u8 = SCREEN_WIDTH * SCREEN_HEIGHT;
-    let screen = Screen {buffer: [0; SCREEN_SIZE as usize]};
-    let kb = Keyboard {keys_pressed: vec![]};
-
-    let mut sim: Sim = Sim::new();
+    // jcpu-sim [--config machine.toml] [boot.img], the compiler writes instructions.d next to the image
+    let mut args = std::env::args().skip(1);
+    let mut bootimg = String::from("./boot.img");
+    let mut config = MachineConfig::default();
+    while let Some(arg) = args.next() {
+        if arg == "--config" {
+            let path = args.next().unwrap_or_default();
+            config = MachineConfig::load(&path).map_err(|e| io::Error::new(io::ErrorKind::InvalidInput, e.to_string()))?;
+        } else {
+            bootimg = arg;
+        }
+    }
+    let instructions = Path::new(&bootimg).with_file_name("instructions.d");
 
-    sim.mb.peripherals.insert("screen", Peripheral::Screen(screen));
-    sim.mb.peripherals.insert("keyboad", Peripheral::Keyboard(kb));
+    // the profile decides the peripherals, the board plugs them in
+    let mut sim: Sim = Sim::new(&bootimg, &instructions.to_string_lossy(), config)
+        .map_err(|e| io::Error::new(io::ErrorKind::InvalidInput, e.to_string()))?;
+    let bank = sim.mb.config.region("bank");
 
     sim.start();
 
@@ -79,10 +90,14 @@ fn run_app<B: Backend>(terminal: &mut Terminal<B>) -> io::Result<()> {
             let info_cpu_data = sim.get_cpu_details();
             let info_alu_data = sim.get_alu_details();
             let info_ram = sim.get_ram_info();
-            let info_kb = sim.get_kb_info();
+            let (ram_bank, ram_banks) = sim.get_ram_bank();
+            let (info_kb, kb_size) = sim.get_kb_info();
             let info_mb = sim.get_mb_info();
             let info_dbg = sim.get_dbg_info();
+            let info_fault = sim.get_fault_info();
             let info_instructions = sim.get_cpu_instructions_text();
+            let info_isa = sim.get_isa_info();
+            let info_serial = sim.get_serial_info();
 
             // -----------------------------------------------------------------
             // Surrounding block
@@ -105,7 +120,7 @@ fn run_app<B: Backend>(terminal: &mut Terminal<B>) -> io::Result<()> {
 
             let instruction_container = Layout::default()
                 .direction(Direction::Vertical)
-                .constraints([Constraint::Percentage(100)])
+                .constraints([Constraint::Percentage(50), Constraint::Percentage(50)])
                 .split(wrapper[1]);
 
             // -----------------------------------------------------------------
@@ -115,10 +130,14 @@ fn run_app<B: Backend>(terminal: &mut Terminal<B>) -> io::Result<()> {
                 .constraints([Constraint::Percentage(30), Constraint::Percentage(50)].as_ref())
                 .split(chunks[0]);
 
+            // the screen gets a terminal row per two pixel rows, at full size
+            let screen = sim.mb.find_kind::<Screen>().and_then(|id| sim.mb.device::<Screen>(id));
+            let screen_rows = screen.as_ref().map_or(0, |scr| (scr.height as u16).div_ceil(2));
+
              // split cpu info block
             let cpu_info_blocks = Layout::default()
                 .direction(Direction::Vertical)
-                .constraints([Constraint::Percentage(80), Constraint::Percentage(20)].as_ref())
+                .constraints([Constraint::Min(0), Constraint::Length(screen_rows + 2)].as_ref())
                 .split(top_chunks[0]);
 
             // split cpu block
@@ -153,16 +172,22 @@ fn run_app<B: Backend>(terminal: &mut Terminal<B>) -> io::Result<()> {
 
             // for c in 0..screen.buffer.clone().len() {}
 
-            if let Some(Peripheral::Screen(scr)) = sim.mb.peripherals.get("screen") {
-                // Do buffer stuff here
-                for h in 0..SCREEN_HEIGHT {
+            if let Some(scr) = screen {
+                let frame = scr.frame();
+                let width = scr.width as usize;
+                let rgb = |i: u8| {
+                    let (r, g, b) = PALETTE[i as usize & 0x0f];
+                    Color::Rgb(r, g, b)
+                };
+
+                // the top pixel is the foreground of an upper half block, the bottom one its background
+                for rows in frame.chunks(width * 2) {
                     let mut vga_b:Vec <Span> = Vec::new();
 
-                    for i in 0..SCREEN_WIDTH {
-                        let pos = i + ( SCREEN_HEIGHT * h );
-                        let c_value = scr.buffer[pos as usize];
-                        let color = Color::Rgb(c_value as u8, c_value as u8, c_value as u8);
-                        vga_b.push(Span::styled(FULL, Style::default().fg(color)));
+                    for x in 0..width {
+                        let top = rows[x];
+                        let bottom = rows.get(width + x).copied().unwrap_or(0);
+                        vga_b.push(Span::styled(UPPER_HALF, Style::default().fg(rgb(top)).bg(rgb(bottom))));
                     }
 
                     text.push(Spans::from(vga_b));
@@ -242,7 +267,8 @@ fn run_app<B: Backend>(terminal: &mut Terminal<B>) -> io::Result<()> {
 
             // -----------------------------------------------------------------
             // Bottom left block (RAM)
-            let ram_block = Block::default().title("RAM INFO").borders(Borders::ALL);
+            let ram_title = format!("RAM INFO (bank {} of {})", ram_bank, ram_banks);
+            let ram_block = Block::default().title(ram_title).borders(Borders::ALL);
 
             // ram text input
             let bin_data = info_ram;
@@ -265,6 +291,9 @@ fn run_app<B: Backend>(terminal: &mut Terminal<B>) -> io::Result<()> {
                     color = Color::Yellow;
                 } else if i == (sim.mb.cpu.reg_sp as usize) {
                     color = Color::Magenta;
+                } else if bank.is_some_and(|bank| bank.contains(i)) {
+                    // the banked window
+                    color = Color::LightBlue;
                 }
 
                 x.push(Span::styled(format!("{:02x} ", bin_data[i]), Style::default().fg(color)));
@@ -278,9 +307,14 @@ fn run_app<B: Backend>(terminal: &mut Terminal<B>) -> io::Result<()> {
             // Bottom right block (MB INFO)
 
             // split mb block
+            // the serial console takes some of MB INFO when there is a uart
+            let mb_constraints = match info_serial {
+                Some(_) => vec![Constraint::Percentage(35), Constraint::Percentage(20), Constraint::Percentage(10), Constraint::Percentage(25)],
+                None => vec![Constraint::Percentage(60), Constraint::Percentage(20), Constraint::Percentage(10)],
+            };
             let mb_blocks = Layout::default()
                 .direction(Direction::Vertical)
-                .constraints([Constraint::Percentage(60), Constraint::Percentage(20), Constraint::Percentage(10)].as_ref())
+                .constraints(mb_constraints)
                 .split(bottom_chunks[1]);
 
             let mb_block = Block::default()
@@ -321,32 +355,28 @@ fn run_app<B: Backend>(terminal: &mut Terminal<B>) -> io::Result<()> {
             // text bar
             let text_block = Block::default()
                 .title(Span::styled(
-                    "KEYBOARD INPUT", Style::default().fg(Color::White)
+                    format!("KEYBOARD INPUT {}/{}", info_kb.len(), kb_size), Style::default().fg(Color::White)
                 ))
                 .title_alignment(Alignment::Left)
                 .borders(Borders::ALL);
 
-            // ram kb input
-            let kb_data = info_kb;
-
-            let mut k:Vec <Span> = Vec::new();
-            (0..kb_data.len()).for_each(|i| {
-                if bin_data[i] == 0 {
-                    k.push(Span::styled(format!("{:02x} ", bin_data[i]), Style::default().fg(Color::White)));
-                } else {
-                    k.push(Span::styled(format!("{} ", bin_data[i] as char), Style::default().fg(Color::White)));
-                }
-            });
+            // the keyboard's queue, oldest first
+            let k: Vec<Span> = info_kb
+                .iter()
+                .map(|event| Span::styled(format!("{} ", key_label(event)), Style::default().fg(Color::White)))
+                .collect();
 
             let kb_chars = Paragraph::new(Spans::from(k)).block(text_block).alignment(Alignment::Left).wrap(Wrap { trim : true});
 
             f.render_widget(kb_chars, mb_blocks[1]);
 
             // debug bar
+            let dbg_title = match &info_fault {
+                Some(fault) => Span::styled(format!("DEBUG INFO - FAULT: {}", fault), Style::default().fg(Color::Red)),
+                None => Span::styled("DEBUG INFO", Style::default().fg(Color::White)),
+            };
             let dbg_block = Block::default()
-                .title(Span::styled(
-                    "DEBUG INFO", Style::default().fg(Color::White)
-                ))
+                .title(dbg_title)
                 .title_alignment(Alignment::Left)
                 .borders(Borders::ALL);
 
@@ -357,6 +387,25 @@ fn run_app<B: Backend>(terminal: &mut Terminal<B>) -> io::Result<()> {
 
             f.render_widget(dbg_paragraph, mb_blocks[2]);
 
+            // serial console, the lines that fit
+            if let Some((host, console)) = &info_serial {
+                let serial_block = Block::default()
+                    .title(Span::styled(format!("SERIAL ({})", host), Style::default().fg(Color::White)))
+                    .title_alignment(Alignment::Left)
+                    .borders(Borders::ALL);
+
+                let rows = mb_blocks[3].height.saturating_sub(2) as usize;
+                let lines: Vec<&str> = console.lines().collect();
+                let text: Vec<Spans> = lines[lines.len().saturating_sub(rows)..]
+                    .iter()
+                    .map(|l| Spans::from(Span::styled(*l, Style::default().fg(Color::Green))))
+                    .collect();
+
+                let serial_paragraph = Paragraph::new(text).block(serial_block).alignment(Alignment::Left);
+
+                f.render_widget(serial_paragraph, mb_blocks[3]);
+            }
+
             // -----------------------------------------------------------------
             // Bottom Debug bar
 
@@ -370,8 +419,8 @@ fn run_app<B: Backend>(terminal: &mut Terminal<B>) -> io::Result<()> {
                 let address = d.split(":").collect::<Vec<&str>>();
                 let mut color = Color::White;
 
-                // 15 is the peripheral ram
-                if address[0].parse::<usize>().unwrap() == sim.mb.cpu.reg_iar as usize - 15 {
+                // the listing has program addresses, IAR is a ram address
+                if Debugger::program_address(&sim.mb).map(|a| a as usize) == address[0].parse::<usize>().ok() {
                     color = Color::Cyan;
                 }
 
@@ -384,6 +433,24 @@ fn run_app<B: Backend>(terminal: &mut Terminal<B>) -> io::Result<()> {
                 .wrap(Wrap { trim: true });
 
             f.render_widget(paragraph, instruction_container[0]);
+
+            // ISA help block
+            let isa_block = Block::default().title("ISA HELP").borders(Borders::ALL);
+
+            let mut text = Vec::new();
+            for d in info_isa.iter() {
+                text.push(Spans::from(vec![
+                    Span::styled(format!("{} ", d.0), Style::default().fg(Color::Yellow)),
+                    Span::styled(d.1.clone(), Style::default().fg(Color::White)),
+                ]));
+            }
+
+            let paragraph = Paragraph::new(text)
+                .block(isa_block)
+                .alignment(Alignment::Left)
+                .wrap(Wrap { trim: true });
+
+            f.render_widget(paragraph, instruction_container[1]);
         })?;
 
         sim.mb.process_peripherals();
@@ -397,7 +464,10 @@ fn run_app<B: Backend>(terminal: &mut Terminal<B>) -> io::Result<()> {
                                 MouseButton::Middle => return Ok(()),
                                 MouseButton::Right => sim.reset(),
                                 MouseButton::Left => {
-                                    sim.cycle();
+                                    // a fault the policies halt on leaves the cpu where it is
+                                    if let Err(fault) = sim.cycle() {
+                                        sim.mb.cpu.dbg_msg = format!("CPU halted: {}", fault);
+                                    }
                                     if sim.mb.cpu.clearing {
                                         sim.mb.reset_peripherals()
                                     }
@@ -411,13 +481,34 @@ fn run_app<B: Backend>(terminal: &mut Terminal<B>) -> io::Result<()> {
                     // send any key presses to the peripherals
                     // write the keycode pressed to ram
                     match key.code {
-                        KeyCode::Backspace => sim.mb.pass_to_peripheral("keyboard", 19), //9
-                        KeyCode::Enter => sim.mb.pass_to_peripheral("keyboard", 13), //13
-                        KeyCode::Esc => sim.mb.pass_to_peripheral("keyboard", 46), // 46
-                        KeyCode::Char(c) => {
-                            // get key code ascii
-                            let ascii_c = get_key_code(c);
-                            sim.mb.pass_to_peripheral("keyboard", ascii_c)
+                        KeyCode::Backspace => {
+                            sim.press_key(8, 0);
+                            sim.serial_key(8)
+                        },
+                        KeyCode::Enter => {
+                            sim.press_key(b'\n', 0);
+                            sim.serial_key(b'\n')
+                        },
+                        KeyCode::Tab => sim.press_key(9, 0),
+                        KeyCode::Esc => sim.press_key(27, 0),
+                        KeyCode::Left => sim.step_back(),
+                        KeyCode::Right => sim.micro_step(),
+                        KeyCode::F(2) => sim.toggle_breakpoint(),
+                        KeyCode::F(8) => sim.run_to_break(),
+                        KeyCode::F(5) => sim.save_snapshot(),
+                        KeyCode::F(6) => sim.save_screenshot(),
+                        KeyCode::F(9) => sim.load_snapshot(),
+                        KeyCode::Char(c) if c.is_ascii() => {
+                            // the keyboard gets ctrl and alt too, shift is already in c
+                            let mut modifiers = 0;
+                            if key.modifiers.contains(KeyModifiers::CONTROL) {
+                                modifiers |= MOD_CTRL;
+                            }
+                            if key.modifiers.contains(KeyModifiers::ALT) {
+                                modifiers |= MOD_ALT;
+                            }
+                            sim.press_key(c as u8, modifiers);
+                            sim.serial_key(c as u8)
                         }, // pass to peripheral
                         //
                         _ => { }
@@ -428,3 +519,17 @@ fn run_app<B: Backend>(terminal: &mut Terminal<B>) -> io::Result<()> {
         }
     }
 }
+
+// What the KEYBOARD INPUT block shows for an event: the character it types, else its scancode going down or up
+fn key_label(event: &KeyEvent) -> String {
+    match event.char {
+        0 if event.is_up() => format!("{:02x}↑", event.scancode & 0x7f),
+        0 => format!("{:02x}↓", event.scancode),
+        8 => String::from("BS"),
+        9 => String::from("TAB"),
+        10 => String::from("RET"),
+        27 => String::from("ESC"),
+        c @ 1..=26 => format!("^{}", (c + b'@') as char),
+        c => (c as char).to_string(),
+    }
+}
@@ -1,4 +1,7 @@
-use jcpu::{motherboard::Motherboard};
+use std::path::Path;
+
+use jcpu::{capture::Frame, config::{ConfigError, MachineConfig, PeripheralConfig}, debugger::Debugger, fault::CpuFault, microstep::MicroPhase, keyboard::{KeyEvent, Keyboard}, motherboard::Motherboard, uart::{SerialConfig, Uart}};
+use jcpuinstructions::ISA;
 /*
 
 The basic process here is that we have a motherboard that will power up, reserve some memory for itself (for
@@ -11,14 +14,30 @@ For each cycle of the motherboard, it executes a cycle on the CPU.
 
 pub struct Sim {
     pub mb: Motherboard,
+    // F5 saves the whole machine here and F9 loads it back
+    pub snapshot: String,
+    // F6 saves a picture of the screen here
+    pub screenshot: String,
+    pub debugger: Debugger,
 }
 
 impl Sim {
-    pub fn new() -> Self {
-        Self {
-            // our board and CPU are 8 bits and we want to reserve 10 bytes of ram for ourselves
-            mb: Motherboard::new("./boot.img", "./instructions.d")
+    // Fails when the profile asks for a peripheral that can not be set up
+    pub fn new(bootimg: &str, instructions: &str, mut config: MachineConfig) -> Result<Self, ConfigError> {
+        // the TUI owns the terminal, so a stdio serial port only shows in the SERIAL block
+        for p in config.peripherals.iter_mut() {
+            if let PeripheralConfig::Uart { backend: backend @ SerialConfig::Stdio, .. } = p {
+                *backend = SerialConfig::None;
+            }
         }
+
+        Ok(Self {
+            // our board and CPU are 8 bits, config says where the peripherals' ram and the rest sit
+            mb: Motherboard::with_config(bootimg, instructions, config)?,
+            snapshot: Path::new(bootimg).with_extension("snap").to_string_lossy().to_string(),
+            screenshot: Path::new(bootimg).with_extension("png").to_string_lossy().to_string(),
+            debugger: Debugger::new(),
+        })
     }
     // The next four functions are to display the data from the motherboard and CPU
     pub fn get_cpu_info(&mut self) -> Vec<(String,String)> {
@@ -27,35 +46,113 @@ impl Sim {
     pub fn get_mb_info(&mut self) -> Vec<(String,String)> {
         self.mb.mb_info()
     }
-    pub fn get_screen_info(&mut self) {
-        for p in self.mb.peripherals.iter() {
-
-        }
-    }
     pub fn get_cpu_details(&mut self) -> Vec<(String,String)> {
         self.mb.cpu_info()
     }
     pub fn get_cpu_instructions_text(&mut self) -> Vec<String> {
         self.mb.cpu_instructions()
     }
+    pub fn get_isa_info(&mut self) -> Vec<(String,String)> {
+        ISA.iter().map(|spec| (spec.usage(), spec.description.to_string())).collect()
+    }
     pub fn get_alu_details(&mut self) -> Vec<(String,String)> {
         self.mb.alu_info()
     }
     pub fn get_ram_info(&mut self) -> Vec<u8> {
         self.mb.ram_info().to_vec()
     }
-    pub fn get_kb_info(&mut self) -> Vec<u8> {
-        self.mb.kb_info().to_vec()
+    // the selected bank and how many there are
+    pub fn get_ram_bank(&mut self) -> (u8, usize) {
+        (self.mb.ram.bank, self.mb.ram.bank_count())
+    }
+    // the events waiting in the keyboard's buffer, and how many it holds
+    pub fn get_kb_info(&mut self) -> (Vec<KeyEvent>, usize) {
+        match self.mb.find_kind::<Keyboard>().and_then(|id| self.mb.device::<Keyboard>(id)) {
+            Some(kb) => (kb.events.iter().copied().collect(), kb.size),
+            None => (vec![], 0),
+        }
     }
     pub fn get_dbg_info(&mut self) -> String {
         self.mb.dbg_info()
     }
+    pub fn get_fault_info(&mut self) -> Option<String> {
+        self.mb.fault.map(|fault| fault.to_string())
+    }
+    // typed on the first keyboard on the board, if it has one, with ctrl and alt held as modifiers says
+    pub fn press_key(&mut self, code: u8, modifiers: u8) {
+        if let Some(mut kb) = self.mb.find_kind::<Keyboard>().and_then(|id| self.mb.device_mut::<Keyboard>(id)) {
+            kb.type_key(code, modifiers)
+        }
+        self.mb.ram.sync_bus();
+    }
+    // typed keys go down the serial line too, as plain ascii
+    pub fn serial_key(&mut self, byte: u8) {
+        if let Some(uart) = self.mb.find_kind::<Uart>() {
+            self.mb.input(uart, byte)
+        }
+    }
+    // where the serial port goes and the last of what was sent over it, None without one
+    pub fn get_serial_info(&mut self) -> Option<(String, String)> {
+        let uart = self.mb.find_kind::<Uart>().and_then(|id| self.mb.device::<Uart>(id))?;
+        Some((uart.backend.describe(), String::from_utf8_lossy(&uart.console).to_string()))
+    }
     pub fn start(&mut self) {
         self.mb.boot();
     }
-    pub fn cycle(&mut self) -> bool {
+    pub fn cycle(&mut self) -> Result<bool, CpuFault> {
         self.mb.cycle()
     }
+    // one clock step of the instruction, the debug line says which ran
+    pub fn micro_step(&mut self) {
+        let step = self.mb.phase.name();
+        if let Err(fault) = self.mb.micro_step() {
+            self.mb.cpu.dbg_msg = format!("CPU halted: {}", fault);
+            return;
+        }
+        if self.mb.phase == MicroPhase::Fetch && self.mb.cpu.clearing {
+            self.mb.reset_peripherals()
+        }
+        self.mb.cpu.dbg_msg = format!("Micro step: {}", step);
+    }
+    // F2 sets or clears a breakpoint on the instruction about to run
+    pub fn toggle_breakpoint(&mut self) {
+        self.mb.cpu.dbg_msg = match Debugger::program_address(&self.mb) {
+            Some(address) if self.debugger.toggle_breakpoint(address) => format!("Breakpoint set at {}", address),
+            Some(address) => format!("Breakpoint cleared at {}", address),
+            None => String::from("IAR is outside the program"),
+        };
+    }
+    // F8 runs until something stops it
+    pub fn run_to_break(&mut self) {
+        let stop = self.debugger.run_until_break(&mut self.mb, 10_000);
+        self.mb.cpu.dbg_msg = format!("Stopped: {}", stop);
+    }
+    // undo the last cycle
+    pub fn step_back(&mut self) {
+        self.mb.cpu.dbg_msg = match self.mb.step_back() {
+            Some(undone) => format!("Stepped back to cycle {}", undone.cycle),
+            None => String::from("Nothing to step back to"),
+        };
+    }
+    pub fn save_snapshot(&mut self) {
+        self.mb.cpu.dbg_msg = match self.mb.save_snapshot_file(&self.snapshot) {
+            Ok(()) => format!("Saved snapshot to {}", self.snapshot),
+            Err(e) => format!("Snapshot failed: {}", e),
+        };
+    }
+    pub fn load_snapshot(&mut self) {
+        self.mb.cpu.dbg_msg = match self.mb.load_snapshot_file(&self.snapshot) {
+            Ok(()) => format!("Loaded snapshot from {}", self.snapshot),
+            Err(e) => format!("Snapshot failed: {}", e),
+        };
+    }
+    // blown up 4 times, 32 by 24 pixels is hard to make out
+    pub fn save_screenshot(&mut self) {
+        self.mb.cpu.dbg_msg = match Frame::of(&self.mb).and_then(|frame| frame.save(&self.screenshot, 4)) {
+            Ok(()) => format!("Saved screenshot to {}", self.screenshot),
+            Err(e) => format!("Screenshot failed: {}", e),
+        };
+    }
     pub fn reset(&mut self) {
         self.mb.reset();
     }
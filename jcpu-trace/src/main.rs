@@ -0,0 +1,250 @@
+/*
+    Tools for the execution traces jcpu-run --trace writes.
+
+    jcpu-trace diff a.trace b.trace [--ignore FIELD]...
+
+    Finds the first record where two traces differ and says which fields did. Text
+    and json lines traces can be mixed, both are read into the same fields:
+    cycle, iar, bytes, instruction, registers, writes and fault. Exits 0 when the
+    traces match, 1 when they differ and 2 when they can not be read.
+*/
+
+use std::{collections::BTreeMap, fs, process};
+
+use serde_json::Value;
+
+const USAGE: &str = "usage: jcpu-trace diff A B [--ignore cycle|iar|bytes|instruction|registers|writes|fault]...";
+const FIELDS: [&str; 7] = ["cycle", "iar", "bytes", "instruction", "registers", "writes", "fault"];
+
+type Record = BTreeMap<&'static str, String>;
+
+fn main() {
+    let mut args = std::env::args().skip(1);
+
+    match args.next().as_deref() {
+        Some("diff") => {},
+        _ => fail(USAGE),
+    }
+
+    let mut files = vec![];
+    let mut ignore = vec![];
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--ignore" => match args.next() {
+                Some(field) if FIELDS.contains(&field.as_str()) => ignore.push(field),
+                _ => fail(USAGE),
+            },
+            _ => files.push(arg),
+        }
+    }
+    if files.len() != 2 {
+        fail(USAGE);
+    }
+
+    let a = read_trace(&files[0]);
+    let b = read_trace(&files[1]);
+
+    match diff(&files, &a, &b, &ignore) {
+        Some(report) => {
+            println!("{}", report);
+            process::exit(1);
+        },
+        None => println!("traces match ({} records)", a.len()),
+    }
+}
+
+// What the first difference is, None when the traces match
+fn diff(files: &[String], a: &[String], b: &[String], ignore: &[String]) -> Option<String> {
+    for (i, (line_a, line_b)) in a.iter().zip(b.iter()).enumerate() {
+        let (record_a, record_b) = (parse(line_a, &files[0], i), parse(line_b, &files[1], i));
+        let differs: Vec<&str> = FIELDS
+            .iter()
+            .filter(|f| !ignore.iter().any(|i| i == *f))
+            .filter(|f| record_a.get(*f) != record_b.get(*f))
+            .copied()
+            .collect();
+
+        if !differs.is_empty() {
+            return Some(format!("first divergence at record {}\n  a: {}\n  b: {}\n  differs: {}", i, line_a, line_b, differs.join(", ")));
+        }
+    }
+
+    if a.len() != b.len() {
+        let (shorter, longer, rest) = if a.len() < b.len() { ("a", "b", &b[a.len()]) } else { ("b", "a", &a[b.len()]) };
+        return Some(format!("{} ends after {} records, {} goes on\n  {}: {}", shorter, a.len().min(b.len()), longer, longer, rest));
+    }
+
+    None
+}
+
+fn read_trace(path: &str) -> Vec<String> {
+    match fs::read_to_string(path) {
+        Ok(trace) => trace.lines().filter(|l| !l.trim().is_empty()).map(|l| l.to_string()).collect(),
+        Err(e) => fail(&format!("failed to read {}: {}", path, e)),
+    }
+}
+
+fn parse(line: &str, path: &str, index: usize) -> Record {
+    let record = if line.starts_with('{') { parse_json(line) } else { parse_text(line) };
+
+    match record {
+        Some(record) => record,
+        None => fail(&format!("{}: record {} is not a trace record", path, index)),
+    }
+}
+
+//  cycle iar bytes instruction... R1=00->0e [248]=0e fault: ...
+fn parse_text(line: &str) -> Option<Record> {
+    let (line, fault) = match line.split_once(" fault: ") {
+        Some((line, fault)) => (line, fault.to_string()),
+        None => (line, String::new()),
+    };
+    let mut tokens = line.split_whitespace();
+
+    let mut record = Record::new();
+    record.insert("cycle", tokens.next()?.parse::<usize>().ok()?.to_string());
+    let iar = tokens.next()?;
+    record.insert("iar", if iar == "---" { String::new() } else { iar.parse::<u8>().ok()?.to_string() });
+    let rest: Vec<&str> = tokens.collect();
+
+    // a cycle that faulted before the fetch has no bytes or instruction
+    let (bytes, rest) = match (iar, rest.split_first()) {
+        ("---", _) | (_, None) => ("", &rest[..]),
+        (_, Some((bytes, rest))) => (*bytes, rest),
+    };
+    record.insert("bytes", bytes.to_string());
+
+    let changes = rest.iter().position(|t| t.contains('=')).unwrap_or(rest.len());
+    record.insert("instruction", rest[..changes].join(" "));
+    let mut registers: Vec<&str> = rest[changes..].iter().filter(|t| !t.starts_with('[')).copied().collect();
+    // json objects come back sorted by name, so sort these the same way
+    registers.sort();
+    record.insert("registers", registers.join(" "));
+    record.insert("writes", rest[changes..].iter().filter(|t| t.starts_with('[')).copied().collect::<Vec<_>>().join(" "));
+    record.insert("fault", fault);
+
+    Some(record)
+}
+
+// the same fields, written the way the text format has them
+fn parse_json(line: &str) -> Option<Record> {
+    let json: Value = serde_json::from_str(line).ok()?;
+
+    let mut record = Record::new();
+    record.insert("cycle", json["cycle"].as_u64()?.to_string());
+    record.insert("iar", json["iar"].as_u64().map_or(String::new(), |iar| iar.to_string()));
+    record.insert("bytes", json["bytes"].as_str()?.to_string());
+    record.insert("instruction", json["instruction"].as_str()?.to_string());
+
+    let registers: Option<Vec<String>> = json["registers"]
+        .as_object()?
+        .iter()
+        .map(|(name, change)| Some(format!("{}={:02x}->{:02x}", name, change[0].as_u64()?, change[1].as_u64()?)))
+        .collect();
+    record.insert("registers", registers?.join(" "));
+
+    let writes: Option<Vec<String>> = json["writes"]
+        .as_array()?
+        .iter()
+        .map(|write| Some(format!("[{}]={:02x}", write[0].as_u64()?, write[1].as_u64()?)))
+        .collect();
+    record.insert("writes", writes?.join(" "));
+
+    record.insert("fault", json["fault"].as_str().unwrap_or("").to_string());
+
+    Some(record)
+}
+
+fn fail(msg: &str) -> ! {
+    eprintln!("{}", msg);
+    process::exit(2);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use jcpu::{fault::CpuFault, tracer::TraceRecord};
+
+    fn record(cycle: usize, instruction: &str, registers: Vec<(&'static str, u8, u8)>, fault: Option<CpuFault>) -> TraceRecord {
+        TraceRecord { cycle, iar: Some(15 + cycle as u8), bytes: vec![0x20, 0x05], instruction: instruction.to_string(), registers, writes: vec![(248, 14)], fault }
+    }
+
+    fn trace() -> Vec<TraceRecord> {
+        vec![
+            record(0, "DATA R1, 5", vec![("R1", 0, 5)], None),
+            record(1, "PUSH R1", vec![("SP", 0xc7, 0xc6), ("R1", 5, 5)], None),
+            TraceRecord { cycle: 2, iar: None, bytes: vec![], instruction: String::new(), registers: vec![], writes: vec![], fault: Some(CpuFault::AddressOutOfRange(255)) },
+        ]
+    }
+
+    fn text(records: &[TraceRecord]) -> Vec<String> {
+        records.iter().map(|r| r.text()).collect()
+    }
+
+    fn json(records: &[TraceRecord]) -> Vec<String> {
+        records.iter().map(|r| r.json()).collect()
+    }
+
+    fn compare(a: &[String], b: &[String], ignore: &[&str]) -> Option<String> {
+        let files = [String::from("a"), String::from("b")];
+        let ignore: Vec<String> = ignore.iter().map(|f| f.to_string()).collect();
+        diff(&files, a, b, &ignore)
+    }
+
+    #[test]
+    fn text_and_json_read_into_the_same_fields() {
+        let records = trace();
+
+        for (text, json) in text(&records).iter().zip(json(&records).iter()) {
+            assert_eq!(parse_text(text), parse_json(json), "{} / {}", text, json);
+        }
+        assert_eq!(compare(&text(&records), &json(&records), &[]), None);
+    }
+
+    #[test]
+    fn the_first_divergence_names_the_fields() {
+        let a = trace();
+        let mut b = trace();
+        b[1].registers[0].2 = 0xc5;
+        b[1].writes.clear();
+        b[2].fault = Some(CpuFault::StackOverflow);
+
+        let report = compare(&text(&a), &json(&b), &[]).unwrap();
+        assert!(report.starts_with("first divergence at record 1\n"), "{}", report);
+        assert!(report.ends_with("differs: registers, writes"), "{}", report);
+
+        let report = compare(&text(&a), &json(&b), &["registers", "writes"]).unwrap();
+        assert!(report.starts_with("first divergence at record 2\n"), "{}", report);
+        assert!(report.ends_with("differs: fault"), "{}", report);
+    }
+
+    #[test]
+    fn ignored_fields_do_not_count() {
+        let a = trace();
+        let mut b = trace();
+        for record in b.iter_mut() {
+            record.cycle += 100;
+        }
+
+        assert!(compare(&json(&a), &json(&b), &[]).unwrap().ends_with("differs: cycle"));
+        assert_eq!(compare(&json(&a), &json(&b), &["cycle"]), None);
+    }
+
+    #[test]
+    fn a_trace_that_stops_early_differs() {
+        let a = text(&trace());
+
+        let report = compare(&a[..2], &a, &[]).unwrap();
+        assert_eq!(report, format!("a ends after 2 records, b goes on\n  b: {}", a[2]));
+        assert!(compare(&a, &a[..1], &[]).unwrap().starts_with("b ends after 1 records, a goes on"));
+    }
+
+    #[test]
+    fn json_strings_are_escaped() {
+        let mut record = trace().remove(0);
+        record.instruction = String::from("DATA R1, \"5\"\\");
+
+        let fields = parse_json(&record.json()).unwrap();
+        assert_eq!(fields["instruction"], record.instruction);
+    }
+}
@@ -1,14 +1,13 @@
 use jcpuinstructions::JumpFlag;
 
-const INT: u8          = 0b10000000;  // 0x80
-pub const REG_A_ISREG: u8  = 0b01000000;  // 0x40
-pub const REG_B_ISREG: u8  = 0b00100000;  // 0x20
-const FLAG_LT: u8      = 0b00010000;  // 0x10
+pub const FLAG_INT: u8 = 0b10000000;  // 0x80
+const FLAG_GT: u8      = 0b00010000;  // 0x10
 const FLAG_EQ: u8      = 0b00001000;  // 0x8
 const FLAG_Z: u8       = 0b00000100;  // 0x4
 const FLAG_SIGN: u8    = 0b00000010;  // 0x2
 const FLAG_CARRY: u8   = 0b00000001;  // 0x1
 
+#[derive(Clone, PartialEq, Eq)]
 pub struct ALU {
     pub A: u8,
     pub B: u8,
@@ -20,7 +19,7 @@ pub struct ALU {
     pub Shr: u8,
     pub Sum: u8,
     //   0  0  0  0  0 0 0 0
-    // INT|R1|R2|LT|EQ|Z|S|C
+    // INT| -| -|GT|EQ|Z|S|C
     pub flags: u8
 }
 
@@ -38,119 +37,155 @@ impl ALU {
 
         self.check_sign_and_carry(res);
 
-        (res % 255) as u8
+        self.Sum = wrap(res);
+        self.Sum
     }
 
     pub fn op_sub(&mut self) -> u8 {
-        let mut res = self.A as isize - self.B as isize;
+        let res = self.A as isize - self.B as isize;
 
         self.check_sign_and_carry(res);
 
-        if res < 0 {
-            res = 0;
-        }
-
-        (res % 255) as u8
+        self.Sum = wrap(res);
+        self.Sum
     }
 
     pub fn op_inc(&mut self) -> u8 {
-        let mut res = self.A as isize + 1;
+        let res = self.A as isize + 1;
 
         self.check_sign_and_carry(res);
 
-        if res < 0 {
-            res = 0;
-        }
-
-        (res % 255) as u8
+        self.Sum = wrap(res);
+        self.Sum
     }
 
     pub fn op_dec(&mut self) -> u8 {
-        let mut res = self.A as isize - 1;
+        let res = self.A as isize - 1;
         //println!("OPDEC: res[{}]", res);
 
         self.check_sign_and_carry(res);
 
-        if res < 0 {
-            res = 0;
-        }
+        self.Sum = wrap(res);
+        self.Sum
+    }
+
+    pub fn op_and(&mut self) -> u8 {
+        self.Sum = self.A & self.B;
+        self.check_sign_and_carry(self.Sum as isize);
+
+        self.Sum
+    }
+
+    pub fn op_or(&mut self) -> u8 {
+        self.Sum = self.A | self.B;
+        self.check_sign_and_carry(self.Sum as isize);
+
+        self.Sum
+    }
+
+    pub fn op_xor(&mut self) -> u8 {
+        self.Sum = self.A ^ self.B;
+        self.check_sign_and_carry(self.Sum as isize);
+
+        self.Sum
+    }
+
+    pub fn op_not(&mut self) -> u8 {
+        self.Sum = !self.A;
+        self.check_sign_and_carry(self.Sum as isize);
 
-        (res % 255) as u8
+        self.Sum
+    }
+
+    // shifting by 8 or more moves every bit out, the bits that fall off the top set carry
+    pub fn op_shl(&mut self) -> u8 {
+        let res = (self.A as isize) << self.B.min(8);
+
+        self.check_sign_and_carry(res);
+
+        self.Sum = wrap(res);
+        self.Sum
+    }
+
+    pub fn op_shr(&mut self) -> u8 {
+        let res = (self.A as isize) >> self.B.min(8);
+
+        self.check_sign_and_carry(res);
+
+        self.Sum = wrap(res);
+        self.Sum
     }
 
     pub fn flags(&mut self) {
         self.Or = self.A | self.B;
         self.And = self.A & self.B;
         self.Not = !self.A;
-        self.Shl = self.A << self.B;
-        self.Shr = self.A >> self.B;
+        self.Shl = self.A.checked_shl(self.B as u32).unwrap_or(0);
+        self.Shr = self.A.checked_shr(self.B as u32).unwrap_or(0);
         //self.Sum = self.A + self.B;
 
-        if self.A < self.B {
-            self.flags |= FLAG_Z
-        } else {
-            self.flags ^= FLAG_CARRY;
-        };
+        self.flags &= !(FLAG_EQ | FLAG_GT);
 
         if self.A == self.B {
             self.flags |= FLAG_EQ
-        } else {
-            self.flags ^= FLAG_EQ
         }
 
-        if self.A < self.B {
-            self.flags |= FLAG_LT
-        } else {
-            self.flags ^= FLAG_LT
+        if self.A > self.B {
+            self.flags |= FLAG_GT
         }
     }
 
+    // sets C, S and Z from the full result of an op, before it is cut down to 8 bits
     fn check_sign_and_carry(&mut self, num: isize) {
-        if num > 127  || num < -128 {
-            println!("setting carry");
+        self.flags &= !(FLAG_CARRY | FLAG_SIGN | FLAG_Z);
+
+        // unsigned overflow on the way up or a borrow on the way down
+        if !(0..=255).contains(&num) {
             self.flags |= FLAG_CARRY
         }
 
         if num < 0 {
-            println!("setting sign");
             self.flags |= FLAG_SIGN
         }
+
+        if num == 0 {
+            self.flags |= FLAG_Z
+        }
     }
 
+    // The C,A,E,Z condition bits in the same order as the JumpFlag nibble
+    pub fn conditions(&self) -> u8 {
+        let mut conditions = 0;
 
-    pub fn match_flags(&mut self, flags: u8) -> bool {
+        if self.flags & FLAG_CARRY > 0 {
+            conditions |= JumpFlag::C as u8;
+        }
+        if self.flags & FLAG_GT > 0 {
+            conditions |= JumpFlag::A as u8;
+        }
+        if self.flags & FLAG_EQ > 0 {
+            conditions |= JumpFlag::E as u8;
+        }
+        if self.flags & FLAG_Z > 0 {
+            conditions |= JumpFlag::Z as u8;
+        }
+
+        conditions
+    }
 
+
+    // A JMPIF jumps when any of the conditions it names is set, so JMPIFEZ
+    // jumps on a = b or a zero result. CF names none and jumps on carry, the same as C.
+    pub fn match_flags(&mut self, flags: u8) -> bool {
         if flags == JumpFlag::CF as u8 {
-            if self.flags & FLAG_CARRY > 0 {
-                return true;
-            } else {
-                return false;
-            };
-        };
-
-        if flags == JumpFlag::Z as u8 {
-            if self.flags & FLAG_Z > 0 {
-                return true;
-            } else {
-                return false;
-            };
-        };
-
-        false
-        // if flags == JumpFlag::E as u8 {};
-        // if flags == JumpFlag::EZ as u8 {};
-        // if flags == JumpFlag::A as u8 {};
-        // if flags == JumpFlag::AZ as u8 {};
-        // if flags == JumpFlag::AE as u8 {};
-        // if flags == JumpFlag::AEZ as u8 {};
-        // if flags == JumpFlag::C as u8 {};
-        // if flags == JumpFlag::CZ as u8 {};
-        // if flags == JumpFlag::CE as u8 {};
-        // if flags == JumpFlag::CEZ as u8 {};
-        // if flags == JumpFlag::CA as u8 {};
-        // if flags == JumpFlag::CAZ as u8 {};
-        // if flags == JumpFlag::CAE as u8 {};
-        // if flags == JumpFlag::CAEZ as u8 {};
+            return self.flags & FLAG_CARRY > 0;
+        }
 
+        flags & self.conditions() > 0
     }
 }
+
+// Every result is cut down to 8 bits the same way, wrapping round at 256 (carry says when it did)
+fn wrap(res: isize) -> u8 {
+    (res & 0xFF) as u8
+}
@@ -0,0 +1,44 @@
+use crate::device::{AttachError, SharedDevice};
+
+/*
+    Memory mapped I/O. A device claims a range of addresses and Ram hands every
+    read and write in that range to it instead of to memory, so LD and ST talk to
+    the device. The device sees offsets from the start of its range.
+
+    Ram keeps a copy of the mapped bytes in `memory` (refreshed with mmio_peek after
+    every access) so the ram views, dumps and snapshots still show the registers.
+*/
+
+#[derive(Clone)]
+pub struct Mapping {
+    pub name: &'static str,
+    pub start: usize,
+    // exclusive
+    pub end: usize,
+    pub device: SharedDevice,
+}
+
+#[derive(Clone, Default)]
+pub struct Bus {
+    pub mappings: Vec<Mapping>,
+}
+
+impl Bus {
+    pub fn map(&mut self, name: &'static str, start: usize, len: usize, device: SharedDevice) -> Result<(), AttachError> {
+        let end = start + len;
+        if let Some(other) = self.mappings.iter().find(|m| start < m.end && m.start < end) {
+            return Err(AttachError::Overlap { name, range: start..end, other: other.name, other_range: other.start..other.end });
+        }
+
+        self.mappings.push(Mapping { name, start, end, device });
+        Ok(())
+    }
+
+    pub fn unmap(&mut self, name: &str) {
+        self.mappings.retain(|m| m.name != name);
+    }
+
+    pub fn mapping(&self, address: u8) -> Option<&Mapping> {
+        self.mappings.iter().find(|m| (m.start..m.end).contains(&(address as usize)))
+    }
+}
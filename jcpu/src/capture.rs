@@ -0,0 +1,250 @@
+use std::{borrow::Cow, fmt, fs, path::Path};
+
+use crate::{
+    motherboard::Motherboard,
+    peripheral::{Screen, PALETTE},
+};
+
+/*
+    Pictures of the screen. A Frame is what the screen showed on one cycle, and saves
+    as a PPM, PNG or one frame GIF, picked by the file's extension. A Recorder keeps
+    frames while a program runs, either every frame the screen draws (once every
+    `refresh` cycles) or whenever the picture changes, and writes them out as numbered
+    images or as one animated GIF:
+
+        let mut recorder = Recorder::new(CaptureMode::Change);
+        while debugger.step(&mut mb).is_none() {
+            recorder.capture(&mb);
+        }
+        recorder.save_gif("demo.gif", 10, 4)?;
+
+    Every image uses PALETTE, so the same picture always gives the same bytes and
+    frames can be compared file by file. `scale` blows each pixel up to a square of
+    that many, a 32 by 24 screen is hard to see otherwise.
+*/
+
+#[derive(Debug)]
+pub enum CaptureError {
+    Io(String),
+    // the extension is not one we can write
+    UnknownFormat(String),
+    Encode(String),
+    NoScreen,
+    NoFrames,
+}
+
+impl fmt::Display for CaptureError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            CaptureError::Io(e) => write!(f, "{}", e),
+            CaptureError::UnknownFormat(path) => write!(f, "can not tell the image format of {} (use .ppm, .png or .gif)", path),
+            CaptureError::Encode(e) => write!(f, "failed to encode the image: {}", e),
+            CaptureError::NoScreen => write!(f, "the machine has no screen"),
+            CaptureError::NoFrames => write!(f, "no frames were captured"),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ImageFormat {
+    Ppm,
+    Png,
+    Gif,
+}
+
+impl ImageFormat {
+    pub fn from_path(path: &str) -> Result<Self, CaptureError> {
+        let extension = Path::new(path).extension().map(|e| e.to_string_lossy().to_lowercase());
+        match extension.as_deref() {
+            Some("ppm") => Ok(ImageFormat::Ppm),
+            Some("png") => Ok(ImageFormat::Png),
+            Some("gif") => Ok(ImageFormat::Gif),
+            _ => Err(CaptureError::UnknownFormat(path.to_string())),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Frame {
+    pub width: usize,
+    pub height: usize,
+    // palette indexes, row by row
+    pub pixels: Vec<u8>,
+    // the motherboard cycle it was taken on
+    pub cycle: usize,
+}
+
+impl Frame {
+    pub fn from_screen(screen: &Screen, cycle: usize) -> Self {
+        Self { width: screen.width as usize, height: screen.height as usize, pixels: screen.frame(), cycle }
+    }
+
+    // the first screen on the board
+    pub fn of(mb: &Motherboard) -> Result<Self, CaptureError> {
+        let screen = mb.find_kind::<Screen>().and_then(|id| mb.device::<Screen>(id)).ok_or(CaptureError::NoScreen)?;
+        Ok(Self::from_screen(&screen, mb.cycle_i))
+    }
+
+    pub fn scaled(&self, scale: usize) -> Frame {
+        let scale = scale.max(1);
+        let mut pixels = Vec::with_capacity(self.pixels.len() * scale * scale);
+        for row in self.pixels.chunks(self.width.max(1)) {
+            let row: Vec<u8> = row.iter().flat_map(|p| std::iter::repeat_n(*p, scale)).collect();
+            for _ in 0..scale {
+                pixels.extend_from_slice(&row);
+            }
+        }
+
+        Frame { width: self.width * scale, height: self.height * scale, pixels, cycle: self.cycle }
+    }
+
+    // three bytes a pixel
+    pub fn rgb(&self) -> Vec<u8> {
+        self.pixels.iter().flat_map(|p| {
+            let (r, g, b) = PALETTE[*p as usize & 0x0f];
+            [r, g, b]
+        }).collect()
+    }
+
+    pub fn ppm(&self, scale: usize) -> Vec<u8> {
+        let frame = self.scaled(scale);
+        let mut bytes = format!("P6\n{} {}\n255\n", frame.width, frame.height).into_bytes();
+        bytes.extend(frame.rgb());
+        bytes
+    }
+
+    // an indexed PNG with PALETTE as its palette
+    pub fn png(&self, scale: usize) -> Result<Vec<u8>, CaptureError> {
+        let frame = self.scaled(scale);
+        let mut bytes = vec![];
+
+        let mut encoder = png::Encoder::new(&mut bytes, frame.width as u32, frame.height as u32);
+        encoder.set_color(png::ColorType::Indexed);
+        encoder.set_depth(png::BitDepth::Eight);
+        encoder.set_palette(palette());
+        let mut writer = encoder.write_header().map_err(encode_error)?;
+        writer.write_image_data(&frame.pixels).map_err(encode_error)?;
+        writer.finish().map_err(encode_error)?;
+
+        Ok(bytes)
+    }
+
+    pub fn encode(&self, format: ImageFormat, scale: usize) -> Result<Vec<u8>, CaptureError> {
+        match format {
+            ImageFormat::Ppm => Ok(self.ppm(scale)),
+            ImageFormat::Png => self.png(scale),
+            ImageFormat::Gif => gif(std::slice::from_ref(self), 0, scale),
+        }
+    }
+
+    pub fn save(&self, path: &str, scale: usize) -> Result<(), CaptureError> {
+        let bytes = self.encode(ImageFormat::from_path(path)?, scale)?;
+        write(path, &bytes)
+    }
+}
+
+// An animated GIF that shows each frame for delay hundredths of a second and loops
+pub fn gif(frames: &[Frame], delay: u16, scale: usize) -> Result<Vec<u8>, CaptureError> {
+    let first = frames.first().ok_or(CaptureError::NoFrames)?.scaled(scale);
+    let (width, height) = match (u16::try_from(first.width), u16::try_from(first.height)) {
+        (Ok(width), Ok(height)) => (width, height),
+        _ => return Err(CaptureError::Encode(format!("{} by {} is too big for a GIF", first.width, first.height))),
+    };
+
+    let mut bytes = vec![];
+    {
+        let mut encoder = gif::Encoder::new(&mut bytes, width, height, &palette()).map_err(encode_error)?;
+        encoder.set_repeat(gif::Repeat::Infinite).map_err(encode_error)?;
+
+        for frame in frames {
+            let frame = frame.scaled(scale);
+            if (frame.width, frame.height) != (first.width, first.height) {
+                return Err(CaptureError::Encode(String::from("every frame of a GIF has to be the same size")));
+            }
+
+            let gif_frame = gif::Frame { width, height, delay, buffer: Cow::Owned(frame.pixels), ..gif::Frame::default() };
+            encoder.write_frame(&gif_frame).map_err(encode_error)?;
+        }
+    }
+
+    Ok(bytes)
+}
+
+// When a Recorder keeps a frame
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CaptureMode {
+    // each one the screen draws
+    Frame,
+    // whenever the picture is not the last one kept
+    Change,
+}
+
+pub struct Recorder {
+    pub mode: CaptureMode,
+    pub frames: Vec<Frame>,
+    // how many frames the screen had drawn at the last capture
+    drawn: Option<usize>,
+}
+
+impl Recorder {
+    pub fn new(mode: CaptureMode) -> Self {
+        Self { mode, frames: vec![], drawn: None }
+    }
+
+    // Call after every cycle, true when it kept a frame. Boards without a screen keep nothing.
+    pub fn capture(&mut self, mb: &Motherboard) -> bool {
+        let screen = match mb.find_kind::<Screen>().and_then(|id| mb.device::<Screen>(id)) {
+            Some(screen) => screen,
+            None => return false,
+        };
+
+        let drawn = self.drawn.replace(screen.frames);
+        let frame = Frame::from_screen(&screen, mb.cycle_i);
+        let keep = match self.mode {
+            CaptureMode::Frame => drawn != Some(screen.frames),
+            CaptureMode::Change => self.frames.last().is_none_or(|last| last.pixels != frame.pixels),
+        };
+
+        if keep {
+            self.frames.push(frame);
+        }
+        keep
+    }
+
+    // Saves frame i as path with _i before its extension (shot.png gives shot_0000.png, ...), returns the paths
+    pub fn save_frames(&self, path: &str, scale: usize) -> Result<Vec<String>, CaptureError> {
+        let format = ImageFormat::from_path(path)?;
+        let path = Path::new(path);
+        let stem = path.file_stem().map(|s| s.to_string_lossy().to_string()).unwrap_or_default();
+        let extension = path.extension().map(|e| e.to_string_lossy().to_string()).unwrap_or_default();
+
+        let mut saved = vec![];
+        for (i, frame) in self.frames.iter().enumerate() {
+            let name = path.with_file_name(format!("{}_{:04}.{}", stem, i, extension)).to_string_lossy().to_string();
+            write(&name, &frame.encode(format, scale)?)?;
+            saved.push(name);
+        }
+
+        Ok(saved)
+    }
+
+    pub fn gif(&self, delay: u16, scale: usize) -> Result<Vec<u8>, CaptureError> {
+        gif(&self.frames, delay, scale)
+    }
+
+    pub fn save_gif(&self, path: &str, delay: u16, scale: usize) -> Result<(), CaptureError> {
+        write(path, &self.gif(delay, scale)?)
+    }
+}
+
+fn palette() -> Vec<u8> {
+    PALETTE.iter().flat_map(|(r, g, b)| [*r, *g, *b]).collect()
+}
+
+fn encode_error(e: impl fmt::Display) -> CaptureError {
+    CaptureError::Encode(e.to_string())
+}
+
+fn write(path: &str, bytes: &[u8]) -> Result<(), CaptureError> {
+    fs::write(path, bytes).map_err(|e| CaptureError::Io(format!("failed to write {}: {}", path, e)))
+}
@@ -0,0 +1,399 @@
+use std::{fmt, fs, path::Path};
+
+use serde::{Deserialize, Serialize};
+
+use crate::{interrupts::IRQ_LINES, uart::SerialConfig};
+
+/*
+    The board layout, loaded from a TOML or JSON profile (the extension picks which).
+    Every key is optional, whatever is left out keeps the original board:
+
+        ram_size = 255
+
+        [map]
+        boot = 15         # the boot image is loaded here, program addresses count from it
+        program = 112     # bytes of the image that sit at boot, the rest goes into the banks
+                          # (120 on the default board, this leaves room for the timer and uart)
+        fault = 13        # a trapped fault leaves its code here
+        ivt = 247         # the interrupt vector table, one byte per IRQ line
+
+        [map.bank]
+        address = 135
+        size = 64
+        count = 4
+
+        [map.stack]
+        base = 199        # SP starts here and moves toward limit, neither byte is pushed to
+        limit = 247
+        direction = "up"  # or "down"
+
+        [[peripherals]]
+        kind = "keyboard"
+        address = 0
+        size = 7          # its registers, at least 6
+        buffer = 16       # key events waiting to be read
+
+        [[peripherals]]
+        kind = "screen"
+        address = 7
+        size = 6          # its registers, at least 6
+        width = 32
+        height = 24
+        refresh = 64      # cycles per frame, how often vsync is set
+
+        [[peripherals]]   # not on the default board
+        kind = "timer"
+        address = 127
+        size = 5          # its registers, at least 5
+
+        [[peripherals]]   # not on the default board either
+        kind = "uart"
+        address = 132
+        size = 3          # its registers, at least 3
+        fifo = 16         # received bytes waiting to be read
+        backend = "stdio" # "none" (the default), "pty" or { file = { tx = "out.txt", rx = "in.txt" } }
+
+    The CPU addresses ram with one byte, so ram_size is at most 256. Every region has
+    to fit in ram and none may overlap, load checks that before handing the profile back.
+*/
+
+#[derive(Debug)]
+pub enum ConfigError {
+    Io(String),
+    Parse(String),
+    Invalid(String),
+    // a peripheral the profile asks for could not be set up, like a uart backend that fails to open
+    Device(String),
+}
+
+impl fmt::Display for ConfigError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ConfigError::Io(e) => write!(f, "{}", e),
+            ConfigError::Parse(e) => write!(f, "bad machine profile: {}", e),
+            ConfigError::Invalid(what) => write!(f, "invalid machine profile: {}", what),
+            ConfigError::Device(e) => write!(f, "{}", e),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum StackDirection {
+    Up,
+    Down,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct BankConfig {
+    pub address: usize,
+    pub size: usize,
+    pub count: usize,
+}
+
+impl Default for BankConfig {
+    fn default() -> Self {
+        Self { address: 135, size: 64, count: 4 }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct StackConfig {
+    pub base: usize,
+    pub limit: usize,
+    pub direction: StackDirection,
+}
+
+impl Default for StackConfig {
+    fn default() -> Self {
+        Self { base: 199, limit: 247, direction: StackDirection::Up }
+    }
+}
+
+// The addresses the CPU and ram work from, small enough to copy around
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct MemoryMap {
+    pub boot: usize,
+    pub program: usize,
+    pub fault: usize,
+    pub ivt: usize,
+    pub bank: BankConfig,
+    pub stack: StackConfig,
+}
+
+impl Default for MemoryMap {
+    fn default() -> Self {
+        Self {
+            boot: 15,
+            program: 10 * 12,
+            fault: 13,
+            ivt: 255 - IRQ_LINES,
+            bank: BankConfig::default(),
+            stack: StackConfig::default(),
+        }
+    }
+}
+
+impl MemoryMap {
+    // the program address of a ram address, None below boot
+    pub fn program_address(&self, address: u8) -> Option<u8> {
+        (address as usize).checked_sub(self.boot).map(|a| a as u8)
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(tag = "kind", rename_all = "lowercase", deny_unknown_fields)]
+pub enum PeripheralConfig {
+    Keyboard {
+        address: usize,
+        size: usize,
+        #[serde(default = "default_buffer")]
+        buffer: usize,
+    },
+    Screen {
+        address: usize,
+        size: usize,
+        width: u8,
+        height: u8,
+        #[serde(default = "default_refresh")]
+        refresh: usize,
+    },
+    Timer { address: usize, size: usize },
+    Uart {
+        address: usize,
+        size: usize,
+        #[serde(default = "default_fifo")]
+        fifo: usize,
+        #[serde(default)]
+        backend: SerialConfig,
+    },
+}
+
+fn default_refresh() -> usize {
+    64
+}
+
+fn default_buffer() -> usize {
+    16
+}
+
+fn default_fifo() -> usize {
+    16
+}
+
+impl PeripheralConfig {
+    // the name it is plugged into the motherboard under
+    pub fn name(&self) -> &'static str {
+        match self {
+            PeripheralConfig::Keyboard { .. } => "keyboard",
+            PeripheralConfig::Screen { .. } => "screen",
+            PeripheralConfig::Timer { .. } => "timer",
+            PeripheralConfig::Uart { .. } => "uart",
+        }
+    }
+
+    pub fn region(&self) -> Region {
+        match self {
+            PeripheralConfig::Keyboard { address, size, .. }
+            | PeripheralConfig::Screen { address, size, .. }
+            | PeripheralConfig::Timer { address, size }
+            | PeripheralConfig::Uart { address, size, .. } => Region::new(self.name(), *address, *size),
+        }
+    }
+}
+
+// A named stretch of ram, end is exclusive
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Region {
+    pub name: &'static str,
+    pub start: usize,
+    pub end: usize,
+}
+
+impl Region {
+    fn new(name: &'static str, start: usize, len: usize) -> Self {
+        Self { name, start, end: start + len }
+    }
+
+    pub fn contains(&self, address: usize) -> bool {
+        (self.start..self.end).contains(&address)
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct MachineConfig {
+    pub ram_size: usize,
+    pub map: MemoryMap,
+    pub peripherals: Vec<PeripheralConfig>,
+}
+
+impl Default for MachineConfig {
+    fn default() -> Self {
+        Self {
+            ram_size: 255,
+            map: MemoryMap::default(),
+            peripherals: vec![
+                PeripheralConfig::Keyboard { address: 0, size: 7, buffer: default_buffer() },
+                PeripheralConfig::Screen { address: 7, size: 6, width: 32, height: 24, refresh: default_refresh() },
+            ],
+        }
+    }
+}
+
+impl MachineConfig {
+    pub fn load(path: &str) -> Result<Self, ConfigError> {
+        let profile = fs::read_to_string(path).map_err(|e| ConfigError::Io(format!("failed to read {}: {}", path, e)))?;
+
+        match Path::new(path).extension().and_then(|e| e.to_str()) {
+            Some("json") => Self::from_json(&profile),
+            _ => Self::from_toml(&profile),
+        }
+    }
+
+    pub fn from_toml(profile: &str) -> Result<Self, ConfigError> {
+        let config: Self = toml::from_str(profile).map_err(|e| ConfigError::Parse(e.to_string()))?;
+        config.validate()?;
+
+        Ok(config)
+    }
+
+    pub fn from_json(profile: &str) -> Result<Self, ConfigError> {
+        let config: Self = serde_json::from_str(profile).map_err(|e| ConfigError::Parse(e.to_string()))?;
+        config.validate()?;
+
+        Ok(config)
+    }
+
+    // Everything that claims ram, sorted by address
+    pub fn regions(&self) -> Vec<Region> {
+        let map = &self.map;
+        let stack = match map.stack.direction {
+            StackDirection::Up => Region::new("stack", map.stack.base, map.stack.limit.saturating_sub(map.stack.base)),
+            StackDirection::Down => Region::new("stack", map.stack.limit + 1, map.stack.base.saturating_sub(map.stack.limit)),
+        };
+
+        let mut regions: Vec<Region> = self.peripherals.iter().map(|p| p.region()).collect();
+        regions.extend([
+            Region::new("fault", map.fault, 1),
+            Region::new("program", map.boot, map.program),
+            Region::new("bank", map.bank.address, map.bank.size),
+            stack,
+            Region::new("ivt", map.ivt, IRQ_LINES),
+        ]);
+        regions.sort_by_key(|r| r.start);
+
+        regions
+    }
+
+    pub fn region(&self, name: &str) -> Option<Region> {
+        self.regions().into_iter().find(|r| r.name == name)
+    }
+
+    // width and height of the attached screen
+    pub fn screen_size(&self) -> Option<(u8, u8)> {
+        self.peripherals.iter().find_map(|p| match p {
+            PeripheralConfig::Screen { width, height, .. } => Some((*width, *height)),
+            _ => None,
+        })
+    }
+
+    // The most a boot image can hold, the fixed part and every bank
+    pub fn image_size(&self) -> usize {
+        self.map.program + self.map.bank.size * self.map.bank.count
+    }
+
+    pub fn validate(&self) -> Result<(), ConfigError> {
+        let invalid = |what: String| Err(ConfigError::Invalid(what));
+        let map = &self.map;
+
+        if self.ram_size == 0 || self.ram_size > 256 {
+            return invalid(format!("ram_size {} is not between 1 and 256", self.ram_size));
+        }
+        if map.bank.count == 0 {
+            return invalid(String::from("at least one bank is needed"));
+        }
+        let stack_ok = match map.stack.direction {
+            StackDirection::Up => map.stack.base < map.stack.limit,
+            StackDirection::Down => map.stack.base > map.stack.limit,
+        };
+        if !stack_ok {
+            return invalid(format!("the stack base {} and limit {} are the wrong way round for its direction", map.stack.base, map.stack.limit));
+        }
+
+        for (i, p) in self.peripherals.iter().enumerate() {
+            if self.peripherals[..i].iter().any(|other| other.name() == p.name()) {
+                return invalid(format!("more than one {}", p.name()));
+            }
+            if let PeripheralConfig::Screen { width: 0, .. } | PeripheralConfig::Screen { height: 0, .. } = p {
+                return invalid(String::from("the screen needs a width and a height"));
+            }
+            if let PeripheralConfig::Screen { refresh: 0, .. } = p {
+                return invalid(String::from("the screen needs a refresh of at least 1 cycle"));
+            }
+            if let PeripheralConfig::Keyboard { buffer: 0, .. } = p {
+                return invalid(String::from("the keyboard needs a buffer"));
+            }
+            if let PeripheralConfig::Uart { fifo: 0, .. } = p {
+                return invalid(String::from("the uart needs a fifo"));
+            }
+            // room for the registers the bus maps there
+            let registers = match p {
+                PeripheralConfig::Keyboard { .. } => 6,
+                PeripheralConfig::Screen { .. } => 6,
+                PeripheralConfig::Timer { .. } => 5,
+                PeripheralConfig::Uart { .. } => 3,
+            };
+            if p.region().end - p.region().start < registers {
+                return invalid(format!("{} needs at least {} bytes for its registers", p.name(), registers));
+            }
+        }
+
+        let regions = self.regions();
+        for (i, region) in regions.iter().enumerate() {
+            if region.start == region.end {
+                return invalid(format!("{} is empty", region.name));
+            }
+            if region.end > self.ram_size {
+                return invalid(format!("{} ({}..{}) does not fit in {} bytes of ram", region.name, region.start, region.end, self.ram_size));
+            }
+            if let Some(next) = regions.get(i + 1) {
+                if next.start < region.end {
+                    return invalid(format!("{} ({}..{}) overlaps {} ({}..{})", region.name, region.start, region.end, next.name, next.start, next.end));
+                }
+            }
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // the text between start and end in source
+    fn between<'a>(source: &'a str, start: &str, end: &str) -> &'a str {
+        let from = &source[source.find(start).unwrap() + start.len()..];
+        &from[..from.find(end).unwrap()]
+    }
+
+    #[test]
+    fn documented_profile_is_valid() {
+        let block = between(include_str!("config.rs"), "keeps the original board:\n", "\n    The CPU addresses");
+        let profile: Vec<&str> = block.lines().map(|line| line.strip_prefix("        ").unwrap_or(line)).collect();
+        let config = MachineConfig::from_toml(&profile.join("\n")).unwrap();
+
+        assert_eq!(config.peripherals.len(), 4);
+    }
+
+    #[test]
+    fn readme_profile_is_the_default_board() {
+        let profile = between(between(include_str!("../../README.md"), "## Machine profiles", "\n## "), "```toml\n", "```");
+
+        assert_eq!(MachineConfig::from_toml(profile).unwrap(), MachineConfig::default());
+    }
+}
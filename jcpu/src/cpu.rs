@@ -1,7 +1,8 @@
-use jcpuinstructions::{Instruction, Register};
+use jcpuinstructions::{decode, AluOp, DecodeError, DecodedInstruction, Register};
 
-use crate::{alu::{ALU, REG_A_ISREG, REG_B_ISREG}, ram::Ram, motherboard::{BOOT_ADDR, STACK_ADDR}};
+use crate::{alu::{ALU, FLAG_INT}, fault::CpuFault, interrupts::{InterruptController, IRQ_FAULT}, ram::Ram, config::{MemoryMap, StackDirection}};
 
+#[derive(Clone)]
 pub struct CPU {
     // just some descriptors because we're fancy like that
     pub name: &'static str,
@@ -19,13 +20,22 @@ pub struct CPU {
     pub reg_out: u8,    // a bogus output register
     pub reg_sp: u8,
     pub reg_int: u8,
+    // how many CALLs have not returned yet
+    pub call_depth: usize,
+    pub interrupts: InterruptController,
     pub alu: ALU,
     pub dbg_msg: String,
     pub clearing: bool,
+    // where the boot image, stack, vector table and fault code live
+    pub map: MemoryMap,
 }
 
 impl CPU {
     pub fn new() -> Self {
+        Self::with_map(MemoryMap::default())
+    }
+
+    pub fn with_map(map: MemoryMap) -> Self {
         Self {
             name: "jCPU",
             arch: "jx8",
@@ -40,9 +50,12 @@ impl CPU {
             reg_mar: 0,
             reg_out: 0,
             reg_int: 0,
-            reg_sp: STACK_ADDR as u8,
+            reg_sp: map.stack.base as u8,
+            call_depth: 0,
+            interrupts: InterruptController::new(),
             dbg_msg: String::from("CPU started"),
             clearing: false,
+            map,
             alu: ALU {
                 A: 0,
                 B: 0,
@@ -68,6 +81,8 @@ impl CPU {
         self.reg_ir = 0;
         self.reg_out = 0;
         self.reg_int = 0;
+        self.call_depth = 0;
+        self.interrupts.reset();
         self.alu.A =  0;
         self.alu.B = 0;
 
@@ -83,189 +98,454 @@ impl CPU {
         self.clearing = false;
     }
 
-    pub fn cycle(&mut self, ram: &mut Ram) -> bool {
+    pub fn cycle(&mut self, ram: &mut Ram) -> Result<bool, CpuFault> {
         let instruction = self.reg_ir;
 
-        // non alu instructions
-        if (instruction >> 7) == 0b0 {
-            let reg_a = (instruction & 0x0C) >> 2;
-            let reg_b = instruction & 0x03;
-            let flags = instruction & 0b00001111;
+        // the operand bytes (if any) sit right after the instruction
+        let mut bytes = vec![instruction];
+        for i in 1..3 {
+            if (self.reg_mar as usize) + i < ram.memory.len() {
+                bytes.push(ram.fetch(self.reg_mar + i as u8));
+            }
+        }
+
+        let decoded = match decode(&bytes) {
+            Ok(decoded) => decoded,
+            // the operand bytes would be past the end of ram
+            Err(DecodeError::Truncated(_)) => return Err(CpuFault::AddressOutOfRange(ram.memory.len())),
+            Err(_) => return Err(CpuFault::IllegalOpcode(instruction)),
+        };
 
-            // check for non packed instructions
-            if instruction == Instruction::INT as u8 {
-                self.dbg_msg = String::from("interrupt found");
+        match decoded {
+            DecodedInstruction::Int { value } => {
                 self.reg_mar += 1;
 
-                self.reg_int = ram.read(self.reg_mar);
+                // a software interrupt, taken before the next fetch like a device's
+                if !self.interrupts.raise(value) {
+                    return Err(CpuFault::IllegalOpcode(instruction));
+                }
+
+                self.dbg_msg = format!("Raising irq {}", value);
+                self.reg_int = value;
                 self.reg_iar += 1;
-            } else if instruction == Instruction::CLI as u8 {
+            },
+            DecodedInstruction::Cli => {
                 self.dbg_msg = String::from("CLI TIME");
 
                 self.clearing = true;
 
                 self.reg_int = 0;
-            } else if instruction == Instruction::HLT as u8 {
-                self.dbg_msg = String::from("halting");
-                return false;
-            } else if instruction == Instruction::CRF as u8 {
-                self.alu.flags &= 0b1001_1111
-            } else if instruction == Instruction::SF as u8 {
-                //check if next byte is equal to 0b0100_0000 or 0b0010_0000
-                // set the ALU R1 or R2 high accordingly
+            },
+            DecodedInstruction::Ei => {
+                self.alu.flags |= FLAG_INT;
+                self.dbg_msg = String::from("Interrupts enabled");
+            },
+            DecodedInstruction::Di => {
+                self.alu.flags &= !FLAG_INT;
+                self.dbg_msg = String::from("Interrupts disabled");
+            },
+            DecodedInstruction::Iret => {
+                let return_address = self.pop(ram)?;
+                self.alu.flags = self.pop(ram)?;
+                let irq = self.interrupts.active.pop();
+
+                self.reg_iar = return_address.wrapping_sub(1); // -1 because end of function increments
+                self.dbg_msg = format!("Returning from irq {:?} to address {}", irq, return_address);
+            },
+            DecodedInstruction::Bank { value } => {
                 self.reg_mar += 1;
 
-                let flag_setter = ram.read(self.reg_mar);
-                self.alu.flags |= flag_setter;
+                // a bank that does not exist is out of range too
+                if value as usize >= ram.bank_count() {
+                    return Err(CpuFault::AddressOutOfRange(value as usize));
+                }
+                ram.select_bank(value);
 
-                self.dbg_msg = format!("Set Flags now: {:08b}", self.alu.flags);
+                self.dbg_msg = format!("Switching to ram bank {}", value);
+                self.reg_iar += 1;
+            },
+            DecodedInstruction::Hlt => {
+                self.dbg_msg = String::from("halting");
+                return Ok(false);
+            },
+            DecodedInstruction::Clf => {
+                // clear the GT|EQ|Z|S|C flags, leave INT alone
+                self.alu.flags &= 0b1000_0000
+            },
+            DecodedInstruction::Data { reg, value } => {
+                self.reg_mar += 1;
+
+                self.set_register(reg as u8, value)?;
 
+                self.dbg_msg = format!("Setting reg {} to value {}", (reg as u8 + 1), value);
                 self.reg_iar += 1;
-            } else {
+            },
+            DecodedInstruction::Ld { ra, rb } => {
+                // set prev to current mar,
+                let prev = self.reg_mar;
 
-                // opcode first 4 bits
-                let opcode = instruction & 0xF0;
-                if opcode == Instruction::DATA as u8 {
-                    self.reg_mar += 1;
+                // set mar to regA value,
+                self.reg_mar = self.get_register(ra as u8)?;
 
-                    self.set_register(reg_a, ram.read(self.reg_mar));
+                // ld: load memory from ram at regA address into regB
+                let value = self.read(ram, self.reg_mar)?;
+                self.set_register(rb as u8, value)?;
 
-                    self.dbg_msg = format!("Setting reg {} to value {}", (reg_a + 1), ram.read(self.reg_mar));
-                    self.reg_iar += 1;
-                } else if opcode == Instruction::LD as u8 {
-                    // set prev to current mar,
-                    let prev = self.reg_mar;
+                // set mar back to prev
+                self.reg_mar = prev;
+            },
+            DecodedInstruction::St { ra, rb } => {
+                // set prev to current mar,
+                let prev = self.reg_mar;
 
-                    // set mar to regA value,
-                    self.reg_mar = self.get_register(reg_a);
+                // set mar to regA value,
+                self.reg_mar = self.get_register(ra as u8)?;
 
-                    // ld: load memory from ram at regA address into regB
-                    self.set_register(reg_b, ram.read(self.reg_mar));
+                // ld: load value* at regB in regA ram location*
+                let register_b = self.get_register(rb as u8)?;
 
-                    // set mar back to prev
-                    self.reg_mar = prev;
-                } else if opcode == Instruction::ST as u8 {
-                    // set prev to current mar,
-                    let prev = self.reg_mar;
+                self.write(ram, self.reg_mar, register_b)?;
 
-                    // set mar to regA value,
-                    self.reg_mar = self.get_register(reg_a);
+                // set mar back to prev
+                self.reg_mar = prev;
+            },
+            DecodedInstruction::LdImm { address, rb } => {
+                self.reg_mar += 1;
 
-                    // ld: load value* at regB in regA ram location*
-                    let register_b = self.get_register(reg_b);
+                let value = self.read(ram, address)?;
+                self.set_register(rb as u8, value)?;
 
-                    ram.write(self.reg_mar, register_b);
+                self.dbg_msg = format!("Loading ram address {} into reg {}", address, (rb as u8 + 1));
+                self.reg_iar += 1;
+            },
+            DecodedInstruction::StImm { address, rb } => {
+                self.reg_mar += 1;
 
-                    // set mar back to prev
-                    self.reg_mar = prev;
-                } else if opcode == Instruction::JMP as u8 {
-                    self.reg_mar += 1;
-                    let address = (BOOT_ADDR) as u8 + ram.read(self.reg_mar) - 1;
-                    self.reg_iar = address; // -1 because end of function increments
-                    self.dbg_msg = format!("Jumping to address {}", address);
-
-                } else if opcode == Instruction::JMPR as u8 {
-                    self.reg_iar = self.get_register(reg_a);
-
-                    self.reg_mar = self.reg_iar;
-                } else if opcode == Instruction::JMPIF as u8 {
-                    // @FIXME
-                    if self.alu.match_flags(flags) {
-                        self.dbg_msg = String::from("Jump if check passed");
-                        self.reg_mar += 1;
-
-                        self.dbg_msg = format!("Retrieving address from {}, read({})", self.reg_mar, ram.read(self.reg_mar));
-
-                        self.reg_iar = (BOOT_ADDR as u8) + ram.read(self.reg_mar) - 1;
-                    }  else {
-                        self.dbg_msg = String::from("Jump if check failed");
-                        self.reg_iar += 1;
-                    }
-                } else {
-                    panic!("[cpu] unknown instruction")
-                }
-            }
-            self.reg_iar += 1;
-        }
+                let value = self.get_register(rb as u8)?;
+                self.write(ram, address, value)?;
 
+                self.dbg_msg = format!("Storing reg {} at ram address {}", (rb as u8 + 1), address);
+                self.reg_iar += 1;
+            },
+            DecodedInstruction::Jmp { address } => {
+                self.reg_mar += 1;
+                let address = self.jump_target(ram, address)?;
+                self.reg_iar = address; // -1 because end of function increments
+                self.dbg_msg = format!("Jumping to address {}", address);
+            },
+            DecodedInstruction::Jmpr { reg } => {
+                self.reg_iar = self.get_register(reg as u8)?;
 
-        // alu instructions
-        if (instruction >> 7) == 0b1 {
-            // opcode first 4 bits
-            let opcode = instruction & 0xF0;
+                self.reg_mar = self.reg_iar;
+            },
+            DecodedInstruction::Call { address } => {
+                // the return address is the instruction after the CALL and its address byte
+                let return_address = match self.reg_iar.checked_add(2) {
+                    Some(return_address) => return_address,
+                    None => return Err(CpuFault::AddressOutOfRange(self.reg_iar as usize + 2)),
+                };
+                let address = self.jump_target(ram, address)?;
+                self.push(ram, return_address)?;
+                self.call_depth += 1;
 
-            let reg_a = (instruction & 0x0C) >> 2;
-            let reg_b = instruction & 0x03;
+                self.reg_mar += 1;
+                self.reg_iar = address; // -1 because end of function increments
+                self.dbg_msg = format!("Calling address {}, returning to {}", address.wrapping_add(1), return_address);
+            },
+            DecodedInstruction::Ret => {
+                let return_address = self.pop(ram)?;
+                self.call_depth = self.call_depth.saturating_sub(1);
 
+                self.reg_iar = return_address.wrapping_sub(1); // -1 because end of function increments
+                self.dbg_msg = format!("Returning to address {}", return_address);
+            },
+            DecodedInstruction::JmpIf { flags, address } => {
+                // @FIXME
+                if self.alu.match_flags(flags) {
+                    self.dbg_msg = String::from("Jump if check passed");
+                    self.reg_mar += 1;
 
-            if self.alu.flags & REG_A_ISREG == 0 {
-                self.alu.set_a(self.get_register(reg_a));
-            }
-            if self.alu.flags & REG_B_ISREG == 0 {
-                self.alu.set_b(self.get_register(reg_b));
-            }
+                    self.dbg_msg = format!("Retrieving address from {}, read({})", self.reg_mar, address);
+
+                    self.reg_iar = self.jump_target(ram, address)?;
+                }  else {
+                    self.dbg_msg = String::from("Jump if check failed");
+                    self.reg_iar += 1;
+                }
+            },
+            // alu instructions
+            DecodedInstruction::Add { ra, rb } => {
+                self.load_alu(ra, rb)?;
 
-            if opcode == Instruction::ADD as u8 {
                 let res = self.alu.op_add();
                 self.dbg_msg = format!("Adding reg A and reg B, setting result {} to reg B", {res});
-                self.set_register(reg_b, res)
-            } else if opcode == Instruction::SUB as u8 {
+                self.set_register(rb as u8, res)?;
+
+                self.alu.flags();
+            },
+            DecodedInstruction::Sub { ra, rb } => {
+                self.load_alu(ra, rb)?;
+
                 let res = self.alu.op_sub();
-                self.set_register(reg_b, res)
-            } else if opcode == Instruction::CMP as u8 {
-                //@TODO CMP needs to move out of alu instructions
-                //check flags in if
-                self.reg_mar += 1;
-                let next_byte = ram.read(self.reg_mar);
+                self.set_register(rb as u8, res)?;
 
-                if self.alu.flags & REG_B_ISREG > 1 {
-                    self.alu.set_b(next_byte);
-                } else {
-                    self.alu.set_b(self.get_register(next_byte));
-                }
+                self.alu.flags();
+            },
+            DecodedInstruction::AddImm { reg, value } => {
+                self.alu.set_a(self.get_register(reg as u8)?);
+                self.alu.set_b(value);
+
+                let res = self.alu.op_add();
+                self.dbg_msg = format!("Adding {} to reg {}, setting result {}", value, (reg as u8 + 1), res);
+                self.set_register(reg as u8, res)?;
+
+                self.alu.flags();
+                self.reg_mar += 2;
+                self.reg_iar += 2;
+            },
+            DecodedInstruction::SubImm { reg, value } => {
+                self.alu.set_a(self.get_register(reg as u8)?);
+                self.alu.set_b(value);
+
+                let res = self.alu.op_sub();
+                self.set_register(reg as u8, res)?;
+
+                self.alu.flags();
+                self.reg_mar += 2;
+                self.reg_iar += 2;
+            },
+            DecodedInstruction::Cmp { ra, rb } => {
+                self.load_alu(ra, rb)?;
 
                 let res = self.alu.op_sub();
 
+                // the result is kept in the ALU sum and Z flag, A and B stay as compared
                 self.dbg_msg = format!("Comparing: {} - {} = {}", self.alu.A, self.alu.B, &res);
-                self.alu.A = res;
 
+                self.alu.flags();
+            },
+            DecodedInstruction::CmpImm { reg, value } => {
+                self.alu.set_a(self.get_register(reg as u8)?);
+                self.alu.set_b(value);
+                self.reg_mar += 1;
+
+                let res = self.alu.op_sub();
+
+                // the result is kept in the ALU sum and Z flag, A and B stay as compared
+                self.dbg_msg = format!("Comparing: {} - {} = {}", self.alu.A, self.alu.B, &res);
+
+                self.alu.flags();
                 self.reg_iar += 1
-            } else if opcode == Instruction::INC as u8 {
+            },
+            DecodedInstruction::Inc { reg } => {
+                self.alu.set_a(self.get_register(reg as u8)?);
+
                 let res = self.alu.op_inc();
-                self.set_register(reg_a, res)
-            } else if opcode == Instruction::DEC as u8 {
+                self.set_register(reg as u8, res)?;
+
+                self.alu.flags();
+            },
+            DecodedInstruction::Dec { reg } => {
+                self.alu.set_a(self.get_register(reg as u8)?);
+
                 let res = self.alu.op_dec();
-                self.dbg_msg = format!("Decrementing reg {} to value {}", (reg_a + 1), res);
-                self.set_register(reg_a, res)
-            } else if opcode == Instruction::PUSH as u8 {
-                // SP is after the BIN_SIZE so on boot we plus 1
-                // @TODO check for register value or literal value
-                if self.reg_sp == 255 {
-                   panic!("Stack limit reached!")
-                } else {
-                    self.reg_sp += 1;
-                    let val = self.get_register(reg_a);
-                    self.dbg_msg = format!("Setting value {} in reg {} to stack", val, (reg_a + 1));
-                    ram.write(self.reg_sp, val)
-                }
-            } else if opcode == Instruction::POP as u8 {
-                let val = ram.read(self.reg_sp);
-                self.dbg_msg = format!("Popping value {} in stack to register {}", val, (reg_a + 1));
-                self.set_register(reg_a, val);
-                self.reg_sp -= 1
-            } else {
-                panic!("[cpu] unknown instruction")
-            }
+                self.dbg_msg = format!("Decrementing reg {} to value {}", (reg as u8 + 1), res);
+                self.set_register(reg as u8, res)?;
+
+                self.alu.flags();
+            },
+            DecodedInstruction::Alu { op, ra, rb } => {
+                self.alu.set_a(self.get_register(ra as u8)?);
+                self.alu.set_b(self.get_register(rb as u8)?);
+
+                let res = self.alu_op(op);
+                self.dbg_msg = format!("{:?} reg {} and reg {}, setting result {} to reg {}", op, (ra as u8 + 1), (rb as u8 + 1), res, (rb as u8 + 1));
+                self.set_register(rb as u8, res)?;
+
+                self.alu.flags();
+                self.reg_mar += 1;
+                self.reg_iar += 1;
+            },
+            DecodedInstruction::AluImm { op, reg, value } => {
+                self.alu.set_a(self.get_register(reg as u8)?);
+                self.alu.set_b(value);
+
+                let res = self.alu_op(op);
+                self.dbg_msg = format!("{:?} reg {} and {}, setting result {} to reg {}", op, (reg as u8 + 1), value, res, (reg as u8 + 1));
+                self.set_register(reg as u8, res)?;
+
+                self.alu.flags();
+                self.reg_mar += 2;
+                self.reg_iar += 2;
+            },
+            DecodedInstruction::Not { reg } => {
+                self.alu.set_a(self.get_register(reg as u8)?);
+
+                let res = self.alu.op_not();
+                self.dbg_msg = format!("Flipping reg {} to value {}", (reg as u8 + 1), res);
+                self.set_register(reg as u8, res)?;
+
+                self.alu.flags();
+                self.reg_mar += 1;
+                self.reg_iar += 1;
+            },
+            DecodedInstruction::Push { reg } => {
+                let val = self.get_register(reg as u8)?;
+                self.dbg_msg = format!("Setting value {} in reg {} to stack", val, (reg as u8 + 1));
+                self.push(ram, val)?;
+            },
+            DecodedInstruction::PushImm { value } => {
+                self.dbg_msg = format!("Setting value {} to stack", value);
+                self.push(ram, value)?;
+
+                self.reg_mar += 1;
+                self.reg_iar += 1;
+            },
+            DecodedInstruction::Pop { reg } => {
+                let val = self.pop(ram)?;
+                self.dbg_msg = format!("Popping value {} in stack to register {}", val, (reg as u8 + 1));
+                self.set_register(reg as u8, val)?;
+            },
+        }
+
+        self.reg_iar = self.reg_iar.wrapping_add(1);
+
+        Ok(true)
+    }
+
+    // If interrupts are enabled and a line is pending, push the flags and IAR and
+    // point IAR at the handler from the vector table
+    pub fn service_interrupt(&mut self, ram: &mut Ram) -> Result<bool, CpuFault> {
+        if self.alu.flags & FLAG_INT == 0 {
+            return Ok(false);
+        }
+
+        let irq = match self.interrupts.next() {
+            Some(irq) => irq,
+            None => return Ok(false),
+        };
+        self.interrupts.acknowledge(irq);
+
+        let handler = ram.read((self.map.ivt as u8) + irq);
+        if handler == 0 {
+            self.dbg_msg = format!("No handler for irq {}", irq);
+            return Ok(false);
+        }
+
+        self.enter_handler(ram, irq, handler, self.reg_iar)?;
+        self.dbg_msg = format!("Taking irq {}, handler at {}", irq, self.reg_iar);
 
-            self.alu.flags();
+        Ok(true)
+    }
+
+    // Like an interrupt but it can not be masked, the fault code is left at map.fault
+    // and IRET carries on at resume. Without a handler the fault is handed back.
+    pub fn trap(&mut self, ram: &mut Ram, fault: CpuFault, resume: u8) -> Result<(), CpuFault> {
+        let handler = ram.read((self.map.ivt as u8) + IRQ_FAULT);
+        if handler == 0 {
+            return Err(fault);
+        }
+
+        ram.write(self.map.fault as u8, fault.code());
+        self.enter_handler(ram, IRQ_FAULT, handler, resume)?;
+        self.dbg_msg = format!("Fault: {}, trapping to {}", fault, self.reg_iar);
+
+        Ok(())
+    }
+
+    fn enter_handler(&mut self, ram: &mut Ram, irq: u8, handler: u8, resume: u8) -> Result<(), CpuFault> {
+        let address = self.jump_target(ram, handler)?.wrapping_add(1);
+
+        self.push(ram, self.alu.flags)?;
+        self.push(ram, resume)?;
+        self.alu.flags &= !FLAG_INT;
+        self.interrupts.active.push(irq);
+
+        self.reg_iar = address;
+        self.reg_mar = self.reg_iar;
+
+        Ok(())
+    }
+
+    // SP points at the top of the stack, so push moves it toward the limit before writing
+    fn push(&mut self, ram: &mut Ram, value: u8) -> Result<(), CpuFault> {
+        let stack = self.map.stack;
+        let sp = match stack.direction {
+            StackDirection::Up if self.reg_sp as usize + 1 < stack.limit => self.reg_sp + 1,
+            StackDirection::Down if self.reg_sp as usize > stack.limit + 1 => self.reg_sp - 1,
+            _ => return Err(CpuFault::StackOverflow),
+        };
+
+        self.reg_sp = sp;
+        ram.write(self.reg_sp, value);
+
+        Ok(())
+    }
+
+    fn pop(&mut self, ram: &mut Ram) -> Result<u8, CpuFault> {
+        let base = self.map.stack.base;
+        let sp = match self.map.stack.direction {
+            StackDirection::Up if self.reg_sp as usize > base => self.reg_sp - 1,
+            StackDirection::Down if (self.reg_sp as usize) < base => self.reg_sp + 1,
+            _ => return Err(CpuFault::StackUnderflow),
+        };
+
+        let value = ram.read(self.reg_sp);
+        self.reg_sp = sp;
+
+        Ok(value)
+    }
+
+    // Jump addresses are relative to the boot address, this gives the IAR to set so
+    // the increment at the end of the cycle lands on the target (wrapping round from
+    // 255 when the target is 0)
+    fn jump_target(&self, ram: &Ram, address: u8) -> Result<u8, CpuFault> {
+        let target = self.map.boot + address as usize;
+
+        if target >= ram.memory.len() {
+            return Err(CpuFault::AddressOutOfRange(target));
+        }
+
+        Ok((target as u8).wrapping_sub(1))
+    }
+
+    fn read(&self, ram: &mut Ram, address: u8) -> Result<u8, CpuFault> {
+        if address as usize >= ram.memory.len() {
+            return Err(CpuFault::AddressOutOfRange(address as usize));
+        }
+
+        Ok(ram.read(address))
+    }
 
-            self.reg_iar += 1;
+    fn write(&self, ram: &mut Ram, address: u8, value: u8) -> Result<(), CpuFault> {
+        if address as usize >= ram.memory.len() {
+            return Err(CpuFault::AddressOutOfRange(address as usize));
         }
 
-        true
+        ram.write(address, value);
+
+        Ok(())
+    }
+
+    fn alu_op(&mut self, op: AluOp) -> u8 {
+        match op {
+            AluOp::And => self.alu.op_and(),
+            AluOp::Or => self.alu.op_or(),
+            AluOp::Xor => self.alu.op_xor(),
+            AluOp::Shl => self.alu.op_shl(),
+            AluOp::Shr => self.alu.op_shr(),
+        }
     }
 
-    fn set_register(&mut self, reg: u8, value: u8) {
+    // latch the two registers into the ALU
+    fn load_alu(&mut self, ra: Register, rb: Register) -> Result<(), CpuFault> {
+        self.alu.set_a(self.get_register(ra as u8)?);
+        self.alu.set_b(self.get_register(rb as u8)?);
+
+        Ok(())
+    }
+
+    fn set_register(&mut self, reg: u8, value: u8) -> Result<(), CpuFault> {
         if reg == Register::R1 as u8 {
             self.reg_1 = value;
         } else if reg == Register::R2 as u8 {
@@ -275,21 +555,23 @@ impl CPU {
         } else if reg == Register::R4 as u8 {
             self.reg_4 = value;
         } else {
-            panic!("[cpu] unknown register")
+            return Err(CpuFault::BadRegister(reg));
         }
+
+        Ok(())
     }
 
-    fn get_register(&self, reg: u8) -> u8 {
+    fn get_register(&self, reg: u8) -> Result<u8, CpuFault> {
         if reg == Register::R1 as u8 {
-            self.reg_1
+            Ok(self.reg_1)
         } else if reg == Register::R2 as u8 {
-            self.reg_2
+            Ok(self.reg_2)
         } else if reg == Register::R3 as u8 {
-            self.reg_3
+            Ok(self.reg_3)
         } else if reg == Register::R4 as u8 {
-            self.reg_4
+            Ok(self.reg_4)
         } else {
-            panic!("[cpu] unknown register")
+            Err(CpuFault::BadRegister(reg))
         }
     }
 }
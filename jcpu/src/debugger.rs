@@ -0,0 +1,237 @@
+use std::fmt;
+
+use crate::{
+    cpu::CPU,
+    fault::CpuFault,
+    motherboard::Motherboard,
+    ram::{Access, RamAccess},
+};
+
+/*
+    Breakpoints and watchpoints. The debugger drives the motherboard one cycle at a
+    time, the same way the sim does (peripherals, cycle, CLI clearing), and says why
+    it stopped.
+
+    Breakpoints are program addresses, the ones JMP takes and jcpu-disasm prints, and
+    fire before the instruction there runs. Ram watchpoints are ram addresses, like
+    LD and ST take, and fire after the cycle that touched them. They use the ram
+    access log so reads are seen too and a write of the same value still counts.
+*/
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum WatchAccess {
+    Read,
+    Write,
+    Any,
+}
+
+impl WatchAccess {
+    fn matches(&self, access: Access) -> bool {
+        match self {
+            WatchAccess::Read => access == Access::Read,
+            WatchAccess::Write => access == Access::Write,
+            WatchAccess::Any => true,
+        }
+    }
+}
+
+// The registers a watchpoint can follow
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DebugRegister {
+    R1,
+    R2,
+    R3,
+    R4,
+    Sp,
+    Out,
+    Int,
+    Flags,
+}
+
+impl DebugRegister {
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name.to_lowercase().as_str() {
+            "r1" => Some(DebugRegister::R1),
+            "r2" => Some(DebugRegister::R2),
+            "r3" => Some(DebugRegister::R3),
+            "r4" => Some(DebugRegister::R4),
+            "sp" => Some(DebugRegister::Sp),
+            "out" => Some(DebugRegister::Out),
+            "int" => Some(DebugRegister::Int),
+            "flags" => Some(DebugRegister::Flags),
+            _ => None,
+        }
+    }
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            DebugRegister::R1 => "R1",
+            DebugRegister::R2 => "R2",
+            DebugRegister::R3 => "R3",
+            DebugRegister::R4 => "R4",
+            DebugRegister::Sp => "SP",
+            DebugRegister::Out => "OUT",
+            DebugRegister::Int => "INT",
+            DebugRegister::Flags => "FLAGS",
+        }
+    }
+
+    pub fn value(&self, cpu: &CPU) -> u8 {
+        match self {
+            DebugRegister::R1 => cpu.reg_1,
+            DebugRegister::R2 => cpu.reg_2,
+            DebugRegister::R3 => cpu.reg_3,
+            DebugRegister::R4 => cpu.reg_4,
+            DebugRegister::Sp => cpu.reg_sp,
+            DebugRegister::Out => cpu.reg_out,
+            DebugRegister::Int => cpu.reg_int,
+            DebugRegister::Flags => cpu.alu.flags,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Watchpoint {
+    // start to end, both included
+    Ram { start: u8, end: u8, access: WatchAccess },
+    Register(DebugRegister),
+}
+
+// Why the debugger stopped
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Stop {
+    Breakpoint(u8),
+    Ram { watchpoint: usize, hit: RamAccess },
+    Register { watchpoint: usize, register: DebugRegister, old: u8, new: u8 },
+    Halted,
+    Fault(CpuFault),
+    CycleLimit,
+}
+
+impl fmt::Display for Stop {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Stop::Breakpoint(address) => write!(f, "breakpoint at {}", address),
+            Stop::Ram { watchpoint, hit } => {
+                let access = match hit.access {
+                    Access::Read => "read",
+                    Access::Write => "write",
+                };
+                write!(f, "watchpoint {}: {} of {:02x} at {}", watchpoint, access, hit.value, hit.address)
+            },
+            Stop::Register { watchpoint, register, old, new } => {
+                write!(f, "watchpoint {}: {} changed {:02x} -> {:02x}", watchpoint, register.name(), old, new)
+            },
+            Stop::Halted => write!(f, "halted"),
+            Stop::Fault(fault) => write!(f, "fault: {}", fault),
+            Stop::CycleLimit => write!(f, "cycle limit"),
+        }
+    }
+}
+
+#[derive(Default)]
+pub struct Debugger {
+    pub breakpoints: Vec<u8>,
+    pub watchpoints: Vec<Watchpoint>,
+}
+
+impl Debugger {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn add_breakpoint(&mut self, address: u8) {
+        if !self.breakpoints.contains(&address) {
+            self.breakpoints.push(address);
+        }
+    }
+
+    pub fn remove_breakpoint(&mut self, address: u8) {
+        self.breakpoints.retain(|b| *b != address);
+    }
+
+    // true if the breakpoint is now set
+    pub fn toggle_breakpoint(&mut self, address: u8) -> bool {
+        if self.breakpoints.contains(&address) {
+            self.remove_breakpoint(address);
+            false
+        } else {
+            self.add_breakpoint(address);
+            true
+        }
+    }
+
+    // Stops report the watchpoint by the index this returns
+    pub fn watch(&mut self, watchpoint: Watchpoint) -> usize {
+        self.watchpoints.push(watchpoint);
+        self.watchpoints.len() - 1
+    }
+
+    // The program address IAR points at, None while it is outside the program
+    pub fn program_address(mb: &Motherboard) -> Option<u8> {
+        mb.cpu.map.program_address(mb.cpu.reg_iar)
+    }
+
+    // Run one cycle, Some when something made it stop
+    pub fn step(&mut self, mb: &mut Motherboard) -> Option<Stop> {
+        let registers: Vec<u8> = self
+            .watchpoints
+            .iter()
+            .map(|w| match w {
+                Watchpoint::Register(register) => register.value(&mb.cpu),
+                Watchpoint::Ram { .. } => 0,
+            })
+            .collect();
+
+        mb.ram.log.clear();
+        mb.ram.logging = self.watchpoints.iter().any(|w| matches!(w, Watchpoint::Ram { .. }));
+
+        mb.process_peripherals();
+        let result = mb.cycle();
+        if mb.cpu.clearing {
+            mb.reset_peripherals()
+        }
+
+        mb.ram.logging = false;
+        let log = std::mem::take(&mut mb.ram.log);
+
+        match result {
+            Ok(true) => {},
+            Ok(false) => return Some(Stop::Halted),
+            Err(fault) => return Some(Stop::Fault(fault)),
+        }
+
+        for (i, watchpoint) in self.watchpoints.iter().enumerate() {
+            match watchpoint {
+                Watchpoint::Ram { start, end, access } => {
+                    let hit = log.iter().find(|a| a.address >= *start && a.address <= *end && access.matches(a.access));
+                    if let Some(hit) = hit {
+                        return Some(Stop::Ram { watchpoint: i, hit: *hit });
+                    }
+                },
+                Watchpoint::Register(register) => {
+                    let new = register.value(&mb.cpu);
+                    if new != registers[i] {
+                        return Some(Stop::Register { watchpoint: i, register: *register, old: registers[i], new });
+                    }
+                },
+            }
+        }
+
+        match Self::program_address(mb) {
+            Some(address) if self.breakpoints.contains(&address) => Some(Stop::Breakpoint(address)),
+            _ => None,
+        }
+    }
+
+    // Run until a breakpoint, a watchpoint, HLT or a fault, at most max_cycles
+    pub fn run_until_break(&mut self, mb: &mut Motherboard, max_cycles: usize) -> Stop {
+        for _ in 0..max_cycles {
+            if let Some(stop) = self.step(mb) {
+                return stop;
+            }
+        }
+
+        Stop::CycleLimit
+    }
+}
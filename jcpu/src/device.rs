@@ -0,0 +1,194 @@
+use std::{
+    any::Any,
+    cell::{Ref, RefCell, RefMut},
+    fmt,
+    ops::Range,
+    rc::Rc,
+};
+
+use crate::{interrupts::IRQ_LINES, motherboard::Motherboard};
+
+/*
+    Anything plugged into the board. The keyboard and screen are devices like any
+    other, a crate using jcpu can write its own and attach it:
+
+        let id = mb.attach(Box::new(MyDevice::new()), Some(40..44))?;
+        mb.device_mut::<MyDevice>(id).unwrap().something();
+
+    Every hook has a default that does nothing, so a device only writes the ones it
+    needs. The motherboard calls them:
+
+    reset     on CLI, the device goes back to how it powered on
+    tick      once per process_peripherals, with the cycles that ran since the last one
+    irq       after every tick, a line to raise on the cpu (one past IRQ_LINES is dropped)
+    input     a value from the host, the sim and jcpu-run send key presses this way
+    mmio_*    LD and ST on the device's range of ram, offsets count from its start
+    *_state   what snapshots and the history keep of it
+
+    Devices are found again by the DeviceId attach hands back, by name or by type.
+*/
+
+pub trait Device: Any {
+    // unique on the board, snapshots and the history find the device by it
+    fn name(&self) -> &'static str;
+    fn reset(&mut self) {}
+    fn tick(&mut self, _cycles: usize) {}
+    fn irq(&mut self) -> Option<u8> {
+        None
+    }
+    fn input(&mut self, _value: u8) {}
+
+    // what a read would return, without a read's side effects (fetches, the ram views)
+    fn mmio_peek(&self, _offset: u8) -> u8 {
+        0
+    }
+    fn mmio_read(&mut self, offset: u8) -> u8 {
+        self.mmio_peek(offset)
+    }
+    fn mmio_write(&mut self, _offset: u8, _value: u8) {}
+
+    // load_state only gets bytes check_state accepted
+    fn save_state(&self) -> Vec<u8> {
+        vec![]
+    }
+    fn check_state(&self, state: &[u8]) -> bool {
+        state.is_empty()
+    }
+    fn load_state(&mut self, _state: &[u8]) {}
+}
+
+// The motherboard and the bus both hold the device
+pub type SharedDevice = Rc<RefCell<Box<dyn Device>>>;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct DeviceId(usize);
+
+#[derive(Clone)]
+pub(crate) struct Attached {
+    pub(crate) device: SharedDevice,
+    pub(crate) mmio: Option<Range<usize>>,
+}
+
+// Why attach turned a device away
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum AttachError {
+    // another device on the board has the name
+    NameTaken(&'static str),
+    // the range is empty or runs past the end of ram
+    BadRange { name: &'static str, range: Range<usize> },
+    // another device answers for some of the range
+    Overlap { name: &'static str, range: Range<usize>, other: &'static str, other_range: Range<usize> },
+}
+
+impl fmt::Display for AttachError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            AttachError::NameTaken(name) => write!(f, "a device named {} is already attached", name),
+            AttachError::BadRange { name, range } => write!(f, "{} can not be mapped at {}..{}", name, range.start, range.end),
+            AttachError::Overlap { name, range, other, other_range } => {
+                write!(f, "{} ({}..{}) overlaps {} ({}..{})", name, range.start, range.end, other, other_range.start, other_range.end)
+            },
+        }
+    }
+}
+
+// What the board says about an attached device
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DeviceInfo {
+    pub id: DeviceId,
+    pub name: &'static str,
+    // the ram it answers for, None when it is not on the bus
+    pub mmio: Option<Range<usize>>,
+}
+
+impl Motherboard {
+    // Plug a device in, mapping it onto the bus at mmio when given one. The board is
+    // left as it was when the name is taken or the range can not be mapped.
+    pub fn attach(&mut self, device: Box<dyn Device>, mmio: Option<Range<usize>>) -> Result<DeviceId, AttachError> {
+        let name = device.name();
+        if self.find(name).is_some() {
+            return Err(AttachError::NameTaken(name));
+        }
+
+        let device: SharedDevice = Rc::new(RefCell::new(device));
+        if let Some(range) = mmio.clone() {
+            if range.is_empty() || range.end > self.ram.memory.len() {
+                return Err(AttachError::BadRange { name, range });
+            }
+            self.ram.bus.map(name, range.start, range.len(), device.clone())?;
+            self.ram.sync_bus();
+        }
+
+        self.devices.push(Attached { device, mmio });
+        Ok(DeviceId(self.devices.len() - 1))
+    }
+
+    pub fn devices(&self) -> Vec<DeviceInfo> {
+        self.devices
+            .iter()
+            .enumerate()
+            .map(|(i, a)| DeviceInfo { id: DeviceId(i), name: a.device.borrow().name(), mmio: a.mmio.clone() })
+            .collect()
+    }
+
+    pub fn find(&self, name: &str) -> Option<DeviceId> {
+        self.devices.iter().position(|a| a.device.borrow().name() == name).map(DeviceId)
+    }
+
+    // the first device of type D
+    pub fn find_kind<D: Device>(&self) -> Option<DeviceId> {
+        self.devices.iter().position(|a| is::<D>(&**a.device.borrow())).map(DeviceId)
+    }
+
+    // None when id is some other kind of device
+    pub fn device<D: Device>(&self, id: DeviceId) -> Option<Ref<'_, D>> {
+        let cell = &self.devices.get(id.0)?.device;
+        Ref::filter_map(cell.borrow(), |d| (&**d as &dyn Any).downcast_ref::<D>()).ok()
+    }
+
+    // Changes made through this are not on the bus until the next ram access or sync_bus
+    pub fn device_mut<D: Device>(&self, id: DeviceId) -> Option<RefMut<'_, D>> {
+        let cell = &self.devices.get(id.0)?.device;
+        RefMut::filter_map(cell.borrow_mut(), |d| (&mut **d as &mut dyn Any).downcast_mut::<D>()).ok()
+    }
+
+    pub fn input(&mut self, id: DeviceId, value: u8) {
+        if let Some(a) = self.devices.get(id.0) {
+            a.device.borrow_mut().input(value);
+        }
+        self.ram.sync_bus();
+    }
+
+    pub fn process_peripherals(&mut self) {
+        let cycles = self.cycle_i.saturating_sub(self.ticked);
+        self.ticked = self.cycle_i;
+
+        for a in self.devices.iter() {
+            let mut device = a.device.borrow_mut();
+            device.tick(cycles);
+            if let Some(line) = device.irq() {
+                if !self.cpu.interrupts.raise(line) {
+                    self.cpu.dbg_msg = format!("{} raised irq {}, there are only {} lines", device.name(), line, IRQ_LINES);
+                }
+            }
+        }
+        self.ram.sync_bus();
+    }
+
+    pub fn reset_peripherals(&mut self) {
+        for a in self.devices.iter() {
+            a.device.borrow_mut().reset();
+        }
+        self.ram.sync_bus();
+
+        self.cpu.clearing = false;
+    }
+
+    pub(crate) fn shared_device(&self, name: &str) -> Option<&SharedDevice> {
+        self.devices.iter().map(|a| &a.device).find(|d| d.borrow().name() == name)
+    }
+}
+
+fn is<D: Device>(device: &dyn Device) -> bool {
+    (device as &dyn Any).is::<D>()
+}
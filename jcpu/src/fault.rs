@@ -0,0 +1,88 @@
+use std::fmt;
+
+/*
+    Things a program can do wrong. CPU::cycle hands these back instead of
+    panicking and the motherboard decides what happens next from its policies:
+    halt the machine, trap to the fault vector (IRQ_FAULT) or skip the instruction.
+*/
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CpuFault {
+    IllegalOpcode(u8),
+    StackOverflow,
+    StackUnderflow,
+    BadRegister(u8),
+    AddressOutOfRange(usize),
+}
+
+impl CpuFault {
+    // what a trap handler finds at FAULT_ADDR
+    pub fn code(&self) -> u8 {
+        match self {
+            CpuFault::IllegalOpcode(_) => 1,
+            CpuFault::StackOverflow => 2,
+            CpuFault::StackUnderflow => 3,
+            CpuFault::BadRegister(_) => 4,
+            CpuFault::AddressOutOfRange(_) => 5,
+        }
+    }
+}
+
+impl fmt::Display for CpuFault {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            CpuFault::IllegalOpcode(op) => write!(f, "illegal opcode {:08b}", op),
+            CpuFault::StackOverflow => write!(f, "stack overflow"),
+            CpuFault::StackUnderflow => write!(f, "stack underflow"),
+            CpuFault::BadRegister(reg) => write!(f, "bad register {}", reg),
+            CpuFault::AddressOutOfRange(address) => write!(f, "address {} out of range", address),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FaultPolicy {
+    // stop and hand the fault back from Motherboard::cycle
+    Halt,
+    // push the flags and the address after the faulting instruction, then jump to the fault vector
+    Trap,
+    // carry on with the next instruction
+    Ignore,
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct FaultPolicies {
+    pub illegal_opcode: FaultPolicy,
+    pub stack_overflow: FaultPolicy,
+    pub stack_underflow: FaultPolicy,
+    pub bad_register: FaultPolicy,
+    pub address_out_of_range: FaultPolicy,
+}
+
+impl FaultPolicies {
+    // the same policy for every fault
+    pub fn all(policy: FaultPolicy) -> Self {
+        Self {
+            illegal_opcode: policy,
+            stack_overflow: policy,
+            stack_underflow: policy,
+            bad_register: policy,
+            address_out_of_range: policy,
+        }
+    }
+
+    pub fn policy(&self, fault: &CpuFault) -> FaultPolicy {
+        match fault {
+            CpuFault::IllegalOpcode(_) => self.illegal_opcode,
+            CpuFault::StackOverflow => self.stack_overflow,
+            CpuFault::StackUnderflow => self.stack_underflow,
+            CpuFault::BadRegister(_) => self.bad_register,
+            CpuFault::AddressOutOfRange(_) => self.address_out_of_range,
+        }
+    }
+}
+
+impl Default for FaultPolicies {
+    fn default() -> Self {
+        Self::all(FaultPolicy::Halt)
+    }
+}
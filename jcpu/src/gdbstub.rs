@@ -0,0 +1,348 @@
+use std::{
+    io::{self, ErrorKind, Read, Write},
+    net::{TcpListener, TcpStream},
+};
+
+use crate::{
+    debugger::{Debugger, Stop},
+    fault::CpuFault,
+    motherboard::Motherboard,
+};
+
+/*
+    A GDB remote serial protocol server for one client at a time, over TCP or a
+    unix socket. It speaks the packets a debugger needs to drive the board:
+
+    ?             why the target stopped
+    g / G         read / write all registers
+    p n / P n=v   read / write register n
+    m a,l / M a,l:data  read / write ram
+    s / c         single step / continue (a ^C from the client stops it)
+    Z0 / z0       set / clear a software breakpoint
+    D / k         detach / kill, both end the session
+
+    Registers are one byte each, in the order R1 R2 R3 R4 IAR SP FLAGS. IAR and the
+    breakpoint addresses are ram addresses like the ones m and M take, so a program
+    address is the boot address higher. Anything else gets the empty "not supported"
+    reply.
+*/
+
+const REGISTERS: usize = 7;
+// SIGINT for ^C, SIGTRAP for steps and breakpoints, SIGILL and SIGSEGV for faults
+const SIGINT: u8 = 2;
+const SIGILL: u8 = 4;
+const SIGTRAP: u8 = 5;
+const SIGSEGV: u8 = 11;
+// how many cycles continue runs between looking for a ^C
+const POLL_CYCLES: usize = 256;
+
+pub trait Connection: Read + Write {
+    fn set_nonblocking(&self, nonblocking: bool) -> io::Result<()>;
+}
+
+impl Connection for TcpStream {
+    fn set_nonblocking(&self, nonblocking: bool) -> io::Result<()> {
+        TcpStream::set_nonblocking(self, nonblocking)
+    }
+}
+
+#[cfg(unix)]
+impl Connection for std::os::unix::net::UnixStream {
+    fn set_nonblocking(&self, nonblocking: bool) -> io::Result<()> {
+        std::os::unix::net::UnixStream::set_nonblocking(self, nonblocking)
+    }
+}
+
+// Wait for one client on address (host:port) and serve it until it detaches
+pub fn serve_tcp(mb: &mut Motherboard, debugger: &mut Debugger, address: &str) -> io::Result<()> {
+    let listener = TcpListener::bind(address)?;
+    let (stream, _) = listener.accept()?;
+    stream.set_nodelay(true)?;
+
+    GdbStub::new(mb, debugger, stream).run()
+}
+
+#[cfg(unix)]
+pub fn serve_unix(mb: &mut Motherboard, debugger: &mut Debugger, path: &str) -> io::Result<()> {
+    let listener = std::os::unix::net::UnixListener::bind(path)?;
+    let (stream, _) = listener.accept()?;
+
+    GdbStub::new(mb, debugger, stream).run()
+}
+
+enum Reply {
+    Packet(String),
+    // reply then end the session
+    Close(String),
+    // end the session without a reply
+    Hangup,
+}
+
+pub struct GdbStub<'a, C: Connection> {
+    mb: &'a mut Motherboard,
+    debugger: &'a mut Debugger,
+    conn: C,
+    // bytes read but not yet parsed
+    pending: Vec<u8>,
+    last_stop: u8,
+}
+
+impl<'a, C: Connection> GdbStub<'a, C> {
+    pub fn new(mb: &'a mut Motherboard, debugger: &'a mut Debugger, conn: C) -> Self {
+        Self {
+            mb,
+            debugger,
+            conn,
+            pending: vec![],
+            last_stop: SIGTRAP,
+        }
+    }
+
+    pub fn run(&mut self) -> io::Result<()> {
+        while let Some(packet) = self.read_packet()? {
+            match self.handle(&packet)? {
+                Reply::Packet(reply) => self.send(&reply)?,
+                Reply::Close(reply) => {
+                    self.send(&reply)?;
+                    break;
+                },
+                Reply::Hangup => break,
+            }
+        }
+
+        Ok(())
+    }
+
+    // The next packet with its checksum checked and acked, None once the client hangs up
+    fn read_packet(&mut self) -> io::Result<Option<String>> {
+        loop {
+            // anything before the $ is an ack or a stray ^C
+            if let Some(start) = self.pending.iter().position(|b| *b == b'$') {
+                self.pending.drain(..start);
+
+                if let Some(end) = self.pending.iter().position(|b| *b == b'#') {
+                    if self.pending.len() >= end + 3 {
+                        let packet: Vec<u8> = self.pending[1..end].to_vec();
+                        let checksum = std::str::from_utf8(&self.pending[end + 1..end + 3]).ok().and_then(|c| u8::from_str_radix(c, 16).ok());
+                        self.pending.drain(..end + 3);
+
+                        if checksum == Some(sum(&packet)) {
+                            self.conn.write_all(b"+")?;
+                            return Ok(Some(String::from_utf8_lossy(&packet).to_string()));
+                        }
+
+                        self.conn.write_all(b"-")?;
+                        continue;
+                    }
+                }
+            } else {
+                self.pending.clear();
+            }
+
+            let mut buffer = [0; 1024];
+            let read = self.conn.read(&mut buffer)?;
+            if read == 0 {
+                return Ok(None);
+            }
+            self.pending.extend_from_slice(&buffer[..read]);
+        }
+    }
+
+    fn send(&mut self, reply: &str) -> io::Result<()> {
+        let packet = format!("${}#{:02x}", reply, sum(reply.as_bytes()));
+
+        // resend until the client acks it
+        loop {
+            self.conn.write_all(packet.as_bytes())?;
+            self.conn.flush()?;
+
+            let mut ack = [0; 1];
+            loop {
+                if self.conn.read(&mut ack)? == 0 {
+                    return Ok(());
+                }
+                match ack[0] {
+                    b'+' => return Ok(()),
+                    b'-' => break,
+                    // the start of the next packet, keep it for read_packet
+                    other => self.pending.push(other),
+                }
+            }
+        }
+    }
+
+    fn handle(&mut self, packet: &str) -> io::Result<Reply> {
+        // a first byte that is not ascii is no command, and is not supported like any other
+        let (command, args) = packet.split_at_checked(1).unwrap_or(("", packet));
+
+        let reply = match command {
+            "?" => format!("S{:02x}", self.last_stop),
+            "g" => self.registers().iter().map(|r| format!("{:02x}", r)).collect(),
+            "G" => match parse_hex_bytes(args) {
+                Some(values) if values.len() == REGISTERS => {
+                    for (n, value) in values.iter().enumerate() {
+                        self.set_register(n, *value);
+                    }
+                    String::from("OK")
+                },
+                _ => String::from("E01"),
+            },
+            "p" => match usize::from_str_radix(args, 16) {
+                Ok(n) if n < REGISTERS => format!("{:02x}", self.registers()[n]),
+                _ => String::from("E01"),
+            },
+            "P" => {
+                let parsed = args.split_once('=').and_then(|(n, v)| Some((usize::from_str_radix(n, 16).ok()?, u8::from_str_radix(v, 16).ok()?)));
+                match parsed {
+                    Some((n, value)) if n < REGISTERS => {
+                        self.set_register(n, value);
+                        String::from("OK")
+                    },
+                    _ => String::from("E01"),
+                }
+            },
+            "m" => match parse_range(args, self.mb.ram.memory.len()) {
+                Some((address, len)) => self.mb.ram.memory[address..address + len].iter().map(|b| format!("{:02x}", b)).collect(),
+                None => String::from("E01"),
+            },
+            "M" => {
+                let parsed = args.split_once(':').and_then(|(range, data)| Some((parse_range(range, self.mb.ram.memory.len())?, parse_hex_bytes(data)?)));
+                match parsed {
+                    Some(((address, len), data)) if data.len() == len => {
+                        // the same writes the CPU makes, so they reach the devices and the history sees them
+                        for (offset, byte) in data.iter().enumerate() {
+                            self.mb.ram.write((address + offset) as u8, *byte);
+                        }
+                        String::from("OK")
+                    },
+                    _ => String::from("E01"),
+                }
+            },
+            "s" => {
+                let stop = self.debugger.step(self.mb);
+                self.stop_reply(stop, SIGTRAP)
+            },
+            "c" => {
+                let stop = self.resume()?;
+                self.stop_reply(stop, SIGINT)
+            },
+            "Z" | "z" => self.breakpoint(command == "Z", args),
+            "H" => String::from("OK"),
+            "q" if args.starts_with("Supported") => String::from("PacketSize=1000"),
+            "q" if args == "Attached" => String::from("1"),
+            "D" => return Ok(Reply::Close(String::from("OK"))),
+            // kill has no reply
+            "k" => return Ok(Reply::Hangup),
+            _ => String::new(),
+        };
+
+        Ok(Reply::Packet(reply))
+    }
+
+    // Continue until something stops the board or the client sends ^C
+    fn resume(&mut self) -> io::Result<Option<Stop>> {
+        loop {
+            for _ in 0..POLL_CYCLES {
+                if let Some(stop) = self.debugger.step(self.mb) {
+                    return Ok(Some(stop));
+                }
+            }
+
+            self.conn.set_nonblocking(true)?;
+            let mut buffer = [0; 64];
+            let read = self.conn.read(&mut buffer);
+            self.conn.set_nonblocking(false)?;
+
+            match read {
+                Ok(0) => return Ok(None),
+                Ok(n) => {
+                    if buffer[..n].contains(&0x03) {
+                        return Ok(None);
+                    }
+                    self.pending.extend_from_slice(&buffer[..n]);
+                },
+                Err(e) if e.kind() == ErrorKind::WouldBlock => {},
+                Err(e) => return Err(e),
+            }
+        }
+    }
+
+    // idle is the signal when nothing stopped it, a step that finished or a ^C
+    fn stop_reply(&mut self, stop: Option<Stop>, idle: u8) -> String {
+        let signal = match stop {
+            None => idle,
+            Some(Stop::Halted) => return String::from("W00"),
+            Some(Stop::Fault(CpuFault::IllegalOpcode(_))) => SIGILL,
+            Some(Stop::Fault(_)) => SIGSEGV,
+            Some(_) => SIGTRAP,
+        };
+
+        self.last_stop = signal;
+        format!("S{:02x}", signal)
+    }
+
+    // Z0,addr,kind and z0,addr,kind, only software breakpoints
+    fn breakpoint(&mut self, set: bool, args: &str) -> String {
+        let mut parts = args.split(',');
+        if parts.next() != Some("0") {
+            return String::new();
+        }
+
+        let boot = self.mb.cpu.map.boot;
+        let address = match parts.next().and_then(|a| usize::from_str_radix(a, 16).ok()) {
+            Some(address) if address >= boot && address < self.mb.ram.memory.len() => (address - boot) as u8,
+            _ => return String::from("E01"),
+        };
+
+        if set {
+            self.debugger.add_breakpoint(address);
+        } else {
+            self.debugger.remove_breakpoint(address);
+        }
+
+        String::from("OK")
+    }
+
+    fn registers(&self) -> [u8; REGISTERS] {
+        let cpu = &self.mb.cpu;
+        [cpu.reg_1, cpu.reg_2, cpu.reg_3, cpu.reg_4, cpu.reg_iar, cpu.reg_sp, cpu.alu.flags]
+    }
+
+    fn set_register(&mut self, n: usize, value: u8) {
+        let cpu = &mut self.mb.cpu;
+        match n {
+            0 => cpu.reg_1 = value,
+            1 => cpu.reg_2 = value,
+            2 => cpu.reg_3 = value,
+            3 => cpu.reg_4 = value,
+            4 => cpu.reg_iar = value,
+            5 => cpu.reg_sp = value,
+            _ => cpu.alu.flags = value,
+        }
+    }
+}
+
+fn sum(bytes: &[u8]) -> u8 {
+    bytes.iter().fold(0, |sum, b| sum.wrapping_add(*b))
+}
+
+fn parse_hex_bytes(hex: &str) -> Option<Vec<u8>> {
+    if !hex.len().is_multiple_of(2) {
+        return None;
+    }
+
+    (0..hex.len()).step_by(2).map(|i| u8::from_str_radix(hex.get(i..i + 2)?, 16).ok()).collect()
+}
+
+// "addr,len" in hex, None unless all of it is in ram
+fn parse_range(range: &str, ram_size: usize) -> Option<(usize, usize)> {
+    let (address, len) = range.split_once(',')?;
+    let address = usize::from_str_radix(address, 16).ok()?;
+    let len = usize::from_str_radix(len, 16).ok()?;
+
+    if address.checked_add(len)? > ram_size {
+        return None;
+    }
+
+    Some((address, len))
+}
@@ -0,0 +1,278 @@
+use std::collections::{BTreeMap, VecDeque};
+
+use crate::{
+    cpu::CPU,
+    fault::CpuFault,
+    motherboard::Motherboard,
+    ram::Change,
+};
+
+/*
+    Reverse execution. Every Motherboard::cycle first closes off what the previous
+    cycle changed: the CPU as it was, the ram bytes and banked bytes that were
+    written and the peripherals whose state moved. Anything a peripheral did between
+    the two cycles (keys, irqs) lands in the same delta, so stepping back
+    undoes a whole cycle the way the sim shows it.
+
+    Nothing is copied in full. Ram keeps a journal of the bytes that changed (see
+    ram.rs) and a delta holds just those, the peripherals are compared against their
+    state when the cycle started. `limit` caps how many cycles are kept, 0 turns
+    recording off.
+*/
+
+pub const DEFAULT_HISTORY: usize = 1024;
+
+// What one cycle changed, holding the values from before it ran
+pub struct CycleDelta {
+    // the cycle counter before it ran
+    pub cycle: usize,
+    pub clock: usize,
+    pub cpu: CPU,
+    pub fault: Option<CpuFault>,
+    pub bank: u8,
+    // address, before, after. A BANK swap shows up as writes to the whole window.
+    pub ram_writes: Vec<(u8, u8, u8)>,
+    // bank, offset, before
+    pub bank_writes: Vec<(u8, usize, u8)>,
+    // only the peripherals that changed
+    pub peripherals: Vec<(&'static str, Vec<u8>)>,
+    ticked: usize,
+    micro_address: u8,
+}
+
+impl CycleDelta {
+    pub fn wrote(&self, address: u8) -> bool {
+        self.ram_writes.iter().any(|w| w.0 == address)
+    }
+
+    // the writes as ram journals them
+    fn journal(&self) -> Vec<Change> {
+        let memory = self.ram_writes.iter().map(|(address, before, _)| Change::Memory { address: *address, before: *before });
+        let banks = self.bank_writes.iter().map(|(bank, offset, before)| Change::Bank { bank: *bank, offset: *offset, before: *before });
+
+        memory.chain(banks).collect()
+    }
+}
+
+// The machine as the running cycle found it, apart from ram which the journal covers
+struct CycleStart {
+    cycle: usize,
+    clock: usize,
+    cpu: CPU,
+    fault: Option<CpuFault>,
+    bank: u8,
+    peripherals: Vec<(&'static str, Vec<u8>)>,
+    ticked: usize,
+    micro_address: u8,
+}
+
+pub struct History {
+    pub limit: usize,
+    deltas: VecDeque<CycleDelta>,
+    // the cycle running now, None until the first one is recorded
+    open: Option<CycleStart>,
+}
+
+impl History {
+    pub fn new(limit: usize) -> Self {
+        Self {
+            limit,
+            deltas: VecDeque::new(),
+            open: None,
+        }
+    }
+
+    // how many cycles can be stepped back
+    pub fn len(&self) -> usize {
+        self.deltas.len() + self.open.is_some() as usize
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    pub fn clear(&mut self) {
+        self.deltas.clear();
+        self.open = None;
+    }
+
+    fn push(&mut self, delta: CycleDelta) {
+        self.deltas.push_back(delta);
+        // the open cycle counts towards the limit too
+        while self.deltas.len() >= self.limit {
+            self.deltas.pop_front();
+        }
+    }
+}
+
+impl Default for History {
+    fn default() -> Self {
+        Self::new(DEFAULT_HISTORY)
+    }
+}
+
+impl Motherboard {
+    // called at the start of every cycle
+    pub(crate) fn record_history(&mut self) {
+        self.ram.journaling = self.history.limit > 0;
+        if self.history.limit == 0 {
+            self.history.clear();
+            return;
+        }
+
+        match self.history.open.take() {
+            Some(start) => {
+                let delta = self.close_cycle(start);
+                self.history.push(delta);
+            },
+            // whatever was journaled before recording started belongs to no cycle
+            None => self.ram.journal.clear(),
+        }
+
+        self.history.open = Some(CycleStart {
+            cycle: self.cycle_i,
+            clock: self.clock_i,
+            cpu: self.cpu.clone(),
+            fault: self.fault,
+            bank: self.ram.bank,
+            peripherals: self.peripheral_states(),
+            ticked: self.ticked,
+            micro_address: self.micro.address,
+        });
+    }
+
+    fn peripheral_states(&self) -> Vec<(&'static str, Vec<u8>)> {
+        self.devices
+            .iter()
+            .map(|a| {
+                let device = a.device.borrow();
+                (device.name(), device.save_state())
+            })
+            .collect()
+    }
+
+    // The delta from start to now, taking the journal with it
+    fn close_cycle(&mut self, start: CycleStart) -> CycleDelta {
+        // the first value each byte had this cycle, bytes that ended up where they started are left out
+        let mut memory: BTreeMap<u8, u8> = BTreeMap::new();
+        let mut banks: BTreeMap<(u8, usize), u8> = BTreeMap::new();
+        for change in std::mem::take(&mut self.ram.journal) {
+            match change {
+                Change::Memory { address, before } => {
+                    memory.entry(address).or_insert(before);
+                },
+                Change::Bank { bank, offset, before } => {
+                    banks.entry((bank, offset)).or_insert(before);
+                },
+            }
+        }
+
+        let ram_writes = memory
+            .into_iter()
+            .map(|(address, before)| (address, before, self.ram.memory[address as usize]))
+            .filter(|(_, before, after)| before != after)
+            .collect();
+        let bank_writes = banks
+            .into_iter()
+            .filter(|((bank, offset), before)| self.ram.banks[*bank as usize][*offset] != *before)
+            .map(|((bank, offset), before)| (bank, offset, before))
+            .collect();
+
+        let now = self.peripheral_states();
+        let peripherals = start.peripherals.into_iter().filter(|p| !now.contains(p)).collect();
+
+        CycleDelta {
+            cycle: start.cycle,
+            clock: start.clock,
+            cpu: start.cpu,
+            fault: start.fault,
+            bank: start.bank,
+            ram_writes,
+            bank_writes,
+            peripherals,
+            ticked: start.ticked,
+            micro_address: start.micro_address,
+        }
+    }
+
+    fn undo(&mut self, delta: &CycleDelta) {
+        self.reset_micro();
+        self.cycle_i = delta.cycle;
+        self.clock_i = delta.clock;
+        self.cpu = delta.cpu.clone();
+        self.fault = delta.fault;
+
+        for (address, before, _) in delta.ram_writes.iter() {
+            self.ram.memory[*address as usize] = *before;
+        }
+        for (bank, offset, before) in delta.bank_writes.iter() {
+            self.ram.banks[*bank as usize][*offset] = *before;
+        }
+        self.ram.bank = delta.bank;
+
+        for (name, state) in delta.peripherals.iter() {
+            if let Some(device) = self.shared_device(name) {
+                device.borrow_mut().load_state(state);
+            }
+        }
+        self.ram.sync_bus();
+        self.ticked = delta.ticked;
+        self.micro.address = delta.micro_address;
+    }
+
+    // Undo the last cycle and whatever the peripherals did after it, handing back what was undone
+    pub fn step_back(&mut self) -> Option<CycleDelta> {
+        let start = self.history.open.take()?;
+        let undone = self.close_cycle(start);
+        self.undo(&undone);
+
+        // the cycle before is the open one again, with its writes back in the journal
+        if let Some(previous) = self.history.deltas.pop_back() {
+            let mut peripherals = self.peripheral_states();
+            for (name, state) in previous.peripherals.iter() {
+                if let Some(p) = peripherals.iter_mut().find(|p| p.0 == *name) {
+                    p.1 = state.clone();
+                }
+            }
+
+            self.ram.journal = previous.journal();
+            self.history.open = Some(CycleStart {
+                cycle: previous.cycle,
+                clock: previous.clock,
+                cpu: previous.cpu,
+                fault: previous.fault,
+                bank: previous.bank,
+                peripherals,
+                ticked: previous.ticked,
+                micro_address: previous.micro_address,
+            });
+        } else {
+            self.ram.journal.clear();
+        }
+
+        Some(undone)
+    }
+
+    // Step back until the cycle counter is at most cycle, returns how many steps were taken
+    pub fn run_back_to(&mut self, cycle: usize) -> usize {
+        let mut steps = 0;
+
+        while self.cycle_i > cycle && self.step_back().is_some() {
+            steps += 1;
+        }
+
+        steps
+    }
+
+    // Step back to just before the cycle that last wrote address, None when the
+    // history runs out first (the machine is then at the oldest recorded cycle)
+    pub fn run_back_to_write(&mut self, address: u8) -> Option<usize> {
+        while let Some(undone) = self.step_back() {
+            if undone.wrote(address) {
+                return Some(undone.cycle);
+            }
+        }
+
+        None
+    }
+}
@@ -0,0 +1,61 @@
+/*
+    The interrupt controller. Peripherals raise an IRQ line (INT n raises line n from
+    software), and while the INT flag is set the CPU takes the lowest pending line
+    before its next fetch. It pushes the flags and the return address, clears INT and
+    jumps to the handler in the vector table at IVT_ADDR. IRET pops both back.
+
+    A vector table entry is a program address like the ones JMP takes, 0 means the
+    line has no handler and its interrupts are dropped.
+*/
+
+pub const IRQ_LINES: usize = 8;
+pub const IRQ_TIMER: u8 = 0;
+pub const IRQ_KEYBOARD: u8 = 1;
+pub const IRQ_SERIAL: u8 = 2;
+// faults trapped by their policy, this line can not be masked
+pub const IRQ_FAULT: u8 = 7;
+
+#[derive(Default, Clone)]
+pub struct InterruptController {
+    // one bit per IRQ line
+    pub pending: u8,
+    // the lines being serviced, the innermost last
+    pub active: Vec<u8>,
+}
+
+impl InterruptController {
+    pub fn new() -> Self {
+        Self {
+            pending: 0,
+            active: vec![],
+        }
+    }
+
+    // false for a line the controller does not have, nothing is raised
+    pub fn raise(&mut self, irq: u8) -> bool {
+        if irq as usize >= IRQ_LINES {
+            return false;
+        }
+
+        self.pending |= 1 << irq;
+        true
+    }
+
+    // the lowest pending line goes first
+    pub fn next(&self) -> Option<u8> {
+        if self.pending == 0 {
+            None
+        } else {
+            Some(self.pending.trailing_zeros() as u8)
+        }
+    }
+
+    pub fn acknowledge(&mut self, irq: u8) {
+        self.pending &= !(1 << irq);
+    }
+
+    pub fn reset(&mut self) {
+        self.pending = 0;
+        self.active = vec![];
+    }
+}
@@ -0,0 +1,332 @@
+use std::collections::VecDeque;
+
+use crate::{device::Device, interrupts::IRQ_KEYBOARD};
+
+pub const KEYBOARD_ID: &str = "keyboard";
+
+/*
+    A keyboard controller. Every key going down or coming up is an event, kept in a
+    ring buffer of `buffer` events until the program reads it. An event has the key's
+    scancode, the character it types and the modifiers held at the time. When the
+    buffer is full new events are dropped and the overflow bit says so.
+
+    Scancodes are the PC's set 1: the key's code going down and the same with bit 7
+    set coming up (KEYS below, plus 0x2a and 0x36 shift, 0x1d ctrl, 0x38 alt). The
+    character is ASCII for a US layout, with shift picking the upper one of a key and
+    ctrl turning letters into 1 to 26. Enter is 10, backspace 8, tab 9 and escape 27.
+    Modifiers, key ups and keys that type nothing give 0.
+
+    Normally only key downs that type something are queued, which is all a program
+    after characters wants. Raw mode queues every event.
+
+    Its registers, from the start of its ram:
+
+    0   STATUS     bit 0 an event is waiting, bit 1 overflow, bits 2 to 4 the modifiers
+                   held right now (shift, ctrl, alt). Writing anything clears overflow.
+    1   DATA       the oldest event's character, reading it takes the event off the queue
+    2   SCANCODE   the oldest event's scancode
+    3   MODIFIERS  the modifiers held for the oldest event, bit 0 shift, 1 ctrl, 2 alt
+    4   COUNT      how many events are waiting, writing anything drops them all
+    5   CONTROL    bit 0 raw mode, bit 1 raises the keyboard irq when an event is queued
+                   (on after a reset)
+
+    The host presses keys with key_down and key_up, or types a character with
+    type_char, which presses shift as well if the character needs it. `input`, what
+    the sim and jcpu-run send, types a character.
+*/
+const KEYBOARD_STATUS: u8 = 0;
+const KEYBOARD_DATA: u8 = 1;
+const KEYBOARD_SCANCODE: u8 = 2;
+const KEYBOARD_MODIFIERS: u8 = 3;
+const KEYBOARD_COUNT: u8 = 4;
+const KEYBOARD_CONTROL: u8 = 5;
+
+pub const KEYBOARD_READY: u8 = 0b01;
+pub const KEYBOARD_OVERFLOW: u8 = 0b10;
+pub const KEYBOARD_RAW: u8 = 0b01;
+pub const KEYBOARD_IRQ: u8 = 0b10;
+
+pub const MOD_SHIFT: u8 = 0b001;
+pub const MOD_CTRL: u8 = 0b010;
+pub const MOD_ALT: u8 = 0b100;
+
+// set on a scancode when the key comes up
+pub const KEY_UP: u8 = 0x80;
+
+pub const SC_LSHIFT: u8 = 0x2a;
+pub const SC_RSHIFT: u8 = 0x36;
+pub const SC_CTRL: u8 = 0x1d;
+pub const SC_ALT: u8 = 0x38;
+
+// scancode, the character it types, the character with shift
+pub const KEYS: [(u8, u8, u8); 52] = [
+    (0x01, 27, 27),
+    (0x02, b'1', b'!'),
+    (0x03, b'2', b'@'),
+    (0x04, b'3', b'#'),
+    (0x05, b'4', b'$'),
+    (0x06, b'5', b'%'),
+    (0x07, b'6', b'^'),
+    (0x08, b'7', b'&'),
+    (0x09, b'8', b'*'),
+    (0x0a, b'9', b'('),
+    (0x0b, b'0', b')'),
+    (0x0c, b'-', b'_'),
+    (0x0d, b'=', b'+'),
+    (0x0e, 8, 8),
+    (0x0f, 9, 9),
+    (0x10, b'q', b'Q'),
+    (0x11, b'w', b'W'),
+    (0x12, b'e', b'E'),
+    (0x13, b'r', b'R'),
+    (0x14, b't', b'T'),
+    (0x15, b'y', b'Y'),
+    (0x16, b'u', b'U'),
+    (0x17, b'i', b'I'),
+    (0x18, b'o', b'O'),
+    (0x19, b'p', b'P'),
+    (0x1a, b'[', b'{'),
+    (0x1b, b']', b'}'),
+    (0x1c, 10, 10),
+    (0x1e, b'a', b'A'),
+    (0x1f, b's', b'S'),
+    (0x20, b'd', b'D'),
+    (0x21, b'f', b'F'),
+    (0x22, b'g', b'G'),
+    (0x23, b'h', b'H'),
+    (0x24, b'j', b'J'),
+    (0x25, b'k', b'K'),
+    (0x26, b'l', b'L'),
+    (0x27, b';', b':'),
+    (0x28, b'\'', b'"'),
+    (0x29, b'`', b'~'),
+    (0x2b, b'\\', b'|'),
+    (0x2c, b'z', b'Z'),
+    (0x2d, b'x', b'X'),
+    (0x2e, b'c', b'C'),
+    (0x2f, b'v', b'V'),
+    (0x30, b'b', b'B'),
+    (0x31, b'n', b'N'),
+    (0x32, b'm', b'M'),
+    (0x33, b',', b'<'),
+    (0x34, b'.', b'>'),
+    (0x35, b'/', b'?'),
+    (0x39, b' ', b' '),
+];
+
+// The key that types c and whether it needs shift, None when no key does
+pub fn key_of(c: u8) -> Option<(u8, bool)> {
+    KEYS.iter().find_map(|&(scancode, plain, shifted)| {
+        if c == plain {
+            Some((scancode, false))
+        } else if c == shifted {
+            Some((scancode, true))
+        } else {
+            None
+        }
+    })
+}
+
+fn modifier(scancode: u8) -> u8 {
+    match scancode {
+        SC_LSHIFT | SC_RSHIFT => MOD_SHIFT,
+        SC_CTRL => MOD_CTRL,
+        SC_ALT => MOD_ALT,
+        _ => 0,
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct KeyEvent {
+    pub scancode: u8,
+    pub char: u8,
+    pub modifiers: u8,
+}
+
+impl KeyEvent {
+    pub fn is_up(&self) -> bool {
+        self.scancode & KEY_UP > 0
+    }
+}
+
+pub struct Keyboard {
+    pub events: VecDeque<KeyEvent>,
+    // how many events the buffer holds, the rest are dropped
+    pub size: usize,
+    // the modifiers held down right now
+    pub modifiers: u8,
+    pub control: u8,
+    pub overflow: bool,
+    // set when an event is queued with the irq on, the next tick raises the keyboard irq
+    pub key_waiting: bool,
+}
+
+impl Keyboard {
+    pub fn new(size: usize) -> Self {
+        Self { events: VecDeque::new(), size, modifiers: 0, control: KEYBOARD_IRQ, overflow: false, key_waiting: false }
+    }
+
+    pub fn key_down(&mut self, scancode: u8) {
+        self.key(scancode & !KEY_UP, true);
+    }
+
+    pub fn key_up(&mut self, scancode: u8) {
+        self.key(scancode & !KEY_UP, false);
+    }
+
+    // Presses and lets go of the key for c, with shift around it if c needs it. Characters no key types are ignored.
+    pub fn type_char(&mut self, c: u8) {
+        let (scancode, shift) = match key_of(c) {
+            Some(key) => key,
+            None => return,
+        };
+        let press_shift = shift && self.modifiers & MOD_SHIFT == 0;
+
+        if press_shift {
+            self.key_down(SC_LSHIFT);
+        }
+        self.key_down(scancode);
+        self.key_up(scancode);
+        if press_shift {
+            self.key_up(SC_LSHIFT);
+        }
+    }
+
+    // type_char with ctrl and alt held as modifiers says, shift goes by the character
+    pub fn type_key(&mut self, c: u8, modifiers: u8) {
+        let held: Vec<u8> = [(MOD_CTRL, SC_CTRL), (MOD_ALT, SC_ALT)]
+            .iter()
+            .filter(|(bit, _)| modifiers & bit > 0 && self.modifiers & bit == 0)
+            .map(|(_, scancode)| *scancode)
+            .collect();
+
+        held.iter().for_each(|scancode| self.key_down(*scancode));
+        self.type_char(c);
+        held.iter().rev().for_each(|scancode| self.key_up(*scancode));
+    }
+
+    fn key(&mut self, scancode: u8, down: bool) {
+        let bit = modifier(scancode);
+        if down {
+            self.modifiers |= bit;
+        } else {
+            self.modifiers &= !bit;
+        }
+
+        let event = KeyEvent {
+            scancode: if down { scancode } else { scancode | KEY_UP },
+            char: if down { self.char_of(scancode) } else { 0 },
+            modifiers: self.modifiers,
+        };
+        if self.control & KEYBOARD_RAW > 0 || event.char > 0 {
+            self.push(event);
+        }
+    }
+
+    fn char_of(&self, scancode: u8) -> u8 {
+        let (plain, shifted) = match KEYS.iter().find(|key| key.0 == scancode) {
+            Some(&(_, plain, shifted)) => (plain, shifted),
+            None => return 0,
+        };
+
+        let c = if self.modifiers & MOD_SHIFT > 0 { shifted } else { plain };
+        if self.modifiers & MOD_CTRL > 0 && c.is_ascii_alphabetic() {
+            c & 0x1f
+        } else {
+            c
+        }
+    }
+
+    fn push(&mut self, event: KeyEvent) {
+        if self.events.len() >= self.size {
+            self.overflow = true;
+            return;
+        }
+
+        self.events.push_back(event);
+        if self.control & KEYBOARD_IRQ > 0 {
+            self.key_waiting = true;
+        }
+    }
+
+    fn status(&self) -> u8 {
+        let mut status = self.modifiers << 2;
+        if !self.events.is_empty() {
+            status |= KEYBOARD_READY;
+        }
+        if self.overflow {
+            status |= KEYBOARD_OVERFLOW;
+        }
+        status
+    }
+}
+
+impl Device for Keyboard {
+    fn name(&self) -> &'static str {
+        KEYBOARD_ID
+    }
+
+    fn reset(&mut self) {
+        *self = Self::new(self.size);
+    }
+
+    fn irq(&mut self) -> Option<u8> {
+        std::mem::take(&mut self.key_waiting).then_some(IRQ_KEYBOARD)
+    }
+
+    fn input(&mut self, value: u8) {
+        self.type_char(value);
+    }
+
+    fn mmio_peek(&self, offset: u8) -> u8 {
+        let oldest = self.events.front();
+        match offset {
+            KEYBOARD_STATUS => self.status(),
+            KEYBOARD_DATA => oldest.map_or(0, |e| e.char),
+            KEYBOARD_SCANCODE => oldest.map_or(0, |e| e.scancode),
+            KEYBOARD_MODIFIERS => oldest.map_or(self.modifiers, |e| e.modifiers),
+            KEYBOARD_COUNT => self.events.len() as u8,
+            KEYBOARD_CONTROL => self.control,
+            _ => 0,
+        }
+    }
+
+    fn mmio_read(&mut self, offset: u8) -> u8 {
+        let value = self.mmio_peek(offset);
+        if offset == KEYBOARD_DATA {
+            self.events.pop_front();
+        }
+
+        value
+    }
+
+    fn mmio_write(&mut self, offset: u8, value: u8) {
+        match offset {
+            KEYBOARD_STATUS => self.overflow = false,
+            KEYBOARD_COUNT => self.events.clear(),
+            KEYBOARD_CONTROL => self.control = value,
+            _ => {},
+        }
+    }
+
+    // control, the modifiers held, overflow, key_waiting, then three bytes an event
+    fn save_state(&self) -> Vec<u8> {
+        let mut state = vec![self.control, self.modifiers, self.overflow as u8, self.key_waiting as u8];
+        for event in self.events.iter() {
+            state.extend_from_slice(&[event.scancode, event.char, event.modifiers]);
+        }
+        state
+    }
+
+    fn check_state(&self, state: &[u8]) -> bool {
+        state.len() >= 4 && (state.len() - 4).is_multiple_of(3) && (state.len() - 4) / 3 <= self.size
+    }
+
+    fn load_state(&mut self, state: &[u8]) {
+        self.control = state[0];
+        self.modifiers = state[1];
+        self.overflow = state[2] > 0;
+        self.key_waiting = state[3] > 0;
+        self.events = state[4..].chunks(3).map(|e| KeyEvent { scancode: e[0], char: e[1], modifiers: e[2] }).collect();
+    }
+}
@@ -1,6 +1,22 @@
 pub mod helpers;
+pub mod config;
 pub mod ram;
+pub mod bus;
+pub mod device;
 pub mod cpu;
+pub mod font;
+pub mod keyboard;
 pub mod peripheral;
+pub mod timer;
+pub mod uart;
 pub mod motherboard;
 pub mod alu;
+pub mod interrupts;
+pub mod fault;
+pub mod snapshot;
+pub mod history;
+pub mod debugger;
+pub mod gdbstub;
+pub mod tracer;
+pub mod capture;
+pub mod microstep;
@@ -0,0 +1,143 @@
+use jcpuinstructions::spec_for;
+
+use crate::{fault::CpuFault, motherboard::Motherboard, tracer::TraceStart};
+
+/*
+    The clock steps of one instruction. Motherboard::cycle runs them all at once,
+    micro_step runs one so the registers can be watched between them:
+
+    Fetch         interrupts are taken, then MAR <- IAR
+    LoadIr        IR <- ram[MAR]
+    IncrementIar  IAR moves past the instruction and its operands
+    Execute       the instruction runs
+    AluLatch      the ALU outputs and flags show, only when the instruction used the ALU
+
+    Only Fetch and LoadIr are split out of the CPU for real. The CPU runs an
+    instruction in one go, moving IAR and latching the ALU as it does, so
+    IncrementIar and AluLatch are display steps: they name what the hardware would
+    be doing at that point but change nothing, and every register the instruction
+    touches changes at Execute.
+*/
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MicroPhase {
+    Fetch,
+    LoadIr,
+    IncrementIar,
+    Execute,
+    AluLatch,
+}
+
+impl MicroPhase {
+    pub fn name(&self) -> &'static str {
+        match self {
+            MicroPhase::Fetch => "fetch, MAR <- IAR",
+            MicroPhase::LoadIr => "load IR <- ram[MAR]",
+            MicroPhase::IncrementIar => "increment IAR",
+            MicroPhase::Execute => "execute",
+            MicroPhase::AluLatch => "ALU latch",
+        }
+    }
+}
+
+// What carries over between the steps of one instruction
+#[derive(Default)]
+pub(crate) struct MicroState {
+    // where the instruction being stepped starts
+    pub(crate) address: u8,
+    trace: Option<TraceStart>,
+}
+
+impl Motherboard {
+    // Run the next clock step, results are the same as cycle's
+    pub fn micro_step(&mut self) -> Result<bool, CpuFault> {
+        match self.phase {
+            MicroPhase::Fetch => {
+                self.record_history();
+                self.micro.trace = self.tracer.is_some().then(|| self.trace_start());
+
+                // a pending interrupt moves IAR to its handler before the fetch
+                if let Err(fault) = self.cpu.service_interrupt(&mut self.ram) {
+                    return self.fault_instruction(fault, self.cpu.reg_iar);
+                }
+
+                let address = self.cpu.reg_iar;
+                if address as usize >= self.ram.memory.len() {
+                    return self.fault_instruction(CpuFault::AddressOutOfRange(address as usize), address.wrapping_add(1));
+                }
+
+                self.micro.address = address;
+                self.cpu.reg_mar = address;
+                self.phase = MicroPhase::LoadIr;
+            },
+            MicroPhase::LoadIr => {
+                self.cpu.reg_ir = self.ram.fetch(self.cpu.reg_mar);
+                if let Some(mut start) = self.micro.trace.take() {
+                    start.fetched(self, self.micro.address);
+                    self.micro.trace = Some(start);
+                }
+
+                self.phase = MicroPhase::IncrementIar;
+            },
+            // display only, the CPU moves IAR itself when it executes
+            MicroPhase::IncrementIar => {
+                self.phase = MicroPhase::Execute;
+            },
+            MicroPhase::Execute => {
+                let address = self.micro.address;
+                let alu = self.cpu.alu.clone();
+
+                match self.cpu.cycle(&mut self.ram) {
+                    Ok(true) => {},
+                    Ok(false) => {
+                        self.end_instruction(None);
+                        return Ok(false);
+                    },
+                    // trap and ignore both carry on after the faulting instruction
+                    Err(fault) => return self.fault_instruction(fault, address.wrapping_add(self.instruction_size())),
+                }
+
+                self.cycle_i += 1;
+                // every instruction takes as many clock ticks as the ISA says
+                self.clock_i += spec_for(self.cpu.reg_ir).map_or(1, |spec| spec.cycles);
+
+                if self.cpu.alu != alu {
+                    self.phase = MicroPhase::AluLatch;
+                } else {
+                    self.end_instruction(None);
+                }
+            },
+            // display only, the ALU already latched during execute
+            MicroPhase::AluLatch => {
+                self.end_instruction(None);
+            },
+        }
+
+        Ok(true)
+    }
+
+    fn instruction_size(&self) -> u8 {
+        spec_for(self.cpu.reg_ir).map_or(1, |spec| spec.size) as u8
+    }
+
+    fn fault_instruction(&mut self, fault: CpuFault, resume: u8) -> Result<bool, CpuFault> {
+        let result = self.handle_fault(fault, resume);
+        self.end_instruction(Some(fault));
+
+        result
+    }
+
+    fn end_instruction(&mut self, fault: Option<CpuFault>) {
+        self.phase = MicroPhase::Fetch;
+
+        if let Some(start) = self.micro.trace.take() {
+            self.trace_end(start, fault);
+        }
+    }
+
+    // Drop a half run instruction, for loads and stepping back
+    pub(crate) fn reset_micro(&mut self) {
+        self.phase = MicroPhase::Fetch;
+        self.micro = MicroState::default();
+    }
+}
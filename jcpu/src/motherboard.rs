@@ -1,23 +1,27 @@
-use std::collections::HashMap;
-
-use crate::{ram::Ram, helpers, cpu::CPU, peripheral::{Peripheral, PeripheralTrait, Screen, Keyboard}};
-
-pub const SCREEN_WIDTH: u8 = 8;
-pub const SCREEN_HEIGHT: u8 = 8;
-pub const KEYBOARD_ADDRESS: u8 = 0;
-pub const KEYBOARD_RAM: u8 = 10;
-pub const GPU_RAM: u8 = 3;
-const RESERVED_RAM: u8 = 2;
-const BIN_SIZE: usize = 10 * 12;
-const PERIPHERALS: usize = (KEYBOARD_ADDRESS + KEYBOARD_RAM + GPU_RAM + RESERVED_RAM) as usize;
-pub const BOOT_ADDR: usize = PERIPHERALS; // ADDRESS Starts after PERIPHERALS
-pub const STACK_ADDR: usize = BIN_SIZE + PERIPHERALS; // Stack starts after binary size and peripherals
+use crate::{ram::Ram, helpers, cpu::CPU, alu::FLAG_INT, config::{ConfigError, MachineConfig, PeripheralConfig}, fault::{CpuFault, FaultPolicies, FaultPolicy}, history::History, microstep::{MicroPhase, MicroState}, tracer::Tracer, device::{Attached, Device}, keyboard::Keyboard, peripheral::Screen, timer::Timer, uart::Uart};
 
+// The layout (ram size, where the boot image, banks, stack and peripherals sit) comes
+// from a MachineConfig, see config.rs for the profile format and the default board.
 pub struct Motherboard {
-    cycle_i: usize,
+    pub(crate) cycle_i: usize,
+    pub(crate) clock_i: usize,
     pub cpu: CPU,
+    pub fault_policies: FaultPolicies,
+    // the last fault, whatever its policy did with it
+    pub fault: Option<CpuFault>,
     pub ram: Ram,
-    pub peripherals: HashMap<&'static str, Peripheral>,
+    // plugged in with attach, see device.rs
+    pub(crate) devices: Vec<Attached>,
+    // the cycle the devices last ticked at
+    pub(crate) ticked: usize,
+    // the last cycles, for stepping back
+    pub history: History,
+    // off unless set
+    pub tracer: Option<Tracer>,
+    // the clock step micro_step runs next
+    pub phase: MicroPhase,
+    pub(crate) micro: MicroState,
+    pub config: MachineConfig,
     bootimg: String,
     instructions: String
 }
@@ -26,66 +30,63 @@ pub struct Motherboard {
 // Send cpu instructions to do as cycles
 impl Motherboard {
     pub fn new(bootfile: &str, instructions: &str) -> Motherboard {
-        Motherboard {
-            cycle_i: 0,
-            cpu: CPU::new(),            // new CPU with 3 general purpose registers
-            ram: Ram::new(),         // 256 bytes of ram - STYLING!
-            peripherals: HashMap::new(),
-            bootimg: bootfile.to_string(),
-            instructions: instructions.to_string()
+        // nothing on the default board has a backend to open
+        match Self::with_config(bootfile, instructions, MachineConfig::default()) {
+            Ok(mb) => mb,
+            Err(e) => panic!("[motherboard] {}", e),
         }
     }
 
-    pub fn process_peripherals(&mut self) {
-        for (_, peripheral) in self.peripherals.iter_mut() {
-            match peripheral {
-                Peripheral::Screen(a) => a.process(&mut self.cpu, &mut self.ram),
-                Peripheral::Keyboard(a) => a.process(&mut self.cpu, &mut self.ram),
-            }
-        }
-    }
-
-    pub fn pass_to_peripheral(&mut self, perf: &str, value: u8) {
-        for (_, peripheral) in self.peripherals.iter_mut() {
-            match peripheral {
-                Peripheral::Screen(a) => {
-                    if perf == "screen" {
-                        a.update(value)
-                    }
-                },
-                Peripheral::Keyboard(a) => {
-                    if perf == "keyboard" {
-                        a.update(value)
-                    }
+    // A board laid out by config with its peripherals plugged in, config is expected to be validated.
+    // Fails when a peripheral can not be set up, like a uart whose backend does not open.
+    pub fn with_config(bootfile: &str, instructions: &str, config: MachineConfig) -> Result<Motherboard, ConfigError> {
+        let mut mb = Motherboard {
+            cycle_i: 0,
+            clock_i: 0,
+            cpu: CPU::with_map(config.map),            // new CPU with 3 general purpose registers
+            fault_policies: FaultPolicies::default(),
+            fault: None,
+            ram: Ram::with_config(config.ram_size, config.map.bank),
+            devices: vec![],
+            ticked: 0,
+            history: History::default(),
+            tracer: None,
+            phase: MicroPhase::Fetch,
+            micro: MicroState::default(),
+            config,
+            bootimg: bootfile.to_string(),
+            instructions: instructions.to_string()
+        };
+
+        // each peripheral answers for its region of ram on the bus
+        for peripheral in mb.config.peripherals.clone() {
+            let region = peripheral.region();
+            let device: Box<dyn Device> = match peripheral {
+                PeripheralConfig::Keyboard { buffer, .. } => Box::new(Keyboard::new(buffer)),
+                PeripheralConfig::Screen { width, height, refresh, .. } => Box::new(Screen::new(width, height, refresh)),
+                PeripheralConfig::Timer { .. } => Box::new(Timer::new()),
+                PeripheralConfig::Uart { fifo, backend, .. } => match backend.open() {
+                    Ok(backend) => Box::new(Uart::new(fifo, backend)),
+                    Err(e) => return Err(ConfigError::Device(format!("the uart's backend did not open: {}", e))),
                 },
-            }
-        }
-    }
-
-    pub fn reset_peripherals(&mut self) {
-        for (_, peripheral) in self.peripherals.iter_mut() {
-            match peripheral {
-                Peripheral::Screen(a) => a.clear_state(),
-                Peripheral::Keyboard(a) => a.clear_state(),
-            }
+            };
+            mb.attach(device, Some(region.start..region.end)).map_err(|e| ConfigError::Device(e.to_string()))?;
         }
 
-        self.cpu.clearing = false;
+        Ok(mb)
     }
 
     pub fn ram_info(&self) -> &[u8] {
         &self.ram.memory
     }
 
-    pub fn kb_info(&self) -> &[u8] {
-        &self.ram.memory[0..10]
-    }
-
     pub fn mb_info(&self) -> Vec<(String,String)> {
         vec![
             ("Cycle".to_string(), format!("{}",self.cycle_i)),
+            ("Clock ticks".to_string(), format!("{}",self.clock_i)),
             ("Boot image size".to_string(), format!("{}",self.bootimg.len())),
-            ("Relative address".to_string(), format!("{}", (self.cpu.reg_mar as usize) - BOOT_ADDR))
+            ("Relative address".to_string(), format!("{}", (self.cpu.reg_mar as usize).saturating_sub(self.config.map.boot))),
+            ("Fault".to_string(), self.fault.map_or(String::from("none"), |fault| fault.to_string())),
         ]
     }
 
@@ -93,19 +94,38 @@ impl Motherboard {
         self.cpu.dbg_msg.clone()
     }
 
-    // if false stop cpu
-    pub fn cycle(&mut self) -> bool {
-        self.cpu.reg_mar = self.cpu.reg_iar;
-        self.cpu.reg_ir = self.ram.read(self.cpu.reg_mar);
-
-        if !self.cpu.cycle(&mut self.ram) {
-            return false;
+    // if false stop cpu, a fault only comes back when its policy is to halt
+    pub fn cycle(&mut self) -> Result<bool, CpuFault> {
+        // one instruction is every micro step up to the next fetch, a half stepped one is finished off
+        loop {
+            let result = self.micro_step();
+            if self.phase == MicroPhase::Fetch {
+                return result;
+            }
         }
+    }
 
-        self.cpu.reg_mar += 1;
-        self.cycle_i += 1;
-
-        true
+    // Apply the policy for the fault, resume is where a trap returns to or ignore carries on from
+    pub(crate) fn handle_fault(&mut self, fault: CpuFault, resume: u8) -> Result<bool, CpuFault> {
+        self.fault = Some(fault);
+        self.cpu.dbg_msg = format!("Fault: {}", fault);
+
+        match self.fault_policies.policy(&fault) {
+            FaultPolicy::Halt => Err(fault),
+            FaultPolicy::Trap => {
+                self.cpu.trap(&mut self.ram, fault, resume)?;
+                self.cycle_i += 1;
+
+                Ok(true)
+            },
+            FaultPolicy::Ignore => {
+                self.cpu.reg_iar = resume;
+                self.cpu.reg_mar = resume;
+                self.cycle_i += 1;
+
+                Ok(true)
+            },
+        }
     }
 
     pub fn cpu_state(&self) -> Vec<(String,String)> {
@@ -118,6 +138,12 @@ impl Motherboard {
             ("Left mouse".to_string(), "Cycle".to_string()),
             ("Right mouse".to_string(), "Reset".to_string()),
             ("Middle mouse".to_string(), "Exit".to_string()),
+            ("Left arrow".to_string(), "Step back".to_string()),
+            ("Right arrow".to_string(), "Micro step".to_string()),
+            ("F2".to_string(), "Toggle breakpoint".to_string()),
+            ("F8".to_string(), "Run to breakpoint".to_string()),
+            ("F5".to_string(), "Save snapshot".to_string()),
+            ("F9".to_string(), "Load snapshot".to_string()),
         ]
     }
 
@@ -132,8 +158,13 @@ impl Motherboard {
             ("Register MAR".to_string(), format!("{:02x}",self.cpu.reg_mar)),
             ("Register OUT ".to_string(), format!("{:02x}",self.cpu.reg_out)),
             ("Register SP ".to_string(), format!("{:02x}",self.cpu.reg_sp)),
+            ("Call depth  ".to_string(), format!("{}",self.cpu.call_depth)),
             ("Register INT ".to_string(), format!("{:02x}",self.cpu.reg_int)),
+            ("IRQ enabled ".to_string(), format!("{}",self.cpu.alu.flags & FLAG_INT > 0)),
+            ("IRQ pending ".to_string(), format!("{:08b}",self.cpu.interrupts.pending)),
+            ("IRQ active  ".to_string(), format!("{:?}",self.cpu.interrupts.active)),
             ("Clearing CLI".to_string(), format!("{}",self.cpu.clearing)),
+            ("Next step   ".to_string(), self.phase.name().to_string()),
         ]
     }
 
@@ -151,26 +182,45 @@ impl Motherboard {
             ("SUM Flag  ".to_string(), format!("{:02x}",self.cpu.alu.Sum)),
             ("SHIFT LEFT Flag  ".to_string(), format!("{:02x}",self.cpu.alu.Shl)),
             ("SHIFT RIGHT Flag  ".to_string(), format!("{:02x}",self.cpu.alu.Shr)),
-            ("FLAGS INT|R1|R2|LT|EQ|Z|S|C".to_string(), format!("{:08b}", self.cpu.alu.flags))
+            ("FLAGS INT|-|-|GT|EQ|Z|S|C".to_string(), format!("{:08b}", self.cpu.alu.flags))
         ]
     }
 
     pub fn boot(&mut self) {
         let boot_content = helpers::read_bin_vec(&self.bootimg);
 
+        self.load(boot_content);
+    }
+
+    // Put a compiled binary in ram and point the CPU at it. Whatever does not fit
+    // before the window goes into bank 0, then bank 1 and so on.
+    pub fn load(&mut self, boot_content: Vec<u8>) {
         self.cpu.dbg_msg = format!("bin size: {:?}", &boot_content.len());
-        if boot_content.len() > BIN_SIZE {
+        let map = self.config.map;
+        if boot_content.len() > self.config.image_size() {
             panic!("Compiled binary too large.")
         }
-        self.ram.fill(BOOT_ADDR as u8, boot_content);
-        self.cpu.reg_mar = BOOT_ADDR as u8;
+
+        self.ram.select_bank(0);
+        let (fixed, banked) = boot_content.split_at(boot_content.len().min(map.program));
+        self.ram.fill(map.boot as u8, fixed.to_vec());
+        for (bank, bytes) in banked.chunks(map.bank.size).enumerate() {
+            self.ram.fill_bank(bank as u8, bytes);
+        }
+
+        self.cpu.reg_mar = map.boot as u8;
         self.cpu.reg_iar = self.cpu.reg_mar;
+        self.history.clear();
+        self.reset_micro();
     }
 
     pub fn reset(&mut self) {
         self.cpu.reset();
         self.ram.reset();
         self.cycle_i = 0;
+        self.clock_i = 0;
+        self.ticked = 0;
+        self.fault = None;
         self.boot()
     }
 }
@@ -1,97 +1,262 @@
-use crate::{cpu::CPU, ram::Ram, motherboard::{KEYBOARD_ADDRESS, SCREEN_WIDTH, SCREEN_HEIGHT}};
+use crate::{device::Device, font};
 
-const KEYBOARD_ID: &str = "keyboard";
-const SCREEN_ID: &str = "screen";
-//@NOTE: PERIPHERAL ORDER, 1 = screen, 2 = keyboard, 3 = not used
+// the name the config plugs it in under
+pub const SCREEN_ID: &str = "screen";
 
-pub trait PeripheralTrait {
-    fn create(&mut self);
-    fn clear_state(&mut self);
-    fn process(&mut self, cpu: &mut CPU, ram: &mut Ram) {}
-    fn update(&mut self, value: u8) {}
-}
+/*
+    The screen, a framebuffer of width by height pixels in the 16 colours of PALETTE.
+    Pixels are stored row by row, (x, y) is at y * width + x. In text mode it shows a
+    grid of character cells instead, drawn with the font ROM (see font.rs), each with
+    its own foreground and background colour. The pixels and cells are kept here, the
+    gpu region of ram only holds the registers (even 32 by 24 would not fit in ram).
 
-pub enum Peripheral {
-    Screen(Screen),
-    Keyboard(Keyboard),
-}
+    Its registers, from the start of its ram:
 
-const MAX_BUFFERED_KEYS: u8 = 10;
-pub struct Keyboard {
-    pub keys_pressed: Vec<u8>,
-}
+    0   X       the pixel, or in text mode the cell
+    1   Y
+    2   COLOR   graphics: writing it sets the pixel at X, Y, reading gives that pixel back.
+                text: the colour CHAR writes with, foreground in the low 4 bits and
+                background in the high 4 bits.
+    3   CHAR    text: writing puts the character at X, Y and moves X on, wrapping onto the
+                next line and back to the top. 10 just starts a new line. Reading gives
+                the character at X, Y.
+    4   MODE    0 graphics, 1 text. Writing it clears the screen.
+    5   STATUS  bit 0 (vsync) is set every `refresh` cycles when a frame is drawn, reading
+                STATUS clears it
+*/
+const SCREEN_X: u8 = 0;
+const SCREEN_Y: u8 = 1;
+const SCREEN_COLOR: u8 = 2;
+const SCREEN_CHAR: u8 = 3;
+const SCREEN_MODE: u8 = 4;
+const SCREEN_STATUS: u8 = 5;
 
-pub fn get_key_code(c: char) -> u8 {
-    c as u8 - 32
+pub const MODE_GRAPHICS: u8 = 0;
+pub const MODE_TEXT: u8 = 1;
+pub const SCREEN_VSYNC: u8 = 0b1;
+// white on black
+const DEFAULT_ATTR: u8 = 0x0f;
+
+// The CGA colours, as RGB
+pub const PALETTE: [(u8, u8, u8); 16] = [
+    (0x00, 0x00, 0x00), // black
+    (0x00, 0x00, 0xaa), // blue
+    (0x00, 0xaa, 0x00), // green
+    (0x00, 0xaa, 0xaa), // cyan
+    (0xaa, 0x00, 0x00), // red
+    (0xaa, 0x00, 0xaa), // magenta
+    (0xaa, 0x55, 0x00), // brown
+    (0xaa, 0xaa, 0xaa), // light grey
+    (0x55, 0x55, 0x55), // dark grey
+    (0x55, 0x55, 0xff), // light blue
+    (0x55, 0xff, 0x55), // light green
+    (0x55, 0xff, 0xff), // light cyan
+    (0xff, 0x55, 0x55), // light red
+    (0xff, 0x55, 0xff), // light magenta
+    (0xff, 0xff, 0x55), // yellow
+    (0xff, 0xff, 0xff), // white
+];
+
+pub struct Screen {
+    pub width: u8,
+    pub height: u8,
+    // palette indexes, one per pixel
+    pub buffer: Vec<u8>,
+    // text mode, one per cell
+    pub chars: Vec<u8>,
+    pub attrs: Vec<u8>,
+    pub x: u8,
+    pub y: u8,
+    pub attr: u8,
+    pub mode: u8,
+    pub status: u8,
+    // cycles between frames, and since the last one
+    pub refresh: usize,
+    pub since_frame: usize,
+    // frames drawn since power on, what captures count by
+    pub frames: usize,
 }
 
-impl PeripheralTrait for Keyboard {
-    fn create(&mut self)  {
-        // Keyboard { pressed_state: false, keycode: None }
-        // @FIXME: we want to establish any buffer defaults etc. here, not actually create the peripheral
+impl Screen {
+    pub fn new(width: u8, height: u8, refresh: usize) -> Self {
+        let cells = (width as usize / font::CELL_WIDTH) * (height as usize / font::CELL_HEIGHT);
+
+        Self {
+            width,
+            height,
+            buffer: vec![0; width as usize * height as usize],
+            chars: vec![b' '; cells],
+            attrs: vec![DEFAULT_ATTR; cells],
+            x: 0,
+            y: 0,
+            attr: DEFAULT_ATTR,
+            mode: MODE_GRAPHICS,
+            status: 0,
+            refresh,
+            since_frame: 0,
+            frames: 0,
+        }
     }
 
-    fn process(&mut self, cpu: &mut CPU, ram: &mut Ram) {
-        if cpu.reg_int > 0 && cpu.reg_int == 2 {
-            if self.keys_pressed.len() > 0 {
-                for i in 0..self.keys_pressed.len() {
-                    ram.write(KEYBOARD_ADDRESS + i as u8, self.keys_pressed[i]); // write to ram
-                }
+    // the text mode grid
+    pub fn columns(&self) -> usize {
+        self.width as usize / font::CELL_WIDTH
+    }
 
-                cpu.reg_1 = KEYBOARD_ADDRESS;
-                cpu.reg_2 = self.keys_pressed.len() as u8;
+    pub fn rows(&self) -> usize {
+        self.height as usize / font::CELL_HEIGHT
+    }
+
+    // where X, Y is in the buffer, None off the screen
+    fn pixel(&self) -> Option<usize> {
+        let (x, y) = (self.x as usize, self.y as usize);
+        (x < self.width as usize && y < self.height as usize).then(|| y * self.width as usize + x)
+    }
+
+    // where X, Y is in the cells, None off the grid
+    fn cell(&self) -> Option<usize> {
+        let (x, y) = (self.x as usize, self.y as usize);
+        (x < self.columns() && y < self.rows()).then(|| y * self.columns() + x)
+    }
+
+    fn put_char(&mut self, c: u8) {
+        if c != b'\n' {
+            if let Some(cell) = self.cell() {
+                self.chars[cell] = c;
+                self.attrs[cell] = self.attr;
             }
+            self.x = self.x.wrapping_add(1);
         }
 
+        if c == b'\n' || self.x as usize >= self.columns() {
+            self.x = 0;
+            self.y = self.y.wrapping_add(1);
+        }
+        if self.y as usize >= self.rows() {
+            self.y = 0;
+        }
     }
 
-    fn update(&mut self, value:u8) {
-        if self.keys_pressed.len() < MAX_BUFFERED_KEYS.into() {
-            self.keys_pressed.push(value);
-        }
+    fn clear(&mut self) {
+        self.buffer.iter_mut().for_each(|p| *p = 0);
+        self.chars.iter_mut().for_each(|c| *c = b' ');
+        self.attrs.iter_mut().for_each(|a| *a = DEFAULT_ATTR);
+        self.x = 0;
+        self.y = 0;
     }
 
-    fn clear_state(&mut self) {
-        // @TODO: rememebr to clear the keyboard ram of keys
-        self.keys_pressed = vec![];
+    // What is on screen as palette indexes, row by row. Text mode is drawn with the font.
+    pub fn frame(&self) -> Vec<u8> {
+        if self.mode != MODE_TEXT {
+            return self.buffer.clone();
+        }
+
+        let mut frame = vec![0; self.buffer.len()];
+        for row in 0..self.rows() {
+            for col in 0..self.columns() {
+                let cell = row * self.columns() + col;
+                let (c, attr) = (self.chars[cell], self.attrs[cell]);
+
+                for gy in 0..font::CELL_HEIGHT {
+                    for gx in 0..font::CELL_WIDTH {
+                        let (x, y) = (col * font::CELL_WIDTH + gx, row * font::CELL_HEIGHT + gy);
+                        frame[y * self.width as usize + x] = if font::lit(c, gx, gy) { attr & 0x0f } else { attr >> 4 };
+                    }
+                }
+            }
+        }
+
+        frame
     }
-}
 
-pub struct Screen {
-    pub buffer: [u8; (SCREEN_WIDTH * SCREEN_HEIGHT) as usize],
-}
+    // text mode's characters, a line per row
+    pub fn text(&self) -> Vec<String> {
+        self.chars.chunks(self.columns().max(1)).map(|row| row.iter().map(|c| *c as char).collect()).collect()
+    }
 
-impl Screen {
     pub fn get_buffer(&mut self) -> Vec<u8> {
-        self.buffer.clone().into()
+        self.buffer.clone()
     }
 }
 
-impl PeripheralTrait for Screen {
-    fn create(&mut self) {
-        //println!("todo")
+// CLI leaves the picture up, so there is no reset
+impl Device for Screen {
+    fn name(&self) -> &'static str {
+        SCREEN_ID
+    }
+
+    fn tick(&mut self, cycles: usize) {
+        self.since_frame += cycles;
+        if self.since_frame >= self.refresh {
+            self.since_frame %= self.refresh;
+            self.status |= SCREEN_VSYNC;
+            self.frames += 1;
+        }
     }
 
-    fn process(&mut self, cpu: &mut CPU, ram: &mut Ram) {
-        if cpu.reg_int > 0 && cpu.reg_int == 1 {
-            // get x
-            let x = cpu.reg_1;
-            // get y
-            let y = cpu.reg_2;
-            // get color
-            let c = cpu.reg_3;
+    fn mmio_peek(&self, offset: u8) -> u8 {
+        match offset {
+            SCREEN_X => self.x,
+            SCREEN_Y => self.y,
+            SCREEN_COLOR if self.mode == MODE_TEXT => self.attr,
+            SCREEN_COLOR => self.pixel().map_or(0, |pos| self.buffer[pos]),
+            SCREEN_CHAR => self.cell().map_or(0, |cell| self.chars[cell]),
+            SCREEN_MODE => self.mode,
+            SCREEN_STATUS => self.status,
+            _ => 0,
+        }
+    }
 
-            let pos = y + (SCREEN_WIDTH * x);
-            // add it to the "screen" buffer
-            self.buffer[pos as usize] = c;
+    fn mmio_read(&mut self, offset: u8) -> u8 {
+        let value = self.mmio_peek(offset);
+        if offset == SCREEN_STATUS {
+            self.status = 0;
         }
+
+        value
+    }
+
+    fn mmio_write(&mut self, offset: u8, value: u8) {
+        match offset {
+            SCREEN_X => self.x = value,
+            SCREEN_Y => self.y = value,
+            SCREEN_COLOR if self.mode == MODE_TEXT => self.attr = value,
+            SCREEN_COLOR => {
+                if let Some(pos) = self.pixel() {
+                    self.buffer[pos] = value & 0x0f;
+                }
+            },
+            SCREEN_CHAR if self.mode == MODE_TEXT => self.put_char(value),
+            SCREEN_MODE => {
+                self.mode = value;
+                self.clear();
+            },
+            _ => {},
+        }
+    }
+
+    // the pixels, the characters, their colours, then the registers and the frame counter
+    fn save_state(&self) -> Vec<u8> {
+        let mut state = self.buffer.to_vec();
+        state.extend_from_slice(&self.chars);
+        state.extend_from_slice(&self.attrs);
+        state.extend_from_slice(&[self.x, self.y, self.attr, self.mode, self.status]);
+        state.extend_from_slice(&(self.since_frame as u32).to_le_bytes());
+        state
     }
 
-    fn update(&mut self, value: u8) {
-        //
+    fn check_state(&self, state: &[u8]) -> bool {
+        state.len() == self.buffer.len() + self.chars.len() * 2 + 9
     }
 
-    fn clear_state(&mut self) {
-        // print
+    fn load_state(&mut self, state: &[u8]) {
+        let (buffer, state) = state.split_at(self.buffer.len());
+        let (chars, state) = state.split_at(self.chars.len());
+        let (attrs, state) = state.split_at(self.attrs.len());
+        self.buffer.copy_from_slice(buffer);
+        self.chars.copy_from_slice(chars);
+        self.attrs.copy_from_slice(attrs);
+        [self.x, self.y, self.attr, self.mode, self.status] = [state[0], state[1], state[2], state[3], state[4]];
+        self.since_frame = u32::from_le_bytes([state[5], state[6], state[7], state[8]]) as usize;
     }
 }
@@ -1,29 +1,195 @@
+use crate::{bus::Bus, config::{BankConfig, MachineConfig}};
+
+/*
+    The memory controller. The CPU can only address 256 bytes, so the bank window
+    (see BankConfig) is backed by several banks and BANK picks which one shows through.
+    `memory` is always what the CPU sees, the other banks sit in `banks` until
+    they are selected.
+
+    Reads and writes to an address a device has mapped on the bus go to the device
+    (see bus.rs), `memory` only keeps a copy of its registers.
+
+    While `logging` is on every read and write goes into `log` for the debugger's
+    watchpoints. Instruction fetches go through `fetch` and are not logged.
+
+    While `journaling` is on every byte of `memory` or `banks` that changes goes into
+    `journal` with the value it had before, which is what the history keeps of a cycle.
+*/
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Access {
+    Read,
+    Write,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct RamAccess {
+    pub address: u8,
+    pub access: Access,
+    pub value: u8,
+}
+
+// A byte that changed and what it was before
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Change {
+    Memory { address: u8, before: u8 },
+    Bank { bank: u8, offset: usize, before: u8 },
+}
+
+#[derive(Clone)]
 pub struct Ram {
-    pub memory: [u8; 255]
+    pub memory: Vec<u8>,
+    pub banks: Vec<Vec<u8>>,
+    pub bank: u8,
+    pub window: BankConfig,
+    pub logging: bool,
+    pub log: Vec<RamAccess>,
+    pub journaling: bool,
+    pub journal: Vec<Change>,
+    pub bus: Bus,
 }
 
 impl Ram {
     pub fn new() -> Self {
+        let config = MachineConfig::default();
+        Self::with_config(config.ram_size, config.map.bank)
+    }
+
+    pub fn with_config(size: usize, window: BankConfig) -> Self {
+        if window.count == 0 {
+            panic!("[ram] at least one bank is needed")
+        }
+
         Self {
-            memory: [0; 255]
+            memory: vec![0; size],
+            banks: vec![vec![0; window.size]; window.count],
+            bank: 0,
+            window,
+            logging: false,
+            log: vec![],
+            journaling: false,
+            journal: vec![],
+            bus: Bus::default(),
+        }
+    }
+
+    pub fn read(&mut self, address: u8) -> u8 {
+        let value = match self.bus.mapping(address) {
+            Some(m) => m.device.borrow_mut().mmio_read(address - m.start as u8),
+            None => self.memory[address as usize],
+        };
+        self.sync_mmio(address);
+        if self.logging {
+            self.log.push(RamAccess { address, access: Access::Read, value });
         }
+
+        value
     }
-    pub fn read(&self, address: u8) -> u8 {
+
+    pub fn fetch(&self, address: u8) -> u8 {
         self.memory[address as usize]
     }
 
     pub fn write(&mut self, address: u8, data: u8) {
-        self.memory[address as usize] = data
+        if self.logging {
+            self.log.push(RamAccess { address, access: Access::Write, value: data });
+        }
+
+        match self.bus.mapping(address) {
+            Some(m) => m.device.borrow_mut().mmio_write(address - m.start as u8, data),
+            None => self.set(address as usize, data),
+        }
+        self.sync_mmio(address);
+    }
+
+    // Copy the registers of the device mapped at address (if any) into memory,
+    // a read or write can change more than the one byte
+    fn sync_mmio(&mut self, address: u8) {
+        let registers: Vec<(usize, u8)> = match self.bus.mapping(address) {
+            Some(m) => {
+                let device = m.device.borrow();
+                (m.start..m.end).map(|a| (a, device.mmio_peek((a - m.start) as u8))).collect()
+            },
+            None => return,
+        };
+
+        for (address, value) in registers {
+            self.set(address, value);
+        }
+    }
+
+    fn set(&mut self, address: usize, value: u8) {
+        let before = self.memory[address];
+        if before != value {
+            if self.journaling {
+                self.journal.push(Change::Memory { address: address as u8, before });
+            }
+            self.memory[address] = value;
+        }
+    }
+
+    fn set_bank(&mut self, bank: u8, offset: usize, value: u8) {
+        let before = self.banks[bank as usize][offset];
+        if before != value {
+            if self.journaling {
+                self.journal.push(Change::Bank { bank, offset, before });
+            }
+            self.banks[bank as usize][offset] = value;
+        }
+    }
+
+    // Refresh every mapped device, for when one changed outside a read or write
+    pub fn sync_bus(&mut self) {
+        let starts: Vec<usize> = self.bus.mappings.iter().map(|m| m.start).collect();
+        for start in starts {
+            self.sync_mmio(start as u8);
+        }
     }
 
     pub fn fill(&mut self, address: u8, bytes: Vec<u8>) {
 
         for bite in address as usize ..address as usize + bytes.len() {
-            self.memory[bite] = bytes[bite - address as usize ];
+            self.set(bite, bytes[bite - address as usize ]);
+        }
+    }
+
+    pub fn bank_count(&self) -> usize {
+        self.banks.len()
+    }
+
+    // Swap the window out to its bank and the selected bank in
+    pub fn select_bank(&mut self, bank: u8) {
+        if bank as usize >= self.banks.len() {
+            panic!("[ram] unknown bank {}", bank)
+        }
+
+        let start = self.window.address;
+        for offset in 0..self.window.size {
+            self.set_bank(self.bank, offset, self.memory[start + offset]);
+        }
+        for offset in 0..self.window.size {
+            self.set(start + offset, self.banks[bank as usize][offset]);
+        }
+        self.bank = bank;
+    }
+
+    // Write straight into a bank, whether or not it is selected
+    pub fn fill_bank(&mut self, bank: u8, bytes: &[u8]) {
+        if bank == self.bank {
+            self.fill(self.window.address as u8, bytes.to_vec());
+        } else {
+            for (offset, byte) in bytes.iter().enumerate() {
+                self.set_bank(bank, offset, *byte);
+            }
         }
     }
 
     pub fn reset(&mut self) {
-        self.memory = [0; 255]
+        self.memory.iter_mut().for_each(|byte| *byte = 0);
+        self.banks.iter_mut().for_each(|bank| bank.iter_mut().for_each(|byte| *byte = 0));
+        self.bank = 0;
+        self.log = vec![];
+        self.journal = vec![];
+        // the devices keep their registers
+        self.sync_bus();
     }
 }
@@ -0,0 +1,402 @@
+use std::{fmt, fs};
+
+use crate::{
+    fault::{CpuFault, FaultPolicies, FaultPolicy},
+    microstep::MicroPhase,
+    motherboard::Motherboard,
+};
+
+/*
+    A whole machine in one file: the CPU registers, ALU latches and flags, ram with
+    every bank, the cycle counters, the fault state and each peripheral's own state.
+
+    A machine stopped between micro steps saves the step it is on, loading it
+    carries on from there.
+
+    The file starts with MAGIC and a version byte. Everything after it is written in
+    a fixed order, numbers little endian. Bump VERSION whenever that order changes,
+    older files are refused rather than read wrong.
+
+    Peripherals are stored by name and restored into the peripherals of the same
+    name, so the board loading a snapshot needs the same ones plugged in.
+*/
+
+pub const MAGIC: &[u8; 4] = b"JSNP";
+pub const VERSION: u8 = 5;
+
+#[derive(Debug)]
+pub enum SnapshotError {
+    Io(String),
+    BadMagic,
+    UnsupportedVersion(u8),
+    // the file ended before everything was read
+    Truncated,
+    Invalid(String),
+    MissingPeripheral(String),
+}
+
+impl fmt::Display for SnapshotError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            SnapshotError::Io(e) => write!(f, "{}", e),
+            SnapshotError::BadMagic => write!(f, "not a jcpu snapshot"),
+            SnapshotError::UnsupportedVersion(v) => write!(f, "snapshot version {} is not supported (expected {})", v, VERSION),
+            SnapshotError::Truncated => write!(f, "snapshot is truncated"),
+            SnapshotError::Invalid(what) => write!(f, "invalid snapshot: {}", what),
+            SnapshotError::MissingPeripheral(name) => write!(f, "snapshot needs a peripheral named {}", name),
+        }
+    }
+}
+
+// Appends values in snapshot order
+#[derive(Default)]
+pub struct SnapshotWriter {
+    pub bytes: Vec<u8>,
+}
+
+impl SnapshotWriter {
+    pub fn u8(&mut self, value: u8) {
+        self.bytes.push(value);
+    }
+
+    pub fn bool(&mut self, value: bool) {
+        self.u8(value as u8);
+    }
+
+    pub fn u32(&mut self, value: usize) {
+        self.bytes.extend_from_slice(&(value as u32).to_le_bytes());
+    }
+
+    pub fn u64(&mut self, value: usize) {
+        self.bytes.extend_from_slice(&(value as u64).to_le_bytes());
+    }
+
+    // length first
+    pub fn bytes(&mut self, bytes: &[u8]) {
+        self.u32(bytes.len());
+        self.bytes.extend_from_slice(bytes);
+    }
+
+    pub fn string(&mut self, value: &str) {
+        self.bytes(value.as_bytes());
+    }
+}
+
+// Reads values back in the order SnapshotWriter wrote them
+pub struct SnapshotReader<'a> {
+    bytes: &'a [u8],
+    pos: usize,
+}
+
+impl<'a> SnapshotReader<'a> {
+    pub fn new(bytes: &'a [u8]) -> Self {
+        Self { bytes, pos: 0 }
+    }
+
+    fn take(&mut self, len: usize) -> Result<&'a [u8], SnapshotError> {
+        if self.pos + len > self.bytes.len() {
+            return Err(SnapshotError::Truncated);
+        }
+
+        let taken = &self.bytes[self.pos..self.pos + len];
+        self.pos += len;
+
+        Ok(taken)
+    }
+
+    pub fn u8(&mut self) -> Result<u8, SnapshotError> {
+        Ok(self.take(1)?[0])
+    }
+
+    pub fn bool(&mut self) -> Result<bool, SnapshotError> {
+        Ok(self.u8()? > 0)
+    }
+
+    pub fn u32(&mut self) -> Result<usize, SnapshotError> {
+        let bytes = self.take(4)?;
+        Ok(u32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]) as usize)
+    }
+
+    pub fn u64(&mut self) -> Result<usize, SnapshotError> {
+        let mut bytes = [0; 8];
+        bytes.copy_from_slice(self.take(8)?);
+        Ok(u64::from_le_bytes(bytes) as usize)
+    }
+
+    pub fn bytes(&mut self) -> Result<&'a [u8], SnapshotError> {
+        let len = self.u32()?;
+        self.take(len)
+    }
+
+    pub fn string(&mut self) -> Result<String, SnapshotError> {
+        String::from_utf8(self.bytes()?.to_vec()).map_err(|_| SnapshotError::Invalid(String::from("string is not utf8")))
+    }
+
+    // ram and the banks are sized by the machine profile, they must match exactly
+    pub fn exact(&mut self, len: usize) -> Result<&'a [u8], SnapshotError> {
+        let bytes = self.bytes()?;
+        if bytes.len() != len {
+            return Err(SnapshotError::Invalid(format!("expected {} bytes, found {}", len, bytes.len())));
+        }
+
+        Ok(bytes)
+    }
+
+    pub fn finished(&self) -> bool {
+        self.pos == self.bytes.len()
+    }
+}
+
+fn write_fault(w: &mut SnapshotWriter, fault: &Option<CpuFault>) {
+    match fault {
+        None => w.u8(0),
+        Some(fault) => {
+            w.u8(fault.code());
+            match fault {
+                CpuFault::IllegalOpcode(op) => w.u32(*op as usize),
+                CpuFault::BadRegister(reg) => w.u32(*reg as usize),
+                CpuFault::AddressOutOfRange(address) => w.u32(*address),
+                CpuFault::StackOverflow | CpuFault::StackUnderflow => w.u32(0),
+            }
+        },
+    }
+}
+
+fn read_fault(r: &mut SnapshotReader) -> Result<Option<CpuFault>, SnapshotError> {
+    let code = r.u8()?;
+    if code == 0 {
+        return Ok(None);
+    }
+
+    let detail = r.u32()?;
+    let fault = match code {
+        1 => CpuFault::IllegalOpcode(detail as u8),
+        2 => CpuFault::StackOverflow,
+        3 => CpuFault::StackUnderflow,
+        4 => CpuFault::BadRegister(detail as u8),
+        5 => CpuFault::AddressOutOfRange(detail),
+        _ => return Err(SnapshotError::Invalid(format!("unknown fault code {}", code))),
+    };
+
+    Ok(Some(fault))
+}
+
+fn write_policy(w: &mut SnapshotWriter, policy: FaultPolicy) {
+    w.u8(match policy {
+        FaultPolicy::Halt => 0,
+        FaultPolicy::Trap => 1,
+        FaultPolicy::Ignore => 2,
+    });
+}
+
+fn read_policy(r: &mut SnapshotReader) -> Result<FaultPolicy, SnapshotError> {
+    match r.u8()? {
+        0 => Ok(FaultPolicy::Halt),
+        1 => Ok(FaultPolicy::Trap),
+        2 => Ok(FaultPolicy::Ignore),
+        p => Err(SnapshotError::Invalid(format!("unknown fault policy {}", p))),
+    }
+}
+
+fn write_phase(w: &mut SnapshotWriter, phase: MicroPhase) {
+    w.u8(match phase {
+        MicroPhase::Fetch => 0,
+        MicroPhase::LoadIr => 1,
+        MicroPhase::IncrementIar => 2,
+        MicroPhase::Execute => 3,
+        MicroPhase::AluLatch => 4,
+    });
+}
+
+fn read_phase(r: &mut SnapshotReader) -> Result<MicroPhase, SnapshotError> {
+    match r.u8()? {
+        0 => Ok(MicroPhase::Fetch),
+        1 => Ok(MicroPhase::LoadIr),
+        2 => Ok(MicroPhase::IncrementIar),
+        3 => Ok(MicroPhase::Execute),
+        4 => Ok(MicroPhase::AluLatch),
+        p => Err(SnapshotError::Invalid(format!("unknown micro step {}", p))),
+    }
+}
+
+impl Motherboard {
+    pub fn save_snapshot(&self) -> Vec<u8> {
+        let mut w = SnapshotWriter::default();
+        w.bytes.extend_from_slice(MAGIC);
+        w.u8(VERSION);
+
+        // motherboard
+        w.u64(self.cycle_i);
+        w.u64(self.ticked);
+        w.u64(self.clock_i);
+        write_fault(&mut w, &self.fault);
+        let p = &self.fault_policies;
+        for policy in [p.illegal_opcode, p.stack_overflow, p.stack_underflow, p.bad_register, p.address_out_of_range] {
+            write_policy(&mut w, policy);
+        }
+        write_phase(&mut w, self.phase);
+        w.u8(self.micro.address);
+
+        // cpu
+        let cpu = &self.cpu;
+        for reg in [cpu.reg_1, cpu.reg_2, cpu.reg_3, cpu.reg_4, cpu.reg_iar, cpu.reg_mar, cpu.reg_ir, cpu.reg_out, cpu.reg_sp, cpu.reg_int] {
+            w.u8(reg);
+        }
+        w.u32(cpu.call_depth);
+        w.u8(cpu.interrupts.pending);
+        w.bytes(&cpu.interrupts.active);
+        w.bool(cpu.clearing);
+        w.string(&cpu.dbg_msg);
+
+        // alu
+        let alu = &cpu.alu;
+        for latch in [alu.A, alu.B, alu.Or, alu.And, alu.Not, alu.Shl, alu.Shr, alu.Sum, alu.flags] {
+            w.u8(latch);
+        }
+
+        // ram
+        w.bytes(&self.ram.memory);
+        w.u8(self.ram.bank);
+        w.u32(self.ram.banks.len());
+        for bank in self.ram.banks.iter() {
+            w.bytes(bank);
+        }
+
+        // peripherals, sorted so the same machine always gives the same file
+        let mut devices: Vec<(&'static str, Vec<u8>)> = self
+            .devices
+            .iter()
+            .map(|a| {
+                let device = a.device.borrow();
+                (device.name(), device.save_state())
+            })
+            .collect();
+        devices.sort_by_key(|d| d.0);
+        w.u32(devices.len());
+        for (name, state) in devices {
+            w.string(name);
+            w.bytes(&state);
+        }
+
+        w.bytes
+    }
+
+    // Nothing changes unless the whole snapshot reads back
+    pub fn load_snapshot(&mut self, bytes: &[u8]) -> Result<(), SnapshotError> {
+        let mut r = SnapshotReader::new(bytes);
+        if r.take(MAGIC.len()).map_err(|_| SnapshotError::BadMagic)? != MAGIC {
+            return Err(SnapshotError::BadMagic);
+        }
+        let version = r.u8()?;
+        if version != VERSION {
+            return Err(SnapshotError::UnsupportedVersion(version));
+        }
+
+        let cycle_i = r.u64()?;
+        let ticked = r.u64()?;
+        if ticked > cycle_i {
+            return Err(SnapshotError::Invalid(format!("peripherals ticked at cycle {}, ahead of cycle {}", ticked, cycle_i)));
+        }
+        let clock_i = r.u64()?;
+        let fault = read_fault(&mut r)?;
+        let fault_policies = FaultPolicies {
+            illegal_opcode: read_policy(&mut r)?,
+            stack_overflow: read_policy(&mut r)?,
+            stack_underflow: read_policy(&mut r)?,
+            bad_register: read_policy(&mut r)?,
+            address_out_of_range: read_policy(&mut r)?,
+        };
+        let phase = read_phase(&mut r)?;
+        let micro_address = r.u8()?;
+
+        let mut regs = [0; 10];
+        for reg in regs.iter_mut() {
+            *reg = r.u8()?;
+        }
+        let call_depth = r.u32()?;
+        let pending = r.u8()?;
+        let active = r.bytes()?.to_vec();
+        let clearing = r.bool()?;
+        let dbg_msg = r.string()?;
+
+        let mut latches = [0; 9];
+        for latch in latches.iter_mut() {
+            *latch = r.u8()?;
+        }
+
+        let memory = r.exact(self.ram.memory.len())?.to_vec();
+        let bank = r.u8()?;
+        let bank_count = r.u32()?;
+        if bank_count != self.ram.bank_count() {
+            return Err(SnapshotError::Invalid(format!("{} ram banks saved, this board has {}", bank_count, self.ram.bank_count())));
+        }
+        if bank as usize >= bank_count {
+            return Err(SnapshotError::Invalid(format!("bank {} selected out of {}", bank, bank_count)));
+        }
+        let mut banks = Vec::with_capacity(bank_count);
+        for _ in 0..bank_count {
+            banks.push(r.exact(self.ram.window.size)?.to_vec());
+        }
+
+        let mut peripherals = Vec::new();
+        for _ in 0..r.u32()? {
+            let name = r.string()?;
+            if self.find(&name).is_none() {
+                return Err(SnapshotError::MissingPeripheral(name));
+            }
+            peripherals.push((name, r.bytes()?));
+        }
+
+        if !r.finished() {
+            return Err(SnapshotError::Invalid(String::from("trailing bytes")));
+        }
+
+        // check every peripheral takes its state before touching anything
+        for (name, state) in peripherals.iter() {
+            if !self.shared_device(name).unwrap().borrow().check_state(state) {
+                return Err(SnapshotError::Invalid(format!("bad state for peripheral {}", name)));
+            }
+        }
+        for (name, state) in peripherals {
+            self.shared_device(&name).unwrap().borrow_mut().load_state(state);
+        }
+
+        self.cycle_i = cycle_i;
+        self.ticked = ticked;
+        self.clock_i = clock_i;
+        self.fault = fault;
+        self.fault_policies = fault_policies;
+
+        let cpu = &mut self.cpu;
+        [cpu.reg_1, cpu.reg_2, cpu.reg_3, cpu.reg_4, cpu.reg_iar, cpu.reg_mar, cpu.reg_ir, cpu.reg_out, cpu.reg_sp, cpu.reg_int] = regs;
+        cpu.call_depth = call_depth;
+        cpu.interrupts.pending = pending;
+        cpu.interrupts.active = active;
+        cpu.clearing = clearing;
+        cpu.dbg_msg = dbg_msg;
+
+        let alu = &mut cpu.alu;
+        [alu.A, alu.B, alu.Or, alu.And, alu.Not, alu.Shl, alu.Shr, alu.Sum, alu.flags] = latches;
+
+        self.ram.memory = memory;
+        self.ram.bank = bank;
+        self.ram.banks = banks;
+        self.ram.sync_bus();
+        // the recorded cycles belong to the machine that was replaced
+        self.history.clear();
+        self.reset_micro();
+        self.phase = phase;
+        self.micro.address = micro_address;
+
+        Ok(())
+    }
+
+    pub fn save_snapshot_file(&self, path: &str) -> Result<(), SnapshotError> {
+        fs::write(path, self.save_snapshot()).map_err(|e| SnapshotError::Io(format!("failed to write {}: {}", path, e)))
+    }
+
+    pub fn load_snapshot_file(&mut self, path: &str) -> Result<(), SnapshotError> {
+        let bytes = fs::read(path).map_err(|e| SnapshotError::Io(format!("failed to read {}: {}", path, e)))?;
+        self.load_snapshot(&bytes)
+    }
+}
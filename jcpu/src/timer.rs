@@ -0,0 +1,144 @@
+use crate::{device::Device, interrupts::IRQ_TIMER};
+
+pub const TIMER_ID: &str = "timer";
+
+/*
+    A programmable interval timer. While it runs, the counter goes down by one every
+    prescaler + 1 cycles of the motherboard, and when it gets to 0 the timer expires:
+    the status bit is set and, if asked for, the timer irq is raised. A periodic timer
+    then starts again from reload, a one shot one stops. With a reload of 0 it expires
+    every time the counter would move.
+
+    Its registers, from the start of its ram:
+
+    0   CONTROL     bit 0 runs it, bit 1 periodic (one shot when clear), bit 2 raises the irq.
+                    Starting it loads the counter from reload.
+    1   STATUS      bit 0 is set when it expires, writing anything clears it
+    2   RELOAD      writing it loads the counter as well
+    3   PRESCALER
+    4   COUNTER     what is left, writing it sets it
+*/
+const TIMER_CONTROL: u8 = 0;
+const TIMER_STATUS: u8 = 1;
+const TIMER_RELOAD: u8 = 2;
+const TIMER_PRESCALER: u8 = 3;
+const TIMER_COUNTER: u8 = 4;
+
+pub const TIMER_RUN: u8 = 0b001;
+pub const TIMER_PERIODIC: u8 = 0b010;
+pub const TIMER_IRQ: u8 = 0b100;
+pub const TIMER_EXPIRED: u8 = 0b001;
+
+#[derive(Default)]
+pub struct Timer {
+    pub control: u8,
+    pub status: u8,
+    pub reload: u8,
+    pub prescaler: u8,
+    pub counter: u8,
+    // cycles since the counter last moved
+    pub prescale: u8,
+    // set on expiry, the next irq raises it
+    pub irq_waiting: bool,
+}
+
+impl Timer {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn running(&self) -> bool {
+        self.control & TIMER_RUN > 0
+    }
+
+    fn expire(&mut self) {
+        self.status |= TIMER_EXPIRED;
+        if self.control & TIMER_IRQ > 0 {
+            self.irq_waiting = true;
+        }
+
+        if self.control & TIMER_PERIODIC > 0 {
+            self.counter = self.reload;
+        } else {
+            self.control &= !TIMER_RUN;
+        }
+    }
+}
+
+impl Device for Timer {
+    fn name(&self) -> &'static str {
+        TIMER_ID
+    }
+
+    fn reset(&mut self) {
+        *self = Self::new();
+    }
+
+    fn tick(&mut self, cycles: usize) {
+        for _ in 0..cycles {
+            if !self.running() {
+                return;
+            }
+
+            if self.prescale < self.prescaler {
+                self.prescale += 1;
+                continue;
+            }
+            self.prescale = 0;
+
+            self.counter = self.counter.saturating_sub(1);
+            if self.counter == 0 {
+                self.expire();
+            }
+        }
+    }
+
+    fn irq(&mut self) -> Option<u8> {
+        std::mem::take(&mut self.irq_waiting).then_some(IRQ_TIMER)
+    }
+
+    fn mmio_peek(&self, offset: u8) -> u8 {
+        match offset {
+            TIMER_CONTROL => self.control,
+            TIMER_STATUS => self.status,
+            TIMER_RELOAD => self.reload,
+            TIMER_PRESCALER => self.prescaler,
+            TIMER_COUNTER => self.counter,
+            _ => 0,
+        }
+    }
+
+    fn mmio_write(&mut self, offset: u8, value: u8) {
+        match offset {
+            TIMER_CONTROL => {
+                if value & TIMER_RUN > 0 && !self.running() {
+                    self.counter = self.reload;
+                    self.prescale = 0;
+                }
+                self.control = value;
+            },
+            TIMER_STATUS => self.status = 0,
+            TIMER_RELOAD => {
+                self.reload = value;
+                self.counter = value;
+            },
+            TIMER_PRESCALER => self.prescaler = value,
+            TIMER_COUNTER => self.counter = value,
+            _ => {},
+        }
+    }
+
+    fn save_state(&self) -> Vec<u8> {
+        vec![self.control, self.status, self.reload, self.prescaler, self.counter, self.prescale, self.irq_waiting as u8]
+    }
+
+    fn check_state(&self, state: &[u8]) -> bool {
+        state.len() == 7
+    }
+
+    fn load_state(&mut self, state: &[u8]) {
+        [self.control, self.status, self.reload, self.prescaler, self.counter, self.prescale] =
+            [state[0], state[1], state[2], state[3], state[4], state[5]];
+        self.irq_waiting = state[6] > 0;
+    }
+}
@@ -0,0 +1,216 @@
+use std::{
+    fs::File,
+    io::{self, BufWriter, Write},
+};
+
+use jcpuinstructions::{decode, spec_for};
+use serde::{Serialize, Serializer};
+
+use crate::{
+    debugger::DebugRegister,
+    fault::CpuFault,
+    motherboard::Motherboard,
+    ram::Access,
+};
+
+/*
+    An opt-in execution trace, one record per Motherboard::cycle: the cycle counter,
+    the ram address the instruction was fetched from, its bytes and jasm, the
+    registers and flags it changed and the ram it wrote. A fault is added to the
+    record of the instruction that caused it.
+
+    Text puts each record on one line for reading, json lines has the same fields
+    for tools (jcpu-trace diff reads both).
+
+        cycle iar bytes  instruction      changes
+            7 017 78f8   ST 248, R1       [248]=0e
+*/
+
+const TRACED: [DebugRegister; 8] = [
+    DebugRegister::R1,
+    DebugRegister::R2,
+    DebugRegister::R3,
+    DebugRegister::R4,
+    DebugRegister::Sp,
+    DebugRegister::Out,
+    DebugRegister::Int,
+    DebugRegister::Flags,
+];
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TraceFormat {
+    Text,
+    JsonLines,
+}
+
+// Serializes to the json lines format, bytes as hex and registers as name: [before, after]
+#[derive(Serialize)]
+pub struct TraceRecord {
+    pub cycle: usize,
+    // None when the cycle faulted before it could fetch
+    pub iar: Option<u8>,
+    #[serde(serialize_with = "hex")]
+    pub bytes: Vec<u8>,
+    pub instruction: String,
+    // name, before, after
+    #[serde(serialize_with = "changes")]
+    pub registers: Vec<(&'static str, u8, u8)>,
+    // address, value
+    pub writes: Vec<(u8, u8)>,
+    #[serde(serialize_with = "message")]
+    pub fault: Option<CpuFault>,
+}
+
+fn hex<S: Serializer>(bytes: &[u8], serializer: S) -> Result<S::Ok, S::Error> {
+    serializer.collect_str(&bytes.iter().map(|b| format!("{:02x}", b)).collect::<String>())
+}
+
+fn changes<S: Serializer>(registers: &[(&'static str, u8, u8)], serializer: S) -> Result<S::Ok, S::Error> {
+    serializer.collect_map(registers.iter().map(|(name, before, after)| (name, [before, after])))
+}
+
+fn message<S: Serializer>(fault: &Option<CpuFault>, serializer: S) -> Result<S::Ok, S::Error> {
+    match fault {
+        Some(fault) => serializer.collect_str(fault),
+        None => serializer.serialize_none(),
+    }
+}
+
+impl TraceRecord {
+    pub fn text(&self) -> String {
+        let bytes: String = self.bytes.iter().map(|b| format!("{:02x}", b)).collect();
+        let iar = self.iar.map_or(String::from("---"), |iar| format!("{:03}", iar));
+        let mut line = format!("{:>6} {} {:<6} {:<16}", self.cycle, iar, bytes, self.instruction);
+
+        for (name, before, after) in self.registers.iter() {
+            line.push_str(&format!(" {}={:02x}->{:02x}", name, before, after));
+        }
+        for (address, value) in self.writes.iter() {
+            line.push_str(&format!(" [{}]={:02x}", address, value));
+        }
+        if let Some(fault) = self.fault {
+            line.push_str(&format!(" fault: {}", fault));
+        }
+
+        line.trim_end().to_string()
+    }
+
+    pub fn json(&self) -> String {
+        // plain strings and numbers, nothing here can fail to serialize
+        serde_json::to_string(self).expect("a trace record serializes")
+    }
+}
+
+pub struct Tracer {
+    pub format: TraceFormat,
+    out: Box<dyn Write>,
+    // the first write that failed, tracing stops there
+    pub error: Option<io::Error>,
+}
+
+impl Tracer {
+    pub fn new(out: Box<dyn Write>, format: TraceFormat) -> Self {
+        Self { format, out, error: None }
+    }
+
+    pub fn to_file(path: &str, format: TraceFormat) -> io::Result<Self> {
+        Ok(Self::new(Box::new(BufWriter::new(File::create(path)?)), format))
+    }
+
+    pub fn write(&mut self, record: &TraceRecord) {
+        if self.error.is_some() {
+            return;
+        }
+
+        let line = match self.format {
+            TraceFormat::Text => record.text(),
+            TraceFormat::JsonLines => record.json(),
+        };
+        if let Err(e) = writeln!(self.out, "{}", line) {
+            self.error = Some(e);
+        }
+    }
+
+    pub fn flush(&mut self) -> io::Result<()> {
+        self.out.flush()
+    }
+}
+
+// What the tracer needs from before the cycle ran
+pub(crate) struct TraceStart {
+    cycle: usize,
+    registers: [u8; TRACED.len()],
+    log_start: usize,
+    was_logging: bool,
+    iar: Option<u8>,
+    bytes: Vec<u8>,
+}
+
+impl TraceStart {
+    // called with the instruction just fetched, before it runs
+    pub(crate) fn fetched(&mut self, mb: &Motherboard, address: u8) {
+        let size = spec_for(mb.ram.fetch(address)).map_or(1, |spec| spec.size);
+        let end = (address as usize + size).min(mb.ram.memory.len());
+
+        self.iar = Some(address);
+        self.bytes = mb.ram.memory[address as usize..end].to_vec();
+    }
+}
+
+impl Motherboard {
+    pub(crate) fn trace_start(&mut self) -> TraceStart {
+        let start = TraceStart {
+            cycle: self.cycle_i,
+            registers: TRACED.map(|r| r.value(&self.cpu)),
+            log_start: self.ram.log.len(),
+            was_logging: self.ram.logging,
+            iar: None,
+            bytes: vec![],
+        };
+        self.ram.logging = true;
+
+        start
+    }
+
+    // fault is whatever the cycle raised, before its policy was applied
+    pub(crate) fn trace_end(&mut self, start: TraceStart, fault: Option<CpuFault>) {
+        let writes = self.ram.log[start.log_start..]
+            .iter()
+            .filter(|a| a.access == Access::Write)
+            .map(|a| (a.address, a.value))
+            .collect();
+
+        // leave the log the way the debugger had it
+        self.ram.logging = start.was_logging;
+        if !start.was_logging {
+            self.ram.log.truncate(start.log_start);
+        }
+
+        let registers = TRACED
+            .iter()
+            .zip(start.registers.iter())
+            .map(|(r, before)| (r.name(), *before, r.value(&self.cpu)))
+            .filter(|(_, before, after)| before != after)
+            .collect();
+
+        let instruction = match decode(&start.bytes) {
+            Ok(decoded) => decoded.to_string(),
+            Err(_) if start.bytes.is_empty() => String::new(),
+            Err(_) => String::from("??"),
+        };
+
+        let record = TraceRecord {
+            cycle: start.cycle,
+            iar: start.iar,
+            bytes: start.bytes,
+            instruction,
+            registers,
+            writes,
+            fault,
+        };
+
+        if let Some(tracer) = self.tracer.as_mut() {
+            tracer.write(&record);
+        }
+    }
+}
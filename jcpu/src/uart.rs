@@ -0,0 +1,364 @@
+use std::{
+    collections::VecDeque,
+    fs::{self, File},
+    io::{Read, Write},
+    sync::mpsc::{self, Receiver},
+    thread,
+};
+
+use serde::{Deserialize, Serialize};
+
+use crate::{device::Device, interrupts::IRQ_SERIAL};
+
+pub const UART_ID: &str = "uart";
+
+/*
+    A serial port. What the program sends goes out to the host straight away, what
+    the host sends waits in the RX FIFO until the program reads it. A byte that
+    arrives with the FIFO full is dropped and the overrun bit says so.
+
+    Its registers, from the start of its ram:
+
+    0   DATA      writing sends a byte, reading takes the oldest received one (0 when there is none)
+    1   STATUS    bit 0 RX ready, bit 1 TX ready (always), bit 2 overrun, writing anything clears overrun
+    2   CONTROL   bit 0 raises the serial irq when a byte arrives
+
+    The host end is a SerialBackend: stdin and stdout, files or a pseudo terminal.
+    Everything sent is also kept in `console` for the sim to show. Stepping back
+    rewinds the FIFO and registers but can not take back what the host was sent.
+*/
+const UART_DATA: u8 = 0;
+const UART_STATUS: u8 = 1;
+const UART_CONTROL: u8 = 2;
+
+pub const UART_RX_READY: u8 = 0b001;
+pub const UART_TX_READY: u8 = 0b010;
+pub const UART_OVERRUN: u8 = 0b100;
+pub const UART_RX_IRQ: u8 = 0b001;
+
+// how much of what was sent console keeps
+const CONSOLE_SIZE: usize = 4096;
+
+pub trait SerialBackend {
+    fn send(&mut self, byte: u8);
+    // at most max of the bytes the host sent since the last call, never blocks
+    fn receive(&mut self, max: usize) -> Vec<u8>;
+    // where the other end is, for the sim and jcpu-run to show
+    fn describe(&self) -> String;
+}
+
+// Not connected, for when only console is wanted
+pub struct NullBackend;
+
+impl SerialBackend for NullBackend {
+    fn send(&mut self, _byte: u8) {}
+
+    fn receive(&mut self, _max: usize) -> Vec<u8> {
+        vec![]
+    }
+
+    fn describe(&self) -> String {
+        String::from("not connected")
+    }
+}
+
+// stdin is read on its own thread so receive never waits on it
+pub struct StdioBackend {
+    rx: Receiver<u8>,
+}
+
+impl StdioBackend {
+    pub fn new() -> Self {
+        let (tx, rx) = mpsc::channel();
+        thread::spawn(move || {
+            for byte in std::io::stdin().lock().bytes() {
+                match byte {
+                    Ok(byte) if tx.send(byte).is_ok() => {},
+                    _ => return,
+                }
+            }
+        });
+
+        Self { rx }
+    }
+}
+
+impl Default for StdioBackend {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl SerialBackend for StdioBackend {
+    fn send(&mut self, byte: u8) {
+        let mut stdout = std::io::stdout();
+        let _ = stdout.write_all(&[byte]).and_then(|_| stdout.flush());
+    }
+
+    fn receive(&mut self, max: usize) -> Vec<u8> {
+        self.rx.try_iter().take(max).collect()
+    }
+
+    fn describe(&self) -> String {
+        String::from("stdio")
+    }
+}
+
+// Sends to tx, and receives the whole of rx (if given) as fast as the FIFO takes it
+pub struct FileBackend {
+    tx: File,
+    tx_path: String,
+    rx: VecDeque<u8>,
+}
+
+impl FileBackend {
+    pub fn open(tx: &str, rx: Option<&str>) -> Result<Self, String> {
+        let input = match rx {
+            Some(path) => fs::read(path).map_err(|e| format!("failed to read {}: {}", path, e))?,
+            None => vec![],
+        };
+        let output = File::create(tx).map_err(|e| format!("failed to create {}: {}", tx, e))?;
+
+        Ok(Self { tx: output, tx_path: tx.to_string(), rx: input.into() })
+    }
+}
+
+impl SerialBackend for FileBackend {
+    fn send(&mut self, byte: u8) {
+        let _ = self.tx.write_all(&[byte]);
+    }
+
+    fn receive(&mut self, max: usize) -> Vec<u8> {
+        let n = max.min(self.rx.len());
+        self.rx.drain(..n).collect()
+    }
+
+    fn describe(&self) -> String {
+        format!("file {}", self.tx_path)
+    }
+}
+
+// The master side of a new pseudo terminal, a terminal program opens `path`
+#[cfg(unix)]
+pub struct PtyBackend {
+    master: File,
+    pub path: String,
+}
+
+#[cfg(unix)]
+impl PtyBackend {
+    pub fn open() -> Result<Self, String> {
+        use std::{ffi::CStr, os::unix::io::FromRawFd};
+
+        let failed = |what: &str| format!("failed to {} a pseudo terminal: {}", what, std::io::Error::last_os_error());
+
+        // SAFETY: plain libc calls on the fd they hand back, which File then owns
+        unsafe {
+            let fd = libc::posix_openpt(libc::O_RDWR | libc::O_NOCTTY);
+            if fd < 0 {
+                return Err(failed("open"));
+            }
+            let master = File::from_raw_fd(fd);
+            if libc::grantpt(fd) != 0 || libc::unlockpt(fd) != 0 {
+                return Err(failed("unlock"));
+            }
+            let name = libc::ptsname(fd);
+            if name.is_null() {
+                return Err(failed("name"));
+            }
+            let path = CStr::from_ptr(name).to_string_lossy().to_string();
+
+            // raw, so bytes go through as they are and nothing is echoed back
+            let mut termios: libc::termios = std::mem::zeroed();
+            if libc::tcgetattr(fd, &mut termios) != 0 {
+                return Err(failed("set up"));
+            }
+            libc::cfmakeraw(&mut termios);
+            if libc::tcsetattr(fd, libc::TCSANOW, &termios) != 0 {
+                return Err(failed("set up"));
+            }
+            let flags = libc::fcntl(fd, libc::F_GETFL);
+            if flags < 0 || libc::fcntl(fd, libc::F_SETFL, flags | libc::O_NONBLOCK) < 0 {
+                return Err(failed("set up"));
+            }
+
+            Ok(Self { master, path })
+        }
+    }
+}
+
+#[cfg(unix)]
+impl SerialBackend for PtyBackend {
+    // dropped while nothing has the other end open
+    fn send(&mut self, byte: u8) {
+        let _ = self.master.write_all(&[byte]);
+    }
+
+    fn receive(&mut self, max: usize) -> Vec<u8> {
+        let mut buf = vec![0; max];
+        match self.master.read(&mut buf) {
+            Ok(n) => buf.truncate(n),
+            Err(_) => buf.clear(),
+        }
+        buf
+    }
+
+    fn describe(&self) -> String {
+        format!("pty {}", self.path)
+    }
+}
+
+// The host end as a machine profile names it
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase", deny_unknown_fields)]
+pub enum SerialConfig {
+    #[default]
+    None,
+    Stdio,
+    File { tx: String, rx: Option<String> },
+    Pty,
+}
+
+impl SerialConfig {
+    pub fn open(&self) -> Result<Box<dyn SerialBackend>, String> {
+        match self {
+            SerialConfig::None => Ok(Box::new(NullBackend)),
+            SerialConfig::Stdio => Ok(Box::new(StdioBackend::new())),
+            SerialConfig::File { tx, rx } => Ok(Box::new(FileBackend::open(tx, rx.as_deref())?)),
+            #[cfg(unix)]
+            SerialConfig::Pty => Ok(Box::new(PtyBackend::open()?)),
+            #[cfg(not(unix))]
+            SerialConfig::Pty => Err(String::from("pseudo terminals are only there on unix")),
+        }
+    }
+}
+
+pub struct Uart {
+    pub rx: VecDeque<u8>,
+    pub fifo_size: usize,
+    pub control: u8,
+    pub overrun: bool,
+    // set when a byte arrives with the irq on, the next irq raises it
+    pub irq_waiting: bool,
+    pub console: Vec<u8>,
+    pub backend: Box<dyn SerialBackend>,
+}
+
+impl Uart {
+    pub fn new(fifo_size: usize, backend: Box<dyn SerialBackend>) -> Self {
+        Self {
+            rx: VecDeque::new(),
+            fifo_size,
+            control: 0,
+            overrun: false,
+            irq_waiting: false,
+            console: vec![],
+            backend,
+        }
+    }
+
+    fn status(&self) -> u8 {
+        let mut status = UART_TX_READY;
+        if !self.rx.is_empty() {
+            status |= UART_RX_READY;
+        }
+        if self.overrun {
+            status |= UART_OVERRUN;
+        }
+        status
+    }
+
+    fn arrived(&mut self, byte: u8) {
+        if self.rx.len() >= self.fifo_size {
+            self.overrun = true;
+            return;
+        }
+
+        self.rx.push_back(byte);
+        if self.control & UART_RX_IRQ > 0 {
+            self.irq_waiting = true;
+        }
+    }
+
+    fn send(&mut self, byte: u8) {
+        self.backend.send(byte);
+
+        self.console.push(byte);
+        if self.console.len() > CONSOLE_SIZE {
+            self.console.drain(..self.console.len() - CONSOLE_SIZE);
+        }
+    }
+}
+
+impl Device for Uart {
+    fn name(&self) -> &'static str {
+        UART_ID
+    }
+
+    fn reset(&mut self) {
+        self.rx.clear();
+        self.control = 0;
+        self.overrun = false;
+        self.irq_waiting = false;
+    }
+
+    fn tick(&mut self, _cycles: usize) {
+        let free = self.fifo_size.saturating_sub(self.rx.len());
+        for byte in self.backend.receive(free) {
+            self.arrived(byte);
+        }
+    }
+
+    fn irq(&mut self) -> Option<u8> {
+        std::mem::take(&mut self.irq_waiting).then_some(IRQ_SERIAL)
+    }
+
+    // a byte typed on the host side, the sim sends keys this way
+    fn input(&mut self, value: u8) {
+        self.arrived(value);
+    }
+
+    fn mmio_peek(&self, offset: u8) -> u8 {
+        match offset {
+            UART_DATA => self.rx.front().copied().unwrap_or(0),
+            UART_STATUS => self.status(),
+            UART_CONTROL => self.control,
+            _ => 0,
+        }
+    }
+
+    fn mmio_read(&mut self, offset: u8) -> u8 {
+        if offset == UART_DATA {
+            return self.rx.pop_front().unwrap_or(0);
+        }
+
+        self.mmio_peek(offset)
+    }
+
+    fn mmio_write(&mut self, offset: u8, value: u8) {
+        match offset {
+            UART_DATA => self.send(value),
+            UART_STATUS => self.overrun = false,
+            UART_CONTROL => self.control = value,
+            _ => {},
+        }
+    }
+
+    // control, overrun, irq_waiting then the FIFO
+    fn save_state(&self) -> Vec<u8> {
+        let mut state = vec![self.control, self.overrun as u8, self.irq_waiting as u8];
+        state.extend(self.rx.iter());
+        state
+    }
+
+    fn check_state(&self, state: &[u8]) -> bool {
+        state.len() >= 3 && state.len() - 3 <= self.fifo_size
+    }
+
+    fn load_state(&mut self, state: &[u8]) {
+        self.control = state[0];
+        self.overrun = state[1] > 0;
+        self.irq_waiting = state[2] > 0;
+        self.rx = state[3..].iter().copied().collect();
+    }
+}
@@ -0,0 +1,58 @@
+use jcpu::{config::MachineConfig, fault::CpuFault, motherboard::Motherboard};
+use jcpuinstructions::{encode, DecodedInstruction};
+
+// A full 256 byte board with the program at the top, so instructions can sit on the last bytes of ram
+const TOP_PROGRAM: &str = r#"
+ram_size = 256
+peripherals = []
+
+[map]
+boot = 200
+program = 56
+fault = 199
+ivt = 190
+
+[map.bank]
+address = 150
+size = 10
+count = 1
+
+[map.stack]
+base = 100
+limit = 140
+direction = "up"
+"#;
+
+fn run(mb: &mut Motherboard) -> Result<(), CpuFault> {
+    for _ in 0..100 {
+        if !mb.cycle()? {
+            break;
+        }
+    }
+
+    Ok(())
+}
+
+#[test]
+fn call_on_the_last_bytes_of_ram_faults() {
+    let config = MachineConfig::from_toml(TOP_PROGRAM).unwrap();
+    let mut mb = Motherboard::with_config("", "", config).unwrap();
+
+    // jump to program address 54 (ram 254) and CALL from there, its return address would be 256
+    let mut image = vec![0; 56];
+    image[..2].copy_from_slice(&encode(&DecodedInstruction::Jmp { address: 54 }));
+    image[54..].copy_from_slice(&encode(&DecodedInstruction::Call { address: 0 }));
+    mb.load(image);
+
+    assert_eq!(run(&mut mb), Err(CpuFault::AddressOutOfRange(256)));
+}
+
+#[test]
+fn jump_to_address_0_with_boot_at_0() {
+    let config = MachineConfig::from_toml("peripherals = []\n\n[map]\nboot = 0\nprogram = 100\nfault = 100\n").unwrap();
+    let mut mb = Motherboard::with_config("", "", config).unwrap();
+    mb.load(jcpu_compiler::assemble("start:\nJMP $start\n"));
+
+    assert_eq!(run(&mut mb), Ok(()));
+    assert_eq!(mb.cpu.reg_iar, 0);
+}
@@ -0,0 +1,58 @@
+use jcpu::motherboard::Motherboard;
+
+// Runs jsm and hands back R1 and whether carry was left set
+fn run(jsm: &str) -> (u8, bool) {
+    let mut mb = Motherboard::new("", "");
+    mb.load(jcpu_compiler::assemble(&format!("{}HLT\n", jsm)));
+
+    for _ in 0..100 {
+        if !mb.cycle().unwrap() {
+            break;
+        }
+    }
+
+    (mb.cpu.reg_1, mb.cpu.alu.flags & 1 > 0)
+}
+
+#[test]
+fn every_op_wraps_at_256() {
+    let cases = [
+        ("DATA R1, 252\nADD R1, 3\n", (255, false)),
+        ("DATA R1, 255\nADD R1, 1\n", (0, true)),
+        ("DATA R1, 200\nADD R1, 100\n", (44, true)),
+        ("DATA R1, 255\nINC R1\n", (0, true)),
+        ("DATA R1, 3\nSUB R1, 5\n", (254, true)),
+        ("DATA R1, 0\nDEC R1\n", (255, true)),
+        ("DATA R1, 0x81\nSHL R1, 1\n", (0x02, true)),
+        ("DATA R1, 0x81\nSHR R1, 1\n", (0x40, false)),
+    ];
+
+    for (jsm, expected) in cases {
+        assert_eq!(run(jsm), expected, "{}", jsm.replace('\n', "; "));
+    }
+}
+
+#[test]
+fn logic_and_shifts_in_both_forms() {
+    // the register forms leave the result in the second register
+    let cases = [
+        ("DATA R1, 0xf0\nDATA R2, 0x3c\nAND R2, R1\n", (0x30, false)),
+        ("DATA R1, 0xf0\nAND R1, 0x3c\n", (0x30, false)),
+        ("DATA R1, 0xf0\nDATA R2, 0x3c\nOR R2, R1\n", (0xfc, false)),
+        ("DATA R1, 0xf0\nOR R1, 0x3c\n", (0xfc, false)),
+        ("DATA R1, 0xf0\nDATA R2, 0x3c\nXOR R2, R1\n", (0xcc, false)),
+        ("DATA R1, 0xf0\nXOR R1, 0x3c\n", (0xcc, false)),
+        ("DATA R1, 0xf0\nNOT R1\n", (0x0f, false)),
+        ("DATA R2, 0x81\nDATA R1, 2\nSHL R2, R1\n", (0x04, true)),
+        ("DATA R2, 0x81\nDATA R1, 2\nSHR R2, R1\n", (0x20, false)),
+        // 8 or more shifts every bit out
+        ("DATA R1, 0x81\nSHL R1, 8\n", (0, true)),
+        ("DATA R1, 0xff\nSHR R1, 9\n", (0, false)),
+        ("DATA R1, 0\nSHL R1, 200\n", (0, false)),
+        ("DATA R2, 0xff\nDATA R1, 8\nSHR R2, R1\n", (0, false)),
+    ];
+
+    for (jsm, expected) in cases {
+        assert_eq!(run(jsm), expected, "{}", jsm.replace('\n', "; "));
+    }
+}
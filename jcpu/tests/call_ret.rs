@@ -0,0 +1,47 @@
+use jcpu::motherboard::Motherboard;
+
+fn board(program: &str) -> Motherboard {
+    let mut mb = Motherboard::new("", "");
+    mb.load(jcpu_compiler::assemble(program));
+    mb
+}
+
+#[test]
+fn ret_comes_back_after_the_call() {
+    // R1 counts in the subroutine, R3 after it returns
+    let mut mb = board("CALL $sub\nINC R3\nHLT\nsub:\nINC R1\nRET\n");
+    let sp = mb.cpu.reg_sp;
+    let boot = mb.cpu.map.boot as u8;
+
+    mb.cycle().unwrap();
+    // IAR is on sub (program address 4) with the return address on the stack
+    assert_eq!(mb.cpu.reg_iar, boot + 4);
+    assert_eq!(mb.ram.memory[mb.cpu.reg_sp as usize], boot + 2);
+    assert_eq!(mb.cpu.call_depth, 1);
+
+    while mb.cycle().unwrap() {}
+    assert_eq!((mb.cpu.reg_1, mb.cpu.reg_3), (1, 1));
+    assert_eq!(mb.cpu.reg_sp, sp);
+    assert_eq!(mb.cpu.call_depth, 0);
+}
+
+#[test]
+fn nested_calls_unwind_in_order() {
+    // outer calls inner half way through, each step is numbered in R4 and kept in ram 140 to 143
+    let program = "CALL $outer\nINC R4\nST 143, R4\nHLT\n\
+                   outer:\nINC R4\nST 140, R4\nCALL $inner\nINC R4\nST 142, R4\nRET\n\
+                   inner:\nINC R4\nST 141, R4\nRET\n";
+    let mut mb = board(program);
+    let sp = mb.cpu.reg_sp;
+
+    let mut depths = vec![mb.cpu.call_depth];
+    while mb.cycle().unwrap() {
+        if depths.last() != Some(&mb.cpu.call_depth) {
+            depths.push(mb.cpu.call_depth);
+        }
+    }
+
+    assert_eq!(depths, [0, 1, 2, 1, 0]);
+    assert_eq!(mb.ram.memory[140..144], [1, 2, 3, 4]);
+    assert_eq!(mb.cpu.reg_sp, sp);
+}
@@ -0,0 +1,129 @@
+use jcpu::{
+    capture::{gif, CaptureError, CaptureMode, Frame, ImageFormat, Recorder},
+    motherboard::Motherboard,
+    peripheral::PALETTE,
+};
+
+// two by two: black, blue / red, white
+fn frame() -> Frame {
+    Frame { width: 2, height: 2, pixels: vec![0, 1, 4, 15], cycle: 0 }
+}
+
+fn rgb(index: usize) -> [u8; 3] {
+    let (r, g, b) = PALETTE[index];
+    [r, g, b]
+}
+
+#[test]
+fn ppm_is_a_header_and_the_rgb_pixels() {
+    let ppm = frame().ppm(1);
+    let header = b"P6\n2 2\n255\n";
+
+    assert_eq!(ppm[..header.len()], header[..]);
+    assert_eq!(ppm[header.len()..], [rgb(0), rgb(1), rgb(4), rgb(15)].concat()[..]);
+}
+
+#[test]
+fn scaling_blows_each_pixel_up_to_a_square() {
+    let scaled = frame().scaled(2);
+    assert_eq!((scaled.width, scaled.height), (4, 4));
+    assert_eq!(scaled.pixels, [0, 0, 1, 1, 0, 0, 1, 1, 4, 4, 15, 15, 4, 4, 15, 15]);
+
+    assert!(frame().ppm(3).starts_with(b"P6\n6 6\n255\n"));
+    // 0 is taken as 1
+    assert_eq!(frame().scaled(0), frame());
+}
+
+#[test]
+fn png_decodes_to_the_same_indexes_and_palette() {
+    let png = frame().png(2).unwrap();
+
+    let decoder = png::Decoder::new(&png[..]);
+    let mut reader = decoder.read_info().unwrap();
+    let mut pixels = vec![0; reader.output_buffer_size()];
+    let info = reader.next_frame(&mut pixels).unwrap();
+
+    assert_eq!((info.width, info.height), (4, 4));
+    assert_eq!(info.color_type, png::ColorType::Indexed);
+    assert_eq!(pixels[..info.buffer_size()], frame().scaled(2).pixels[..]);
+
+    let palette = reader.info().palette.as_ref().unwrap();
+    let expected: Vec<u8> = (0..16).flat_map(rgb).collect();
+    assert_eq!(palette[..], expected[..]);
+}
+
+#[test]
+fn gif_keeps_every_frame_and_the_delay() {
+    let mut second = frame();
+    second.pixels.reverse();
+    let bytes = gif(&[frame(), second.clone()], 7, 1).unwrap();
+
+    let mut options = gif::DecodeOptions::new();
+    options.set_color_output(gif::ColorOutput::Indexed);
+    let mut decoder = options.read_info(&bytes[..]).unwrap();
+    assert_eq!((decoder.width(), decoder.height()), (2, 2));
+
+    let mut frames = vec![];
+    while let Some(decoded) = decoder.read_next_frame().unwrap() {
+        assert_eq!(decoded.delay, 7);
+        frames.push(decoded.buffer.to_vec());
+    }
+    assert_eq!(frames, [frame().pixels, second.pixels]);
+}
+
+#[test]
+fn gif_needs_frames_of_one_size() {
+    assert!(matches!(gif(&[], 0, 1), Err(CaptureError::NoFrames)));
+
+    let wide = Frame { width: 4, height: 1, pixels: vec![0; 4], cycle: 0 };
+    assert!(matches!(gif(&[frame(), wide], 0, 1), Err(CaptureError::Encode(_))));
+}
+
+#[test]
+fn the_extension_picks_the_format() {
+    assert_eq!(ImageFormat::from_path("shot.ppm").unwrap(), ImageFormat::Ppm);
+    assert_eq!(ImageFormat::from_path("dir.d/SHOT.PNG").unwrap(), ImageFormat::Png);
+    assert_eq!(ImageFormat::from_path("a.gif").unwrap(), ImageFormat::Gif);
+    assert!(matches!(ImageFormat::from_path("shot.bmp"), Err(CaptureError::UnknownFormat(_))));
+    assert!(matches!(ImageFormat::from_path("shot"), Err(CaptureError::UnknownFormat(_))));
+
+    // a one frame GIF is the same as gif() of that frame
+    assert_eq!(frame().encode(ImageFormat::Gif, 2).unwrap(), gif(&[frame()], 0, 2).unwrap());
+}
+
+// the default board: screen at 7, X is 7 and COLOR is 9, a frame every 64 cycles
+fn record(mode: CaptureMode, program: &str, cycles: usize) -> Recorder {
+    let mut mb = Motherboard::new("", "");
+    mb.load(jcpu_compiler::assemble(program));
+
+    let mut recorder = Recorder::new(mode);
+    recorder.capture(&mb);
+    for _ in 0..cycles {
+        mb.process_peripherals();
+        if !mb.cycle().unwrap() {
+            break;
+        }
+        recorder.capture(&mb);
+    }
+    recorder
+}
+
+#[test]
+fn change_mode_keeps_each_new_picture() {
+    // two red pixels, then nothing changes
+    let recorder = record(CaptureMode::Change, "DATA R1, 4\nDATA R2, 1\nST 9, R1\nST 7, R2\nST 9, R1\nST 9, R1\nHLT\n", 20);
+
+    let lit: Vec<usize> = recorder.frames.iter().map(|f| f.pixels.iter().filter(|p| **p == 4).count()).collect();
+    assert_eq!(lit, [0, 1, 2]);
+    assert_eq!(recorder.frames[1].cycle, 3);
+}
+
+#[test]
+fn frame_mode_keeps_each_drawn_frame() {
+    let recorder = record(CaptureMode::Frame, "loop:\nJMP $loop\n", 200);
+
+    let cycles: Vec<usize> = recorder.frames.iter().map(|f| f.cycle).collect();
+    assert_eq!(cycles.len(), 4, "{:?}", cycles);
+    assert!(cycles.windows(2).skip(1).all(|w| w[1] - w[0] == 64), "{:?}", cycles);
+    assert!(matches!(Recorder::new(CaptureMode::Frame).gif(10, 1), Err(CaptureError::NoFrames)));
+}
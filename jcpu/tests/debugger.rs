@@ -0,0 +1,99 @@
+use jcpu::{
+    debugger::{DebugRegister, Debugger, Stop, WatchAccess, Watchpoint},
+    motherboard::Motherboard,
+    ram::{Access, RamAccess},
+};
+
+// stores 9 at 0x60, reads it back into R3, then counts R4 up once
+const PROGRAM: &str = "DATA R1, 0x60\nDATA R2, 9\nST R1, R2\nLD R1, R3\nINC R4\nHLT\n";
+// program addresses of the instructions
+const ST: u8 = 4;
+const LD: u8 = 5;
+
+fn board(program: &str) -> Motherboard {
+    let mut mb = Motherboard::new("", "");
+    mb.load(jcpu_compiler::assemble(program));
+    mb
+}
+
+#[test]
+fn a_breakpoint_stops_before_its_instruction() {
+    let mut mb = board(PROGRAM);
+    let mut debugger = Debugger::new();
+    debugger.add_breakpoint(LD);
+
+    assert_eq!(debugger.run_until_break(&mut mb, 100), Stop::Breakpoint(LD));
+    assert_eq!(Debugger::program_address(&mb), Some(LD));
+    assert_eq!(mb.ram.memory[0x60], 9);
+    assert_eq!(mb.cpu.reg_3, 0);
+
+    // carries on from there to the end
+    assert_eq!(debugger.run_until_break(&mut mb, 100), Stop::Halted);
+    assert_eq!(mb.cpu.reg_3, 9);
+}
+
+#[test]
+fn breakpoints_are_set_once_and_toggle() {
+    let mut debugger = Debugger::new();
+    debugger.add_breakpoint(ST);
+    debugger.add_breakpoint(ST);
+    assert_eq!(debugger.breakpoints, [ST]);
+
+    assert!(!debugger.toggle_breakpoint(ST));
+    assert!(debugger.breakpoints.is_empty());
+    assert!(debugger.toggle_breakpoint(LD));
+    debugger.remove_breakpoint(LD);
+
+    let mut mb = board(PROGRAM);
+    assert_eq!(debugger.run_until_break(&mut mb, 100), Stop::Halted);
+}
+
+#[test]
+fn ram_watchpoints_stop_after_the_access() {
+    let mut mb = board(PROGRAM);
+    let mut debugger = Debugger::new();
+    let read = debugger.watch(Watchpoint::Ram { start: 0x60, end: 0x60, access: WatchAccess::Read });
+    let write = debugger.watch(Watchpoint::Ram { start: 0x5f, end: 0x61, access: WatchAccess::Write });
+
+    let stop = debugger.run_until_break(&mut mb, 100);
+    assert_eq!(stop, Stop::Ram { watchpoint: write, hit: RamAccess { address: 0x60, access: Access::Write, value: 9 } });
+    assert_eq!(Debugger::program_address(&mb), Some(LD));
+
+    let stop = debugger.run_until_break(&mut mb, 100);
+    assert_eq!(stop, Stop::Ram { watchpoint: read, hit: RamAccess { address: 0x60, access: Access::Read, value: 9 } });
+    assert_eq!(mb.cpu.reg_3, 9);
+
+    assert_eq!(debugger.run_until_break(&mut mb, 100), Stop::Halted);
+}
+
+#[test]
+fn a_write_of_the_same_value_still_counts() {
+    let mut mb = board("DATA R1, 0x60\nDATA R2, 0\nST R1, R2\nHLT\n");
+    let mut debugger = Debugger::new();
+    debugger.watch(Watchpoint::Ram { start: 0x60, end: 0x60, access: WatchAccess::Any });
+    // a range that misses 0x60
+    debugger.watch(Watchpoint::Ram { start: 0x61, end: 0xff, access: WatchAccess::Any });
+
+    let stop = debugger.run_until_break(&mut mb, 100);
+    assert_eq!(stop, Stop::Ram { watchpoint: 0, hit: RamAccess { address: 0x60, access: Access::Write, value: 0 } });
+}
+
+#[test]
+fn a_register_watchpoint_reports_the_change() {
+    let mut mb = board(PROGRAM);
+    let mut debugger = Debugger::new();
+    debugger.watch(Watchpoint::Register(DebugRegister::R4));
+
+    let stop = debugger.run_until_break(&mut mb, 100);
+    assert_eq!(stop, Stop::Register { watchpoint: 0, register: DebugRegister::R4, old: 0, new: 1 });
+    assert_eq!(stop.to_string(), "watchpoint 0: R4 changed 00 -> 01");
+}
+
+#[test]
+fn running_out_of_cycles_is_a_stop() {
+    let mut mb = board("loop:\nJMP $loop\n");
+    let mut debugger = Debugger::new();
+
+    assert_eq!(debugger.run_until_break(&mut mb, 50), Stop::CycleLimit);
+    assert_eq!(debugger.step(&mut mb), None);
+}
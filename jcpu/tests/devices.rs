@@ -0,0 +1,146 @@
+use jcpu::{
+    device::{AttachError, Device, DeviceInfo},
+    keyboard::Keyboard,
+    motherboard::Motherboard,
+    peripheral::Screen,
+};
+
+// Counts the cycles it has seen, and raises line irq after every tick when it has one
+struct Counter {
+    cycles: usize,
+    inputs: Vec<u8>,
+    irq: Option<u8>,
+}
+
+impl Counter {
+    fn new(irq: Option<u8>) -> Self {
+        Self { cycles: 0, inputs: vec![], irq }
+    }
+}
+
+impl Device for Counter {
+    fn name(&self) -> &'static str {
+        "counter"
+    }
+
+    fn tick(&mut self, cycles: usize) {
+        self.cycles += cycles;
+    }
+
+    fn irq(&mut self) -> Option<u8> {
+        self.irq
+    }
+
+    fn input(&mut self, value: u8) {
+        self.inputs.push(value);
+    }
+
+    fn mmio_peek(&self, offset: u8) -> u8 {
+        (self.cycles as u8).wrapping_add(offset)
+    }
+}
+
+// A device with a name already on the default board
+struct Impostor;
+
+impl Device for Impostor {
+    fn name(&self) -> &'static str {
+        "keyboard"
+    }
+}
+
+fn board(program: &str) -> Motherboard {
+    let mut mb = Motherboard::new("", "");
+    mb.load(jcpu_compiler::assemble(program));
+    mb
+}
+
+#[test]
+fn an_attached_device_is_found_and_on_the_bus() {
+    // 14 is free on the default board
+    let mut mb = board("LD 14, R1\nLD 14, R2\nHLT\n");
+    let id = mb.attach(Box::new(Counter::new(None)), Some(14..15)).unwrap();
+
+    assert_eq!(mb.find("counter"), Some(id));
+    assert_eq!(mb.find_kind::<Counter>(), Some(id));
+    assert_eq!(mb.devices().last(), Some(&DeviceInfo { id, name: "counter", mmio: Some(14..15) }));
+    let names: Vec<&str> = mb.devices().iter().map(|d| d.name).collect();
+    assert_eq!(names, ["keyboard", "screen", "counter"]);
+
+    // the device is read through the bus, ticking before every cycle
+    for _ in 0..3 {
+        mb.process_peripherals();
+        mb.cycle().unwrap();
+    }
+    assert_eq!((mb.cpu.reg_1, mb.cpu.reg_2), (0, 1));
+    assert_eq!(mb.device::<Counter>(id).unwrap().cycles, 2);
+
+    mb.input(id, 42);
+    mb.device_mut::<Counter>(id).unwrap().cycles = 10;
+    mb.ram.sync_bus();
+    assert_eq!(mb.device::<Counter>(id).unwrap().inputs, [42]);
+    assert_eq!(mb.ram.memory[14], 10);
+}
+
+#[test]
+fn looking_up_the_wrong_kind_finds_nothing() {
+    let mut mb = board("HLT\n");
+    let keyboard = mb.find_kind::<Keyboard>().unwrap();
+
+    assert!(mb.device::<Screen>(keyboard).is_none());
+    assert!(mb.device_mut::<Counter>(keyboard).is_none());
+    assert!(mb.find("counter").is_none());
+    assert!(mb.find_kind::<Counter>().is_none());
+
+    // a device off the bus is still found
+    let id = mb.attach(Box::new(Counter::new(None)), None).unwrap();
+    assert_eq!(mb.find_kind::<Counter>(), Some(id));
+    assert_eq!(mb.devices().last().unwrap().mmio, None);
+}
+
+#[test]
+fn a_taken_name_is_refused() {
+    let mut mb = board("HLT\n");
+
+    assert_eq!(mb.attach(Box::new(Impostor), Some(14..15)), Err(AttachError::NameTaken("keyboard")));
+    assert_eq!(mb.devices().len(), 2);
+    assert!(mb.ram.bus.mapping(14).is_none());
+}
+
+#[test]
+fn an_overlapping_range_is_refused() {
+    let mut mb = board("HLT\n");
+
+    // the screen is at 7 to 12
+    let err = mb.attach(Box::new(Counter::new(None)), Some(12..15)).unwrap_err();
+    assert_eq!(err, AttachError::Overlap { name: "counter", range: 12..15, other: "screen", other_range: 7..13 });
+    assert_eq!(err.to_string(), "counter (12..15) overlaps screen (7..13)");
+    assert!(mb.find("counter").is_none());
+
+    // nothing was left half attached, so it fits where it does not overlap
+    assert!(mb.attach(Box::new(Counter::new(None)), Some(14..15)).is_ok());
+}
+
+#[test]
+fn a_range_outside_ram_is_refused() {
+    let mut mb = board("HLT\n");
+
+    assert_eq!(mb.attach(Box::new(Counter::new(None)), Some(250..260)), Err(AttachError::BadRange { name: "counter", range: 250..260 }));
+    assert_eq!(mb.attach(Box::new(Counter::new(None)), Some(14..14)), Err(AttachError::BadRange { name: "counter", range: 14..14 }));
+    assert_eq!(mb.devices().len(), 2);
+}
+
+#[test]
+fn an_irq_line_the_cpu_does_not_have_is_dropped() {
+    let mut mb = board("HLT\n");
+    mb.attach(Box::new(Counter::new(Some(9))), None).unwrap();
+
+    mb.process_peripherals();
+    assert_eq!(mb.cpu.interrupts.pending, 0);
+    assert_eq!(mb.dbg_info(), "counter raised irq 9, there are only 8 lines");
+
+    let mut mb = board("HLT\n");
+    mb.attach(Box::new(Counter::new(Some(5))), None).unwrap();
+    mb.process_peripherals();
+    assert_eq!(mb.cpu.interrupts.pending, 1 << 5);
+}
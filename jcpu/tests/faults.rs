@@ -0,0 +1,87 @@
+use jcpu::{
+    fault::{CpuFault, FaultPolicies, FaultPolicy},
+    motherboard::Motherboard,
+};
+
+// POP on an empty stack underflows (fault code 3), the handler for line 7 keeps the code in R4
+const PROGRAM: &str = "DATA R1, $handler\nST 254, R1\nPOP R2\nINC R3\nHLT\nhandler:\nLD 13, R4\nIRET\n";
+// the same without a handler
+const UNHANDLED: &str = "POP R2\nINC R3\nHLT\n";
+
+fn board(program: &str, policy: FaultPolicy) -> Motherboard {
+    let mut mb = Motherboard::new("", "");
+    mb.fault_policies = FaultPolicies::all(policy);
+    mb.load(jcpu_compiler::assemble(program));
+    mb
+}
+
+fn run(mb: &mut Motherboard) -> Result<(), CpuFault> {
+    for _ in 0..100 {
+        if !mb.cycle()? {
+            break;
+        }
+    }
+
+    Ok(())
+}
+
+#[test]
+fn halt_hands_the_fault_back_and_stays_put() {
+    let mut mb = board(UNHANDLED, FaultPolicy::Halt);
+
+    assert_eq!(run(&mut mb), Err(CpuFault::StackUnderflow));
+    assert_eq!(mb.fault, Some(CpuFault::StackUnderflow));
+    let iar = mb.cpu.reg_iar;
+
+    // it faults the same way again rather than carrying on
+    assert_eq!(mb.cycle(), Err(CpuFault::StackUnderflow));
+    assert_eq!(mb.cpu.reg_iar, iar);
+    assert_eq!(mb.cpu.reg_3, 0);
+}
+
+#[test]
+fn trap_calls_the_handler_and_iret_resumes_after_the_fault() {
+    let mut mb = board(PROGRAM, FaultPolicy::Trap);
+    let sp = mb.cpu.reg_sp;
+
+    // interrupts were never enabled, a trap can not be masked
+    assert_eq!(run(&mut mb), Ok(()));
+    assert_eq!(mb.ram.memory[13], CpuFault::StackUnderflow.code());
+    assert_eq!(mb.cpu.reg_4, 3);
+    // the POP was not retried, the INC after it ran once
+    assert_eq!(mb.cpu.reg_3, 1);
+    assert_eq!(mb.cpu.reg_sp, sp);
+    assert!(mb.cpu.interrupts.active.is_empty());
+    assert_eq!(mb.fault, Some(CpuFault::StackUnderflow));
+}
+
+#[test]
+fn trap_without_a_handler_halts() {
+    let mut mb = board(UNHANDLED, FaultPolicy::Trap);
+
+    assert_eq!(run(&mut mb), Err(CpuFault::StackUnderflow));
+    assert_eq!(mb.cpu.reg_3, 0);
+}
+
+#[test]
+fn ignore_skips_the_instruction() {
+    let mut mb = board(UNHANDLED, FaultPolicy::Ignore);
+    let sp = mb.cpu.reg_sp;
+
+    assert_eq!(run(&mut mb), Ok(()));
+    assert_eq!((mb.cpu.reg_2, mb.cpu.reg_3), (0, 1));
+    assert_eq!(mb.cpu.reg_sp, sp);
+    // the handler is not called, the fault is still noted
+    assert_eq!(mb.ram.memory[13], 0);
+    assert_eq!(mb.fault, Some(CpuFault::StackUnderflow));
+}
+
+#[test]
+fn each_fault_has_its_own_policy() {
+    // only underflow traps, so the bad bank that follows halts
+    let mut mb = board("DATA R1, $handler\nST 254, R1\nPOP R2\nBANK 9\nHLT\nhandler:\nIRET\n", FaultPolicy::Halt);
+    mb.fault_policies.stack_underflow = FaultPolicy::Trap;
+
+    assert_eq!(run(&mut mb), Err(CpuFault::AddressOutOfRange(9)));
+    assert_eq!(mb.fault_policies.policy(&CpuFault::AddressOutOfRange(9)), FaultPolicy::Halt);
+}
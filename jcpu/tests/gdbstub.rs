@@ -0,0 +1,160 @@
+use std::{
+    cell::{Cell, RefCell},
+    io::{self, ErrorKind, Read, Write},
+    rc::Rc,
+};
+
+use jcpu::{debugger::Debugger, gdbstub::{Connection, GdbStub}, keyboard::Keyboard, motherboard::Motherboard};
+
+// A client that sends every packet up front and keeps whatever the stub writes back
+struct Script {
+    input: Vec<u8>,
+    read: usize,
+    output: Rc<RefCell<Vec<u8>>>,
+    nonblocking: Cell<bool>,
+}
+
+impl Read for Script {
+    fn read(&mut self, buffer: &mut [u8]) -> io::Result<usize> {
+        let n = buffer.len().min(self.input.len() - self.read);
+        // nothing more to send, but the client is still there
+        if n == 0 && self.nonblocking.get() {
+            return Err(ErrorKind::WouldBlock.into());
+        }
+
+        buffer[..n].copy_from_slice(&self.input[self.read..self.read + n]);
+        self.read += n;
+        Ok(n)
+    }
+}
+
+impl Write for Script {
+    fn write(&mut self, bytes: &[u8]) -> io::Result<usize> {
+        self.output.borrow_mut().extend_from_slice(bytes);
+        Ok(bytes.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+impl Connection for Script {
+    fn set_nonblocking(&self, nonblocking: bool) -> io::Result<()> {
+        self.nonblocking.set(nonblocking);
+        Ok(())
+    }
+}
+
+fn checksum(packet: &str) -> u8 {
+    packet.bytes().fold(0, |sum, b| sum.wrapping_add(b))
+}
+
+// Sends the packets, acking every reply, and hands back the replies in order
+fn session(mb: &mut Motherboard, debugger: &mut Debugger, packets: &[&str]) -> Vec<String> {
+    let input: String = packets.iter().map(|p| format!("${}#{:02x}+", p, checksum(p))).collect();
+    raw_session(mb, debugger, input.into_bytes())
+}
+
+// The same with the bytes the client sends as they are
+fn raw_session(mb: &mut Motherboard, debugger: &mut Debugger, input: Vec<u8>) -> Vec<String> {
+    let output = Rc::new(RefCell::new(vec![]));
+    let script = Script { input, read: 0, output: output.clone(), nonblocking: Cell::new(false) };
+
+    GdbStub::new(mb, debugger, script).run().unwrap();
+
+    let output = String::from_utf8(output.take()).unwrap();
+    output
+        .split('$')
+        .skip(1)
+        .map(|reply| {
+            let (body, sum) = reply.split_once('#').unwrap();
+            assert_eq!(sum[..2], format!("{:02x}", checksum(body)), "bad checksum on {}", body);
+            body.to_string()
+        })
+        .collect()
+}
+
+// the default board, boot at 15 (0x0f)
+fn board() -> Motherboard {
+    let mut mb = Motherboard::new("", "");
+    mb.load(jcpu_compiler::assemble("DATA R1, 5\nDATA R2, 7\nADD R1, R2\nHLT\n"));
+    mb
+}
+
+#[test]
+fn g_reads_the_registers() {
+    let mut mb = board();
+    mb.cpu.reg_1 = 0x12;
+    mb.cpu.reg_4 = 0xab;
+    mb.cpu.reg_sp = 0xc7;
+
+    let replies = session(&mut mb, &mut Debugger::new(), &["g"]);
+    assert_eq!(replies, ["120000ab0fc700"]);
+}
+
+#[test]
+fn m_reads_ram() {
+    let mut mb = board();
+
+    let replies = session(&mut mb, &mut Debugger::new(), &["m0f,2", "mfe,1", "mfe,2", "m1,ffffffffffffffff"]);
+    // DATA R1 is 0x20, then its value. The last two run past the end of ram.
+    assert_eq!(replies, ["2005", "00", "E01", "E01"]);
+}
+
+#[test]
+fn m_upper_writes_ram_through_the_bus() {
+    let mut mb = board();
+
+    let replies = session(&mut mb, &mut Debugger::new(), &["M60,3:010203", "m60,3", "M5,1:03", "M60,2:01", "Mffffffffffffffff,1:00"]);
+    assert_eq!(replies, ["OK", "010203", "OK", "E01", "E01"]);
+    assert_eq!(mb.ram.memory[0x60..0x63], [1, 2, 3]);
+
+    // address 5 is the keyboard's CONTROL register
+    let keyboard = mb.find_kind::<Keyboard>().and_then(|id| mb.device::<Keyboard>(id)).unwrap();
+    assert_eq!(keyboard.control, 3);
+}
+
+#[test]
+fn m_upper_writes_are_undone_by_stepping_back() {
+    let mut mb = board();
+    let mut debugger = Debugger::new();
+    debugger.step(&mut mb);
+
+    session(&mut mb, &mut debugger, &["M60,1:aa"]);
+    assert_eq!(mb.ram.memory[0x60], 0xaa);
+
+    mb.step_back();
+    assert_eq!(mb.ram.memory[0x60], 0);
+}
+
+#[test]
+fn z0_then_c_stops_on_the_breakpoint() {
+    let mut mb = board();
+    let mut debugger = Debugger::new();
+
+    // ADD sits 4 bytes into the program, ram 0x13
+    let replies = session(&mut mb, &mut debugger, &["Z0,13,1", "c", "g", "z0,13,1", "c", "Z0,5,1", "Z1,13,1"]);
+    assert_eq!(replies, ["OK", "S05", "0507000013c700", "OK", "W00", "E01", ""]);
+    assert_eq!(mb.cpu.reg_2, 12);
+}
+
+#[test]
+fn a_packet_that_is_not_ascii_is_not_supported() {
+    let mut mb = board();
+    let mut debugger = Debugger::new();
+
+    // 0xff on its own sums to ff
+    let mut input = b"$\xff#ff+".to_vec();
+    input.extend_from_slice(format!("$p0#{:02x}+", checksum("p0")).as_bytes());
+    assert_eq!(raw_session(&mut mb, &mut debugger, input), ["", "00"]);
+}
+
+#[test]
+fn k_ends_the_session_without_a_reply() {
+    let mut mb = board();
+    let mut debugger = Debugger::new();
+
+    // nothing after the kill is read
+    assert_eq!(session(&mut mb, &mut debugger, &["p0", "k", "p0"]), ["00"]);
+}
@@ -0,0 +1,100 @@
+use jcpu::{keyboard::KEYBOARD_ID, motherboard::Motherboard};
+
+// counts up in R1, keeps a copy on the stack and swaps banks as it goes
+const PROGRAM: &str = "DATA R1, 0\nloop:\nINC R1\nPUSH R1\nPOP R2\nST 140, R1\nBANK 1\nST 140, R2\nBANK 0\nJMP $loop\n";
+
+fn board() -> Motherboard {
+    let mut mb = Motherboard::new("", "");
+    mb.load(jcpu_compiler::assemble(PROGRAM));
+    mb
+}
+
+// the peripherals tick before each cycle, stepping back lands between the two
+fn run(mb: &mut Motherboard, cycles: usize) {
+    for _ in 0..cycles {
+        mb.cycle().unwrap();
+        mb.process_peripherals();
+    }
+}
+
+#[test]
+fn stepping_back_returns_to_every_earlier_cycle() {
+    let mut mb = board();
+    mb.process_peripherals();
+    let mut snapshots = vec![];
+    for i in 0..40 {
+        // a key between two cycles is undone along with the cycle before it
+        if i == 17 {
+            let id = mb.find(KEYBOARD_ID).unwrap();
+            mb.input(id, b'q');
+        }
+        snapshots.push((mb.save_snapshot(), mb.ram.banks.clone()));
+        run(&mut mb, 1);
+    }
+
+    while let Some((snapshot, banks)) = snapshots.pop() {
+        let undone = mb.step_back().unwrap();
+        assert_eq!(undone.cycle, snapshots.len());
+        assert!(mb.save_snapshot() == snapshot, "cycle {} is not as it was", snapshots.len());
+        assert_eq!(mb.ram.banks, banks, "cycle {}", snapshots.len());
+    }
+    assert!(mb.step_back().is_none());
+}
+
+#[test]
+fn running_again_after_stepping_back_does_the_same() {
+    let mut mb = board();
+    run(&mut mb, 30);
+    let ahead = mb.save_snapshot();
+
+    assert_eq!(mb.run_back_to(12), 18);
+    run(&mut mb, 18);
+    assert!(mb.save_snapshot() == ahead);
+
+    // the cycles run again are recorded again
+    assert_eq!(mb.run_back_to(0), 30);
+}
+
+#[test]
+fn run_back_to_write_stops_before_the_last_write() {
+    let mut mb = board();
+    // through the first INC, PUSH and POP of the second time round
+    run(&mut mb, 12);
+    assert_eq!(mb.ram.memory[140], 1);
+
+    // both banks hold 1 at 140, so the swaps after it don't count, ST 140, R2 in bank 1 does
+    assert_eq!(mb.run_back_to_write(140), Some(6));
+    assert_eq!(mb.ram.bank, 1);
+    assert_eq!(mb.ram.memory[140], 0);
+    run(&mut mb, 1);
+    assert_eq!(mb.ram.memory[140], 1);
+
+    // address 60 is never written
+    assert_eq!(mb.run_back_to_write(60), None);
+    assert!(mb.history.is_empty());
+    assert_eq!(mb.cpu.reg_iar, 15);
+}
+
+#[test]
+fn the_limit_caps_how_far_back_it_goes() {
+    let mut mb = board();
+    mb.history.limit = 10;
+    run(&mut mb, 50);
+    assert_eq!(mb.history.len(), 10);
+
+    assert_eq!(mb.run_back_to(0), 10);
+    let mut again = board();
+    run(&mut again, 40);
+    assert!(mb.save_snapshot() == again.save_snapshot());
+}
+
+#[test]
+fn a_limit_of_zero_records_nothing() {
+    let mut mb = board();
+    mb.history.limit = 0;
+    run(&mut mb, 20);
+
+    assert!(mb.history.is_empty());
+    assert!(mb.ram.journal.is_empty());
+    assert!(mb.step_back().is_none());
+}
@@ -0,0 +1,108 @@
+use jcpu::{alu::FLAG_INT, fault::CpuFault, keyboard::Keyboard, motherboard::Motherboard};
+
+fn board(program: &str) -> Motherboard {
+    let mut mb = Motherboard::new("", "");
+    mb.load(jcpu_compiler::assemble(program));
+    mb
+}
+
+fn run(mb: &mut Motherboard, cycles: usize) -> Result<(), CpuFault> {
+    for _ in 0..cycles {
+        mb.process_peripherals();
+        if !mb.cycle()? {
+            break;
+        }
+    }
+
+    Ok(())
+}
+
+#[test]
+fn int_calls_the_handler_and_iret_comes_back() {
+    // the handler for line 3 counts in R1, the program carries on in R2
+    let mut mb = board("DATA R1, $handler\nST 250, R1\nDATA R1, 0\nEI\nINT 3\nINC R2\nHLT\nhandler:\nINC R1\nIRET\n");
+    let sp = mb.cpu.reg_sp;
+
+    run(&mut mb, 100).unwrap();
+    assert_eq!((mb.cpu.reg_1, mb.cpu.reg_2, mb.cpu.reg_int), (1, 1, 3));
+    // IRET put the flags back, INT included
+    assert_eq!(mb.cpu.alu.flags & FLAG_INT, FLAG_INT);
+    assert_eq!(mb.cpu.reg_sp, sp);
+    assert!(mb.cpu.interrupts.active.is_empty());
+    assert_eq!(mb.cpu.interrupts.pending, 0);
+}
+
+#[test]
+fn int_waits_for_ei_like_any_line() {
+    let mut mb = board("DATA R1, $handler\nST 250, R1\nINT 3\nINC R2\nINC R2\nEI\nHLT\nhandler:\nADD R2, R3\nIRET\n");
+
+    run(&mut mb, 100).unwrap();
+    assert_eq!((mb.cpu.reg_2, mb.cpu.reg_3), (2, 2));
+}
+
+#[test]
+fn a_line_past_the_last_is_an_illegal_opcode() {
+    let mut mb = board("INT 8\nHLT\n");
+
+    assert_eq!(run(&mut mb, 10), Err(CpuFault::IllegalOpcode(0x01)));
+    assert_eq!(mb.cpu.interrupts.pending, 0);
+}
+
+#[test]
+fn a_handler_can_take_another_line() {
+    // line 2 turns interrupts back on and raises line 1, which runs inside it. Each step of
+    // the way is numbered in R4 and stored at 140 (line 1), 141 and 142 (line 2)
+    let program = "DATA R1, $one\nST 248, R1\nDATA R1, $two\nST 249, R1\nEI\nINT 2\nHLT\n\
+                   one:\nINC R4\nST 140, R4\nIRET\n\
+                   two:\nINC R4\nST 141, R4\nEI\nINT 1\nINC R4\nST 142, R4\nIRET\n";
+    let mut mb = board(program);
+    let sp = mb.cpu.reg_sp;
+
+    let mut deepest = vec![];
+    for _ in 0..100 {
+        if mb.cpu.interrupts.active.len() > deepest.len() {
+            deepest = mb.cpu.interrupts.active.clone();
+        }
+        if !mb.cycle().unwrap() {
+            break;
+        }
+    }
+
+    assert_eq!(deepest, [2, 1]);
+    assert_eq!(mb.ram.memory[141..=142], [1, 3]);
+    assert_eq!(mb.ram.memory[140], 2);
+    assert_eq!(mb.cpu.reg_sp, sp);
+}
+
+#[test]
+fn the_lowest_pending_line_goes_first() {
+    // both are pending by the time EI lets them in, line 1 runs before line 2 (R2 and R3 start at 0)
+    let program = "DATA R1, $one\nST 248, R1\nDATA R1, $two\nST 249, R1\nINT 2\nINT 1\nEI\nHLT\n\
+                   one:\nINC R4\nADD R4, R2\nIRET\n\
+                   two:\nINC R4\nADD R4, R3\nIRET\n";
+    let mut mb = board(program);
+
+    run(&mut mb, 100).unwrap();
+    assert_eq!((mb.cpu.reg_2, mb.cpu.reg_3), (1, 2));
+}
+
+#[test]
+fn the_keyboard_irq_calls_its_handler() {
+    // the keyboard's handler reads DATA into R2 and counts its calls in R3
+    let mut mb = board("DATA R1, $keys\nST 248, R1\nEI\nloop:\nJMP $loop\nkeys:\nLD 1, R2\nINC R3\nIRET\n");
+    let id = mb.find_kind::<Keyboard>().unwrap();
+
+    run(&mut mb, 20).unwrap();
+    assert_eq!(mb.cpu.reg_3, 0);
+
+    mb.input(id, b'j');
+    run(&mut mb, 20).unwrap();
+    assert_eq!((mb.cpu.reg_2, mb.cpu.reg_3), (b'j', 1));
+
+    // with interrupts off the key waits in the buffer
+    mb.cpu.alu.flags &= !FLAG_INT;
+    mb.input(id, b'k');
+    run(&mut mb, 20).unwrap();
+    assert_eq!(mb.cpu.reg_3, 1);
+    assert_eq!(mb.device::<Keyboard>(id).unwrap().events.len(), 1);
+}
@@ -0,0 +1,46 @@
+use jcpu::motherboard::Motherboard;
+use jcpuinstructions::JUMP_FLAGS;
+
+// Each setup leaves the flags in a known state, along with the C,A,E,Z
+// conditions (in JumpFlag order) that should be set afterwards
+const SETUPS: [(&str, &str, u8); 6] = [
+    ("less", "DATA R1, 3\nCMP R1, 5\n", 0b1000),
+    ("equal", "DATA R1, 5\nCMP R1, 5\n", 0b0011),
+    ("greater", "DATA R1, 7\nCMP R1, 5\n", 0b0100),
+    ("carry", "DATA R1, 200\nDATA R2, 100\nADD R1, R2\n", 0b1100),
+    ("zero", "DATA R1, 1\nDEC R1\n", 0b0101),
+    ("cleared", "DATA R1, 7\nCMP R1, 5\nCLF\n", 0b0000),
+];
+
+// R4 ends up 2 if the jump was taken and 1 if it fell through
+fn run(setup: &str, flag: &str) -> u8 {
+    let jsm = format!(
+        "{}JMPIF{} $taken\nDATA R4, 1\nHLT\ntaken:\nDATA R4, 2\nHLT\n",
+        setup,
+        flag.to_uppercase()
+    );
+
+    let mut mb = Motherboard::new("", "");
+    mb.load(jcpu_compiler::assemble(&jsm));
+
+    for _ in 0..100 {
+        if !mb.cycle().unwrap() {
+            break;
+        }
+    }
+
+    mb.cpu.reg_4
+}
+
+#[test]
+fn every_jump_flag_against_every_setup() {
+    for (name, setup, conditions) in SETUPS.iter() {
+        for (i, flag) in JUMP_FLAGS.iter().enumerate() {
+            // CF is 0 but jumps on carry, like C
+            let named = if i == 0 { 0b1000 } else { i as u8 };
+            let expected = if named & conditions > 0 { 2 } else { 1 };
+
+            assert_eq!(run(setup, flag), expected, "JMPIF{} after {}", flag.to_uppercase(), name);
+        }
+    }
+}
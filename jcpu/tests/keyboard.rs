@@ -0,0 +1,197 @@
+use jcpu::{
+    device::Device,
+    interrupts::IRQ_KEYBOARD,
+    keyboard::{
+        key_of, KeyEvent, Keyboard, KEYBOARD_IRQ, KEYBOARD_OVERFLOW, KEYBOARD_RAW, KEYBOARD_READY, KEY_UP, MOD_ALT, MOD_CTRL,
+        MOD_SHIFT, SC_CTRL, SC_LSHIFT,
+    },
+    motherboard::Motherboard,
+};
+
+// the registers, from the start of the keyboard's ram
+const STATUS: u8 = 0;
+const DATA: u8 = 1;
+const SCANCODE: u8 = 2;
+const MODIFIERS: u8 = 3;
+const COUNT: u8 = 4;
+const CONTROL: u8 = 5;
+
+const SC_A: u8 = 0x1e;
+
+fn event(scancode: u8, char: u8, modifiers: u8) -> KeyEvent {
+    KeyEvent { scancode, char, modifiers }
+}
+
+// the characters read out through DATA, oldest first
+fn drain(keyboard: &mut Keyboard) -> Vec<u8> {
+    let mut chars = vec![];
+    while keyboard.mmio_peek(STATUS) & KEYBOARD_READY > 0 {
+        chars.push(keyboard.mmio_read(DATA));
+    }
+    chars
+}
+
+#[test]
+fn typed_characters_come_out_in_order() {
+    let mut keyboard = Keyboard::new(16);
+    for c in b"Hi, 1!\n" {
+        keyboard.type_char(*c);
+    }
+
+    assert_eq!(keyboard.mmio_peek(COUNT), 7);
+    // peeking leaves the event there
+    assert_eq!(keyboard.mmio_peek(DATA), b'H');
+    assert_eq!(keyboard.mmio_peek(SCANCODE), 0x23);
+    assert_eq!(keyboard.mmio_peek(MODIFIERS), MOD_SHIFT);
+    assert_eq!(drain(&mut keyboard), b"Hi, 1!\n");
+
+    // an empty queue reads 0, and characters no key types are ignored
+    keyboard.type_char(0xe9);
+    assert_eq!(keyboard.mmio_read(DATA), 0);
+    assert_eq!(keyboard.mmio_peek(STATUS), 0);
+}
+
+#[test]
+fn a_full_buffer_drops_new_events_and_overflows() {
+    let mut keyboard = Keyboard::new(3);
+    for c in b"abcde" {
+        keyboard.type_char(*c);
+    }
+
+    assert_eq!(keyboard.mmio_peek(STATUS), KEYBOARD_READY | KEYBOARD_OVERFLOW);
+    assert_eq!(keyboard.mmio_peek(COUNT), 3);
+
+    // reading makes room again, the dropped ones stay dropped
+    assert_eq!(keyboard.mmio_read(DATA), b'a');
+    keyboard.type_char(b'f');
+    assert_eq!(drain(&mut keyboard), b"bcf");
+
+    // writing STATUS clears overflow
+    assert_eq!(keyboard.mmio_peek(STATUS), KEYBOARD_OVERFLOW);
+    keyboard.mmio_write(STATUS, 0xff);
+    assert_eq!(keyboard.mmio_peek(STATUS), 0);
+}
+
+#[test]
+fn writing_count_drops_every_event() {
+    let mut keyboard = Keyboard::new(8);
+    for c in b"xyz" {
+        keyboard.type_char(*c);
+    }
+
+    keyboard.mmio_write(COUNT, 0);
+    assert_eq!(keyboard.mmio_peek(COUNT), 0);
+    assert_eq!(keyboard.mmio_peek(STATUS) & KEYBOARD_READY, 0);
+}
+
+#[test]
+fn raw_mode_queues_every_event() {
+    let mut keyboard = Keyboard::new(16);
+    keyboard.mmio_write(CONTROL, KEYBOARD_RAW);
+    keyboard.type_char(b'A');
+
+    let events: Vec<KeyEvent> = keyboard.events.iter().copied().collect();
+    assert_eq!(
+        events,
+        [
+            event(SC_LSHIFT, 0, MOD_SHIFT),
+            event(SC_A, b'A', MOD_SHIFT),
+            event(SC_A | KEY_UP, 0, MOD_SHIFT),
+            event(SC_LSHIFT | KEY_UP, 0, 0),
+        ]
+    );
+    assert!(events[2].is_up());
+
+    // DATA takes key ups off too
+    assert_eq!(drain(&mut keyboard), [0, b'A', 0, 0]);
+}
+
+#[test]
+fn modifiers_change_the_character() {
+    let mut keyboard = Keyboard::new(16);
+
+    keyboard.key_down(SC_LSHIFT);
+    assert_eq!(keyboard.mmio_peek(STATUS), MOD_SHIFT << 2);
+    // with nothing queued MODIFIERS is what is held now
+    assert_eq!(keyboard.mmio_peek(MODIFIERS), MOD_SHIFT);
+    keyboard.key_down(0x02);
+    keyboard.key_up(SC_LSHIFT);
+    keyboard.key_down(0x02);
+
+    // ctrl turns letters into 1 to 26 and leaves the rest
+    keyboard.type_key(b'c', MOD_CTRL);
+    keyboard.type_key(b'1', MOD_CTRL | MOD_ALT);
+    // shift already held is not pressed again
+    keyboard.key_down(SC_CTRL);
+    keyboard.key_down(SC_LSHIFT);
+    keyboard.type_char(b'Z');
+
+    let events: Vec<(u8, u8)> = keyboard.events.iter().map(|e| (e.char, e.modifiers)).collect();
+    assert_eq!(events, [(b'!', MOD_SHIFT), (b'1', 0), (3, MOD_CTRL), (b'1', MOD_CTRL | MOD_ALT), (26, MOD_CTRL | MOD_SHIFT)]);
+    assert_eq!(keyboard.modifiers, MOD_CTRL | MOD_SHIFT);
+
+    assert_eq!(key_of(b'?'), Some((0x35, true)));
+    assert_eq!(key_of(b'/'), Some((0x35, false)));
+    assert_eq!(key_of(0), None);
+}
+
+#[test]
+fn the_irq_is_raised_once_per_batch_when_on() {
+    let mut keyboard = Keyboard::new(16);
+    assert_eq!(keyboard.mmio_peek(CONTROL), KEYBOARD_IRQ);
+
+    keyboard.type_char(b'a');
+    keyboard.type_char(b'b');
+    assert_eq!(keyboard.irq(), Some(IRQ_KEYBOARD));
+    assert_eq!(keyboard.irq(), None);
+
+    keyboard.mmio_write(CONTROL, 0);
+    keyboard.type_char(b'c');
+    assert_eq!(keyboard.irq(), None);
+
+    // a reset turns it back on and empties the queue
+    keyboard.reset();
+    assert_eq!(keyboard.mmio_peek(CONTROL), KEYBOARD_IRQ);
+    assert_eq!(keyboard.mmio_peek(COUNT), 0);
+}
+
+#[test]
+fn state_round_trips() {
+    let mut keyboard = Keyboard::new(4);
+    keyboard.mmio_write(CONTROL, KEYBOARD_RAW | KEYBOARD_IRQ);
+    keyboard.key_down(SC_CTRL);
+    keyboard.type_char(b'q');
+    let state = keyboard.save_state();
+    assert!(keyboard.check_state(&state));
+
+    let mut restored = Keyboard::new(4);
+    restored.load_state(&state);
+    assert_eq!(restored.save_state(), state);
+    assert_eq!(restored.events, keyboard.events);
+
+    // torn events, and more than the buffer holds
+    assert!(!restored.check_state(&state[..state.len() - 1]));
+    assert!(!Keyboard::new(2).check_state(&state));
+}
+
+#[test]
+fn a_program_reads_keys_through_data() {
+    let mut mb = Motherboard::new("", "");
+    // the keyboard is at 0: wait for STATUS bit 0, then LD DATA into R2 and R3
+    mb.load(jcpu_compiler::assemble("wait:\nLD 0, R1\nAND R1, 1\nJMPIFZ $wait\nLD 1, R2\nLD 1, R3\nHLT\n"));
+    let id = mb.find_kind::<Keyboard>().unwrap();
+
+    for cycle in 0..50 {
+        if cycle == 10 {
+            mb.input(id, b'o');
+            mb.input(id, b'K');
+        }
+        mb.process_peripherals();
+        if !mb.cycle().unwrap() {
+            break;
+        }
+    }
+
+    assert_eq!((mb.cpu.reg_2, mb.cpu.reg_3), (b'o', b'K'));
+    assert_eq!(mb.device::<Keyboard>(id).unwrap().events.len(), 0);
+}
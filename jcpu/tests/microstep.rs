@@ -0,0 +1,61 @@
+use jcpu::{microstep::MicroPhase, motherboard::Motherboard};
+
+fn board(jsm: &str) -> Motherboard {
+    let mut mb = Motherboard::new("", "");
+    mb.load(jcpu_compiler::assemble(jsm));
+    mb
+}
+
+// R1 to R4, IAR, MAR, IR, SP and the flags
+fn registers(mb: &Motherboard) -> [u8; 9] {
+    let cpu = &mb.cpu;
+    [cpu.reg_1, cpu.reg_2, cpu.reg_3, cpu.reg_4, cpu.reg_iar, cpu.reg_mar, cpu.reg_ir, cpu.reg_sp, cpu.alu.flags]
+}
+
+#[test]
+fn only_fetch_load_ir_and_execute_change_registers() {
+    let mut mb = board("DATA R1, 7\nADD R1, 3\nHLT\n");
+
+    // DATA has no ALU step
+    assert_eq!(mb.phase, MicroPhase::Fetch);
+    mb.micro_step().unwrap();
+    assert_eq!(mb.cpu.reg_mar, 15);
+    assert_eq!(mb.phase, MicroPhase::LoadIr);
+    mb.micro_step().unwrap();
+    assert_eq!(mb.cpu.reg_ir, 0x20);
+    assert_eq!(mb.phase, MicroPhase::IncrementIar);
+
+    let before = registers(&mb);
+    mb.micro_step().unwrap();
+    assert_eq!(registers(&mb), before, "increment IAR is a display step");
+    assert_eq!(mb.phase, MicroPhase::Execute);
+
+    mb.micro_step().unwrap();
+    assert_eq!((mb.cpu.reg_1, mb.cpu.reg_iar), (7, 17));
+    assert_eq!(mb.phase, MicroPhase::Fetch);
+
+    // ADD goes through the ALU, its results are there as soon as it executes
+    for _ in 0..4 {
+        mb.micro_step().unwrap();
+    }
+    assert_eq!((mb.cpu.reg_1, mb.cpu.alu.Sum, mb.cpu.reg_iar), (10, 10, 20));
+    assert_eq!(mb.phase, MicroPhase::AluLatch);
+
+    let before = (registers(&mb), mb.cpu.alu.clone());
+    mb.micro_step().unwrap();
+    assert!((registers(&mb), mb.cpu.alu.clone()) == before, "the ALU latch is a display step");
+    assert_eq!(mb.phase, MicroPhase::Fetch);
+}
+
+#[test]
+fn micro_steps_end_where_cycles_do() {
+    let jsm = "DATA R1, 250\nloop:\nADD R1, 3\nPUSH R1\nPOP R2\nCMP R1, 2\nJMPIFA $loop\nHLT\n";
+    let mut stepped = board(jsm);
+    let mut cycled = board(jsm);
+
+    while cycled.cycle().unwrap() {}
+    while stepped.micro_step().unwrap() {}
+
+    assert_eq!(registers(&stepped), registers(&cycled));
+    assert_eq!(stepped.ram.memory, cycled.ram.memory);
+}
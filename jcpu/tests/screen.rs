@@ -0,0 +1,144 @@
+use jcpu::{
+    device::Device,
+    peripheral::{Screen, MODE_GRAPHICS, MODE_TEXT, SCREEN_VSYNC},
+};
+
+// the registers, from the start of the screen's ram
+const X: u8 = 0;
+const Y: u8 = 1;
+const COLOR: u8 = 2;
+const CHAR: u8 = 3;
+const MODE: u8 = 4;
+const STATUS: u8 = 5;
+
+// the default screen, in text mode 8 cells across and 4 down
+fn blank(mode: u8) -> Screen {
+    let mut screen = Screen::new(32, 24, 64);
+    screen.mmio_write(MODE, mode);
+    screen
+}
+
+fn type_text(screen: &mut Screen, text: &[u8]) {
+    for c in text {
+        screen.mmio_write(CHAR, *c);
+    }
+}
+
+#[test]
+fn color_draws_the_pixel_at_x_y() {
+    let mut screen = blank(MODE_GRAPHICS);
+    screen.mmio_write(X, 3);
+    screen.mmio_write(Y, 2);
+    screen.mmio_write(COLOR, 4);
+
+    assert_eq!(screen.buffer[2 * 32 + 3], 4);
+    assert_eq!(screen.mmio_peek(COLOR), 4);
+
+    // only the palette's 4 bits are kept
+    screen.mmio_write(COLOR, 0x1f);
+    assert_eq!(screen.mmio_peek(COLOR), 0x0f);
+
+    // off the screen nothing is drawn and it reads 0
+    screen.mmio_write(X, 32);
+    screen.mmio_write(COLOR, 4);
+    assert_eq!(screen.mmio_peek(COLOR), 0);
+    assert_eq!(screen.buffer.iter().filter(|p| **p > 0).count(), 1);
+    assert_eq!(screen.frame(), screen.buffer);
+
+    // CHAR does nothing in graphics mode
+    screen.mmio_write(CHAR, b'A');
+    assert_eq!(screen.mmio_peek(X), 32);
+}
+
+#[test]
+fn char_moves_on_and_wraps() {
+    let mut screen = blank(MODE_TEXT);
+    type_text(&mut screen, b"HELLO");
+    assert_eq!((screen.mmio_peek(X), screen.mmio_peek(Y)), (5, 0));
+
+    // the ninth character starts the next line
+    type_text(&mut screen, b"ABCD");
+    assert_eq!(screen.text()[..2], ["HELLOABC", "D       "]);
+    assert_eq!((screen.mmio_peek(X), screen.mmio_peek(Y)), (1, 1));
+
+    // reading CHAR gives the character at X, Y
+    screen.mmio_write(X, 1);
+    screen.mmio_write(Y, 0);
+    assert_eq!(screen.mmio_peek(CHAR), b'E');
+}
+
+#[test]
+fn a_newline_starts_the_next_line_and_the_last_goes_back_to_the_top() {
+    let mut screen = blank(MODE_TEXT);
+    type_text(&mut screen, b"A\nB\n\nC\nD");
+
+    assert_eq!(screen.text(), ["D       ", "B       ", "        ", "C       "]);
+    assert_eq!((screen.mmio_peek(X), screen.mmio_peek(Y)), (1, 0));
+}
+
+#[test]
+fn color_in_text_mode_is_the_next_characters_colours() {
+    let mut screen = blank(MODE_TEXT);
+    // yellow on blue
+    screen.mmio_write(COLOR, 0x1e);
+    type_text(&mut screen, b" ");
+
+    assert_eq!(screen.mmio_peek(COLOR), 0x1e);
+    assert_eq!(screen.attrs[..2], [0x1e, 0x0f]);
+    // a space is all background, the cell next to it black
+    let frame = screen.frame();
+    assert_eq!(frame[0..4], [1, 1, 1, 1]);
+    assert_eq!(frame[5 * 32..5 * 32 + 8], [1, 1, 1, 1, 0, 0, 0, 0]);
+}
+
+#[test]
+fn writing_mode_clears_the_screen() {
+    let mut screen = blank(MODE_TEXT);
+    type_text(&mut screen, b"HI");
+    screen.mmio_write(MODE, MODE_TEXT);
+    assert!(screen.text().iter().all(|line| line.trim().is_empty()));
+    assert_eq!((screen.mmio_peek(X), screen.mmio_peek(Y)), (0, 0));
+
+    let mut screen = blank(MODE_GRAPHICS);
+    screen.mmio_write(X, 9);
+    screen.mmio_write(COLOR, 2);
+    screen.mmio_write(MODE, MODE_GRAPHICS);
+    assert!(screen.buffer.iter().all(|p| *p == 0));
+    assert_eq!(screen.mmio_peek(X), 0);
+}
+
+#[test]
+fn vsync_is_set_every_refresh_and_cleared_by_reading_status() {
+    let mut screen = blank(MODE_GRAPHICS);
+
+    screen.tick(63);
+    assert_eq!(screen.mmio_peek(STATUS), 0);
+    screen.tick(1);
+    assert_eq!(screen.mmio_peek(STATUS), SCREEN_VSYNC);
+    assert_eq!(screen.frames, 1);
+
+    // peeking leaves it, a read takes it
+    assert_eq!(screen.mmio_read(STATUS), SCREEN_VSYNC);
+    assert_eq!(screen.mmio_read(STATUS), 0);
+
+    // a long tick is still one frame, the rest counts toward the next
+    screen.tick(100);
+    assert_eq!((screen.frames, screen.since_frame), (2, 36));
+}
+
+#[test]
+fn state_round_trips() {
+    let mut screen = blank(MODE_TEXT);
+    screen.mmio_write(COLOR, 0x42);
+    type_text(&mut screen, b"SAVED");
+    screen.tick(70);
+    let state = screen.save_state();
+    assert!(screen.check_state(&state));
+    assert!(!screen.check_state(&state[1..]));
+
+    let mut restored = Screen::new(32, 24, 64);
+    restored.load_state(&state);
+    assert_eq!(restored.save_state(), state);
+    assert_eq!(restored.frame(), screen.frame());
+    assert_eq!(restored.text()[0], "SAVED   ");
+}
@@ -0,0 +1,99 @@
+use jcpu::{
+    config::MachineConfig,
+    keyboard::KEYBOARD_ID,
+    microstep::MicroPhase,
+    motherboard::Motherboard,
+    snapshot::{SnapshotError, VERSION},
+};
+
+// counts up in R1, keeps a copy on the stack and swaps banks as it goes
+const PROGRAM: &str = "DATA R1, 0\nloop:\nINC R1\nPUSH R1\nPOP R2\nST 140, R1\nBANK 1\nST 140, R2\nBANK 0\nJMP $loop\n";
+
+fn board() -> Motherboard {
+    let mut mb = Motherboard::new("", "");
+    mb.load(jcpu_compiler::assemble(PROGRAM));
+    mb
+}
+
+fn run(mb: &mut Motherboard, cycles: usize) {
+    for _ in 0..cycles {
+        mb.process_peripherals();
+        mb.cycle().unwrap();
+    }
+}
+
+#[test]
+fn a_loaded_snapshot_carries_on_like_the_original() {
+    let mut original = board();
+    run(&mut original, 37);
+    let id = original.find(KEYBOARD_ID).unwrap();
+    original.input(id, b'q');
+    let snapshot = original.save_snapshot();
+
+    let mut restored = board();
+    restored.load_snapshot(&snapshot).unwrap();
+    assert_eq!(restored.save_snapshot(), snapshot);
+
+    run(&mut original, 50);
+    run(&mut restored, 50);
+    assert_eq!(restored.save_snapshot(), original.save_snapshot());
+    assert_eq!(restored.ram.banks, original.ram.banks);
+}
+
+#[test]
+fn a_snapshot_taken_between_micro_steps_resumes_the_same_step() {
+    let mut original = board();
+    run(&mut original, 5);
+    original.micro_step().unwrap();
+    original.micro_step().unwrap();
+    assert_eq!(original.phase, MicroPhase::IncrementIar);
+    let snapshot = original.save_snapshot();
+
+    let mut restored = board();
+    restored.load_snapshot(&snapshot).unwrap();
+    assert_eq!(restored.phase, MicroPhase::IncrementIar);
+
+    // cycle finishes the half stepped instruction on both
+    original.cycle().unwrap();
+    restored.cycle().unwrap();
+    assert_eq!(restored.save_snapshot(), original.save_snapshot());
+}
+
+#[test]
+fn a_board_with_other_banks_refuses_the_snapshot() {
+    let snapshot = board().save_snapshot();
+
+    let config = MachineConfig::from_toml("[map.bank]\naddress = 135\nsize = 64\ncount = 2\n").unwrap();
+    let mut other = Motherboard::with_config("", "", config).unwrap();
+    other.load(jcpu_compiler::assemble("DATA R3, 9\n"));
+    other.cycle().unwrap();
+    let before = other.save_snapshot();
+
+    match other.load_snapshot(&snapshot) {
+        Err(SnapshotError::Invalid(e)) => assert!(e.contains("4 ram banks saved, this board has 2"), "{}", e),
+        other => panic!("expected the bank count to be refused, got {:?}", other.err()),
+    }
+    assert_eq!(other.save_snapshot(), before, "a refused snapshot changes nothing");
+
+    let config = MachineConfig::from_toml("[map.bank]\naddress = 135\nsize = 32\ncount = 4\n").unwrap();
+    let mut smaller = Motherboard::with_config("", "", config).unwrap();
+    assert!(matches!(smaller.load_snapshot(&snapshot), Err(SnapshotError::Invalid(_))));
+}
+
+#[test]
+fn broken_files_are_refused() {
+    let snapshot = board().save_snapshot();
+    let mut mb = board();
+
+    assert!(matches!(mb.load_snapshot(b"nope"), Err(SnapshotError::BadMagic)));
+
+    let mut old = snapshot.clone();
+    old[4] = VERSION - 1;
+    assert!(matches!(mb.load_snapshot(&old), Err(SnapshotError::UnsupportedVersion(v)) if v == VERSION - 1));
+
+    assert!(matches!(mb.load_snapshot(&snapshot[..snapshot.len() - 1]), Err(SnapshotError::Truncated)));
+
+    let mut trailing = snapshot.clone();
+    trailing.push(0);
+    assert!(matches!(mb.load_snapshot(&trailing), Err(SnapshotError::Invalid(_))));
+}
@@ -0,0 +1,169 @@
+use jcpu::{
+    config::MachineConfig,
+    device::Device,
+    interrupts::IRQ_TIMER,
+    motherboard::Motherboard,
+    timer::{Timer, TIMER_EXPIRED, TIMER_IRQ, TIMER_PERIODIC, TIMER_RUN},
+};
+
+// the registers, from the start of the timer's ram
+const CONTROL: u8 = 0;
+const STATUS: u8 = 1;
+const RELOAD: u8 = 2;
+const PRESCALER: u8 = 3;
+const COUNTER: u8 = 4;
+
+fn started(reload: u8, prescaler: u8, control: u8) -> Timer {
+    let mut timer = Timer::new();
+    timer.mmio_write(RELOAD, reload);
+    timer.mmio_write(PRESCALER, prescaler);
+    timer.mmio_write(CONTROL, control);
+    timer
+}
+
+// the cycles (counting from 1) after which the timer had expired again
+fn expiries(timer: &mut Timer, cycles: usize) -> Vec<usize> {
+    let mut expired = vec![];
+    for cycle in 1..=cycles {
+        timer.tick(1);
+        if timer.mmio_peek(STATUS) & TIMER_EXPIRED > 0 {
+            expired.push(cycle);
+            timer.mmio_write(STATUS, 0);
+        }
+    }
+    expired
+}
+
+#[test]
+fn a_one_shot_timer_expires_once_and_stops() {
+    let mut timer = started(3, 0, TIMER_RUN);
+
+    assert_eq!(expiries(&mut timer, 10), [3]);
+    assert!(!timer.running());
+    assert_eq!(timer.mmio_peek(COUNTER), 0);
+    // no irq was asked for
+    assert_eq!(timer.irq(), None);
+}
+
+#[test]
+fn a_periodic_timer_reloads_every_prescaler_plus_one_cycles() {
+    let mut timer = started(2, 1, TIMER_RUN | TIMER_PERIODIC);
+
+    assert_eq!(expiries(&mut timer, 12), [4, 8, 12]);
+    assert!(timer.running());
+
+    // a reload of 0 expires every time the counter would move
+    let mut zero = started(0, 1, TIMER_RUN | TIMER_PERIODIC);
+    assert_eq!(expiries(&mut zero, 6), [2, 4, 6]);
+}
+
+#[test]
+fn each_expiry_raises_the_irq_once() {
+    let mut timer = started(2, 0, TIMER_RUN | TIMER_PERIODIC | TIMER_IRQ);
+
+    timer.tick(1);
+    assert_eq!(timer.irq(), None);
+    timer.tick(1);
+    assert_eq!(timer.irq(), Some(IRQ_TIMER));
+    assert_eq!(timer.irq(), None);
+
+    // two expiries between polls are still one irq
+    timer.tick(4);
+    assert_eq!(timer.irq(), Some(IRQ_TIMER));
+    assert_eq!(timer.irq(), None);
+}
+
+#[test]
+fn register_writes() {
+    let mut timer = started(5, 0, TIMER_RUN);
+    timer.tick(2);
+    assert_eq!(timer.mmio_peek(COUNTER), 3);
+
+    // writing CONTROL while it runs keeps the count, starting it again reloads
+    timer.mmio_write(CONTROL, TIMER_RUN | TIMER_PERIODIC);
+    assert_eq!(timer.mmio_peek(COUNTER), 3);
+    timer.mmio_write(CONTROL, 0);
+    timer.tick(2);
+    assert_eq!(timer.mmio_peek(COUNTER), 3);
+    timer.mmio_write(CONTROL, TIMER_RUN);
+    assert_eq!(timer.mmio_peek(COUNTER), 5);
+
+    // RELOAD loads the counter too, COUNTER only the counter
+    timer.mmio_write(RELOAD, 9);
+    assert_eq!(timer.mmio_peek(COUNTER), 9);
+    timer.mmio_write(COUNTER, 1);
+    assert_eq!((timer.mmio_peek(RELOAD), timer.mmio_peek(COUNTER)), (9, 1));
+
+    // storing anything in STATUS clears it
+    timer.tick(1);
+    assert_eq!(timer.mmio_peek(STATUS), TIMER_EXPIRED);
+    timer.mmio_write(STATUS, 0xff);
+    assert_eq!(timer.mmio_peek(STATUS), 0);
+    assert_eq!(timer.mmio_peek(5), 0);
+}
+
+#[test]
+fn state_round_trips() {
+    let mut timer = started(7, 2, TIMER_RUN | TIMER_PERIODIC | TIMER_IRQ);
+    timer.tick(23);
+    let state = timer.save_state();
+    assert!(timer.check_state(&state));
+    assert!(!timer.check_state(&state[1..]));
+
+    let mut restored = Timer::new();
+    restored.load_state(&state);
+    assert_eq!(restored.save_state(), state);
+
+    timer.tick(10);
+    restored.tick(10);
+    assert_eq!(restored.save_state(), timer.save_state());
+    assert_eq!(restored.irq(), timer.irq());
+}
+
+// the README example: the timer at 14 and boot moved up to make room
+const PROFILE: &str = r#"
+[map]
+boot = 20
+program = 115
+
+[[peripherals]]
+kind = "keyboard"
+address = 0
+size = 7
+
+[[peripherals]]
+kind = "screen"
+address = 7
+size = 6
+width = 32
+height = 24
+refresh = 64
+
+[[peripherals]]
+kind = "timer"
+address = 14
+size = 5
+"#;
+
+// the handler counts its calls in R4
+const PROGRAM: &str = "DATA R1, $tick\nST 247, R1\nDATA R1, 3\nST 16, R1\nDATA R1, 1\nST 17, R1\nDATA R1, 7\nST 14, R1\nEI\nloop:\nJMP $loop\ntick:\nINC R4\nIRET\n";
+
+#[test]
+fn the_irq_calls_the_handler_every_six_cycles() {
+    let config = MachineConfig::from_toml(PROFILE).unwrap();
+    let mut mb = Motherboard::with_config("", "", config).unwrap();
+    mb.load(jcpu_compiler::assemble(PROGRAM));
+
+    let mut calls = vec![];
+    for cycle in 0..100 {
+        let count = mb.cpu.reg_4;
+        mb.process_peripherals();
+        mb.cycle().unwrap();
+        if mb.cpu.reg_4 != count {
+            calls.push(cycle);
+        }
+    }
+
+    assert!(calls.len() > 10, "{:?}", calls);
+    assert!(calls.windows(2).all(|w| w[1] - w[0] == 6), "{:?}", calls);
+}
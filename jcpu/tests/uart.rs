@@ -0,0 +1,208 @@
+use std::{cell::RefCell, collections::VecDeque, fs, rc::Rc};
+
+use jcpu::{
+    config::{ConfigError, MachineConfig},
+    device::Device,
+    interrupts::IRQ_SERIAL,
+    motherboard::Motherboard,
+    uart::{SerialBackend, Uart, UART_OVERRUN, UART_RX_IRQ, UART_RX_READY, UART_TX_READY},
+};
+
+#[test]
+fn uart_backend_that_fails_to_open_is_an_error() {
+    let profile = r#"
+[map]
+program = 112
+
+[[peripherals]]
+kind = "uart"
+address = 132
+size = 3
+fifo = 16
+backend = { file = { tx = "/nonexistent/dir/out.txt" } }
+"#;
+    let config = MachineConfig::from_toml(profile).unwrap();
+
+    match Motherboard::with_config("", "", config) {
+        Err(ConfigError::Device(e)) => assert!(e.contains("/nonexistent/dir/out.txt"), "{}", e),
+        Err(e) => panic!("expected a device error, got {}", e),
+        Ok(_) => panic!("expected the uart backend to fail to open"),
+    }
+}
+
+type Sent = Rc<RefCell<Vec<u8>>>;
+type Waiting = Rc<RefCell<VecDeque<u8>>>;
+
+// Keeps what was sent and hands over what the test queued, like the host end would
+struct Host {
+    sent: Sent,
+    waiting: Waiting,
+}
+
+impl SerialBackend for Host {
+    fn send(&mut self, byte: u8) {
+        self.sent.borrow_mut().push(byte);
+    }
+
+    fn receive(&mut self, max: usize) -> Vec<u8> {
+        let mut waiting = self.waiting.borrow_mut();
+        let n = max.min(waiting.len());
+        waiting.drain(..n).collect()
+    }
+
+    fn describe(&self) -> String {
+        String::from("test")
+    }
+}
+
+fn with_host(fifo: usize) -> (Uart, Sent, Waiting) {
+    let (sent, waiting) = (Rc::new(RefCell::new(vec![])), Rc::new(RefCell::new(VecDeque::new())));
+    let host = Host { sent: sent.clone(), waiting: waiting.clone() };
+    (Uart::new(fifo, Box::new(host)), sent, waiting)
+}
+
+// the registers, from the start of the uart's ram
+const DATA: u8 = 0;
+const STATUS: u8 = 1;
+const CONTROL: u8 = 2;
+
+#[test]
+fn sent_bytes_reach_the_host_and_the_console() {
+    let (mut uart, sent, _) = with_host(4);
+    for byte in b"hi\n" {
+        uart.mmio_write(DATA, *byte);
+    }
+
+    assert_eq!(*sent.borrow(), b"hi\n");
+    assert_eq!(uart.console, b"hi\n");
+    assert_eq!(uart.mmio_peek(STATUS), UART_TX_READY);
+}
+
+#[test]
+fn the_console_keeps_the_last_4096_bytes() {
+    let (mut uart, sent, _) = with_host(4);
+    for i in 0..5000 {
+        uart.mmio_write(DATA, i as u8);
+    }
+
+    assert_eq!(sent.borrow().len(), 5000);
+    assert_eq!(uart.console.len(), 4096);
+    assert_eq!(uart.console[0], (5000 - 4096) as u8);
+}
+
+#[test]
+fn received_bytes_wait_in_the_fifo() {
+    let (mut uart, _, waiting) = with_host(4);
+    waiting.borrow_mut().extend(b"abcdef");
+
+    // the host keeps what does not fit
+    uart.tick(1);
+    assert_eq!(uart.rx, b"abcd");
+    assert_eq!(*waiting.borrow(), b"ef");
+    assert_eq!(uart.mmio_peek(STATUS), UART_TX_READY | UART_RX_READY);
+
+    // peeking leaves it, reading takes it
+    assert_eq!(uart.mmio_peek(DATA), b'a');
+    assert_eq!(uart.mmio_read(DATA), b'a');
+    assert_eq!(uart.mmio_read(DATA), b'b');
+    uart.tick(1);
+    assert_eq!(uart.rx, b"cdef");
+
+    for _ in 0..4 {
+        uart.mmio_read(DATA);
+    }
+    assert_eq!(uart.mmio_read(DATA), 0);
+    assert_eq!(uart.mmio_peek(STATUS), UART_TX_READY);
+}
+
+#[test]
+fn a_byte_into_a_full_fifo_is_dropped_and_overruns() {
+    let (mut uart, _, _) = with_host(2);
+    for byte in b"xyz" {
+        uart.input(*byte);
+    }
+
+    assert_eq!(uart.rx, b"xy");
+    assert_eq!(uart.mmio_peek(STATUS), UART_TX_READY | UART_RX_READY | UART_OVERRUN);
+
+    // storing anything in STATUS clears the overrun
+    uart.mmio_write(STATUS, 0);
+    assert_eq!(uart.mmio_peek(STATUS), UART_TX_READY | UART_RX_READY);
+    assert_eq!(uart.rx, b"xy");
+}
+
+#[test]
+fn the_irq_is_raised_when_asked_for() {
+    let (mut uart, _, waiting) = with_host(4);
+    uart.input(1);
+    assert_eq!(uart.irq(), None);
+
+    uart.mmio_write(CONTROL, UART_RX_IRQ);
+    assert_eq!(uart.mmio_peek(CONTROL), UART_RX_IRQ);
+    waiting.borrow_mut().extend([2, 3]);
+    uart.tick(1);
+    assert_eq!(uart.irq(), Some(IRQ_SERIAL));
+    assert_eq!(uart.irq(), None);
+
+    // a dropped byte raises nothing
+    uart.input(4);
+    uart.irq();
+    uart.input(5);
+    assert_eq!(uart.irq(), None);
+}
+
+#[test]
+fn state_round_trips_and_reset_clears_it() {
+    let (mut uart, _, _) = with_host(4);
+    uart.mmio_write(CONTROL, UART_RX_IRQ);
+    for byte in b"12345" {
+        uart.input(*byte);
+    }
+    let state = uart.save_state();
+    assert!(uart.check_state(&state));
+
+    let (mut restored, _, _) = with_host(4);
+    restored.load_state(&state);
+    assert_eq!(restored.save_state(), state);
+    assert_eq!(restored.rx, b"1234");
+
+    // more than its FIFO holds
+    let (small, _, _) = with_host(2);
+    assert!(!small.check_state(&state));
+    assert!(!small.check_state(&[0, 0]));
+
+    uart.console.push(b'!');
+    uart.reset();
+    assert_eq!(uart.save_state(), [0, 0, 0]);
+    // what was sent stays on the console
+    assert_eq!(uart.console, b"!");
+}
+
+#[test]
+fn a_program_talks_to_files() {
+    let dir = std::env::temp_dir().join(format!("jcpu-uart-{}", std::process::id()));
+    fs::create_dir_all(&dir).unwrap();
+    let (tx, rx) = (dir.join("tx.txt"), dir.join("rx.txt"));
+    fs::write(&rx, "ok").unwrap();
+
+    let profile = format!(
+        "[map]\nprogram = 112\n\n[[peripherals]]\nkind = \"uart\"\naddress = 132\nsize = 3\nfifo = 16\nbackend = {{ file = {{ tx = {:?}, rx = {:?} }} }}\n",
+        tx.to_str().unwrap(),
+        rx.to_str().unwrap()
+    );
+    let config = MachineConfig::from_toml(&profile).unwrap();
+    let mut mb = Motherboard::with_config("", "", config).unwrap();
+    // echoes the two bytes it gets back with a > in front of each
+    mb.load(jcpu_compiler::assemble("DATA R1, 0x3e\nLD 132, R2\nLD 132, R3\nST 132, R1\nST 132, R2\nST 132, R1\nST 132, R3\nHLT\n"));
+
+    for _ in 0..20 {
+        mb.process_peripherals();
+        if !mb.cycle().unwrap() {
+            break;
+        }
+    }
+    drop(mb);
+
+    assert_eq!(fs::read_to_string(&tx).unwrap(), ">o>k");
+    fs::remove_dir_all(&dir).unwrap();
+}